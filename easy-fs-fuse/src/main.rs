@@ -1,36 +1,123 @@
 use clap::App;
 use clap::Arg;
+use clap::{ArgMatches, SubCommand};
 use easy_fs::Inode;
-use easy_fs::{BlockDevice, EasyFileSystem};
+use easy_fs::{BlockDevice, BlockMapping, EasyFileSystem};
+use std::collections::HashMap;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::fs::read_dir;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 const BLOCK_SZ: usize = 512;
 use std::sync::Mutex;
 
-struct BlockFile(Mutex<File>);
+struct BlockFile {
+    file: Mutex<File>,
+    crash: Mutex<Option<Crash>>,
+    /// Read requests made to the image so far
+    reads: AtomicUsize,
+}
+
+/// A simulated power cut: once the budget is spent, writes only reach a
+/// volatile copy that is lost together with the device
+struct Crash {
+    writes_left: usize,
+    volatile: HashMap<usize, Vec<u8>>,
+}
+
+impl BlockFile {
+    fn new(file: File) -> Self {
+        Self {
+            file: Mutex::new(file),
+            crash: Mutex::new(None),
+            reads: AtomicUsize::new(0),
+        }
+    }
+
+    /// Let only the next `writes` block writes reach the image
+    #[cfg(test)]
+    fn crash_after(&self, writes: usize) {
+        *self.crash.lock().unwrap() = Some(Crash {
+            writes_left: writes,
+            volatile: HashMap::new(),
+        });
+    }
+
+    #[cfg(test)]
+    fn crashed(&self) -> bool {
+        self.crash
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|crash| crash.writes_left == 0)
+    }
+}
 
 impl BlockDevice for BlockFile {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        let mut file = self.0.lock().unwrap();
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        if let Some(crash) = self.crash.lock().unwrap().as_ref()
+            && let Some(data) = crash.volatile.get(&block_id)
+        {
+            buf.copy_from_slice(data);
+            return;
+        }
+        let mut file = self.file.lock().unwrap();
         file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
             .expect("Error when seeking!");
         assert_eq!(file.read(buf).unwrap(), BLOCK_SZ, "Not a complete block!");
     }
 
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        let mut file = self.0.lock().unwrap();
+        if let Some(crash) = self.crash.lock().unwrap().as_mut() {
+            if crash.writes_left == 0 {
+                crash.volatile.insert(block_id, buf.to_vec());
+                return;
+            }
+            crash.writes_left -= 1;
+        }
+        let mut file = self.file.lock().unwrap();
         file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
             .expect("Error when seeking!");
         assert_eq!(file.write(buf).unwrap(), BLOCK_SZ, "Not a complete block!");
     }
+
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        if self.crash.lock().unwrap().is_some() {
+            for (i, block) in buf.chunks_exact_mut(BLOCK_SZ).enumerate() {
+                self.read_block(block_id + i, block);
+            }
+            return;
+        }
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+            .expect("Error when seeking!");
+        file.read_exact(buf).expect("Not complete blocks!");
+    }
+
+    /// One block at a time while a crash is pending, so it can cut in
+    /// anywhere
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
+        if self.crash.lock().unwrap().is_some() {
+            for (i, block) in buf.chunks_exact(BLOCK_SZ).enumerate() {
+                self.write_block(block_id + i, block);
+            }
+            return;
+        }
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+            .expect("Error when seeking!");
+        file.write_all(buf).expect("Not complete blocks!");
+    }
 }
 
 #[test]
 fn efs_test() -> std::io::Result<()> {
-    let block_file = Arc::new(BlockFile(Mutex::new({
+    let block_file = Arc::new(BlockFile::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
@@ -38,7 +125,7 @@ fn efs_test() -> std::io::Result<()> {
             .open("target/fs.img")?;
         f.set_len(8192 * 512).unwrap();
         f
-    })));
+    }));
     EasyFileSystem::create(block_file.clone(), 4096, 1);
     let efs = EasyFileSystem::open(block_file.clone());
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
@@ -281,30 +368,596 @@ fn efs_test() -> std::io::Result<()> {
     random_str_test(400 * BLOCK_SZ);
     random_str_test(1000 * BLOCK_SZ);
     random_str_test(2000 * BLOCK_SZ);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
 
     Ok(())
 }
-fn easy_fs_pack() -> std::io::Result<()> {
-    let matches = App::new("EasyFileSystem packer")
-        .arg(
-            Arg::with_name("source")
-                .short("s")
-                .long("source")
-                .takes_value(true)
-                .help("Executable source dir(with backslash)"),
-        )
-        .arg(
-            Arg::with_name("target")
-                .short("t")
-                .long("target")
-                .takes_value(true)
-                .help("Executable target dir(with backslash)"),
-        )
-        .get_matches();
+fn open_image(path: &str) -> std::io::Result<Arc<BlockFile>> {
+    let f = OpenOptions::new().read(true).write(true).open(path)?;
+    Ok(Arc::new(BlockFile::new(f)))
+}
+
+#[test]
+fn efs_journal_test() -> std::io::Result<()> {
+    let base = "target/journal_base.img";
+    let image = "target/journal.img";
+    {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(base)?;
+        f.set_len(2048 * 512).unwrap();
+        let efs = EasyFileSystem::create(Arc::new(BlockFile::new(f)), 2048, 1);
+        let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+        let keep = root_inode.create("keep").unwrap();
+        keep.write_at(0, "hello".repeat(300).as_bytes());
+    }
+
+    // crash after every possible number of writes of a two step update, each
+    // step must survive whole or not at all
+    for budget in 0.. {
+        std::fs::copy(base, image)?;
+        let block_file = open_image(image)?;
+        let efs = EasyFileSystem::open(block_file.clone());
+        let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+        block_file.crash_after(budget);
+        let dir = root_inode.mkdir("dir").unwrap();
+        dir.create("inner")
+            .unwrap()
+            .write_at(0, &[7u8; 3 * BLOCK_SZ]);
+        assert!(root_inode.mv("keep", "dir/kept"));
+        let finished = !block_file.crashed();
+        drop((dir, root_inode, efs, block_file));
+
+        let efs = EasyFileSystem::open(open_image(image)?);
+        let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+        let kept = match root_inode.find("dir") {
+            None => root_inode.find("keep").unwrap(),
+            Some(dir) => {
+                assert!(Inode::same_inode(&dir.parent(), &root_inode));
+                let mut names = dir.ls();
+                names.sort();
+                match root_inode.find("keep") {
+                    Some(kept) => {
+                        assert!(names.is_empty() || names == ["inner"]);
+                        kept
+                    }
+                    None => {
+                        assert_eq!(names, ["inner", "kept"]);
+                        let inner = dir.find("inner").unwrap();
+                        let mut buffer = [0u8; 4 * BLOCK_SZ];
+                        assert_eq!(inner.read_at(0, &mut buffer), 3 * BLOCK_SZ);
+                        assert!(buffer[..3 * BLOCK_SZ].iter().all(|byte| *byte == 7));
+                        dir.find("kept").unwrap()
+                    }
+                }
+            }
+        };
+        let mut buffer = [0u8; 2048];
+        assert_eq!(kept.read_at(0, &mut buffer), 1500);
+        assert_eq!(&buffer[..1500], "hello".repeat(300).as_bytes());
+        assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+        // the recovered image keeps working
+        assert!(root_inode.create("after").is_some());
+        assert!(root_inode.find("after").is_some());
+        if finished {
+            assert!(root_inode.find("keep").is_none());
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+static FAKE_TIME: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(1000);
+
+#[test]
+fn efs_stat_test() -> std::io::Result<()> {
+    use easy_fs::StatMode;
+    easy_fs::set_clock(|| FAKE_TIME.load(Ordering::SeqCst));
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("target/stat.img")?;
+    f.set_len(2048 * 512).unwrap();
+    let efs = EasyFileSystem::create(Arc::new(BlockFile::new(f)), 2048, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let root = root_inode.stat();
+    assert_eq!(root.ino, 0);
+    assert_eq!(root.mode, StatMode::DIR | 0o755);
+    assert_eq!(root.nlink, 2);
+
+    FAKE_TIME.store(2000, Ordering::SeqCst);
+    let file = root_inode.create("file").unwrap();
+    let stat = file.stat();
+    assert_eq!(stat.mode, StatMode::FILE | 0o644);
+    assert_eq!((stat.nlink, stat.size, stat.blocks), (1, 0, 0));
+    assert_eq!((stat.atime, stat.mtime, stat.ctime), (2000, 2000, 2000));
+    assert_eq!(root_inode.stat().mtime, 2000);
+
+    FAKE_TIME.store(3000, Ordering::SeqCst);
+    file.write_at(0, &[1u8; 30 * BLOCK_SZ]);
+    let stat = file.stat();
+    assert_eq!((stat.size, stat.blocks), (30 * BLOCK_SZ as u64, 31));
+    assert_eq!((stat.atime, stat.mtime), (2000, 3000));
+    FAKE_TIME.store(4000, Ordering::SeqCst);
+    file.read_at(0, &mut [0u8; 16]);
+    assert_eq!(file.stat().atime, 4000);
+    assert_eq!(file.stat().mtime, 3000);
+
+    // directories count their subdirectories
+    let dir = root_inode.mkdir("dir").unwrap();
+    assert_eq!(root_inode.stat().nlink, 3);
+    dir.mkdir("sub").unwrap();
+    assert_eq!(dir.stat().nlink, 3);
+    assert!(root_inode.mv("dir/sub", "sub"));
+    assert_eq!((root_inode.stat().nlink, dir.stat().nlink), (4, 2));
+    assert!(root_inode.remove("sub"));
+    assert_eq!(root_inode.stat().nlink, 3);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    Ok(())
+}
+
+#[test]
+fn efs_link_test() -> std::io::Result<()> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("target/link.img")?;
+    f.set_len(2048 * 512).unwrap();
+    let efs = EasyFileSystem::create(Arc::new(BlockFile::new(f)), 2048, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let dir = root_inode.mkdir("dir").unwrap();
+    let file = dir.create("file").unwrap();
+    file.write_at(0, b"linked data");
+
+    // hard links share the inode, which lives until the last one goes
+    assert!(root_inode.link("alias", &file));
+    assert!(!root_inode.link("alias", &file));
+    assert!(!root_inode.link("dir2", &dir));
+    assert_eq!(file.stat().nlink, 2);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    assert!(dir.remove("file"));
+    let alias = root_inode.find("alias").unwrap();
+    assert!(Inode::same_inode(&alias, &file));
+    assert_eq!(alias.stat().nlink, 1);
+    let mut buf = [0u8; 11];
+    assert_eq!(alias.read_at(0, &mut buf), 11);
+    assert_eq!(&buf, b"linked data");
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+
+    // symbolic links resolve relative to their directory, or from the root
+    assert!(dir.symlink("up", "../alias").is_some());
+    assert!(root_inode.symlink("abs", "/dir/up").is_some());
+    assert!(root_inode.symlink("to_dir", "/dir").is_some());
+    assert!(Inode::same_inode(&root_inode.cd("dir/up").unwrap(), &file));
+    assert!(Inode::same_inode(&root_inode.cd("abs").unwrap(), &file));
+    assert!(Inode::same_inode(
+        &root_inode.cd("to_dir/up").unwrap(),
+        &file
+    ));
+    let link = root_inode.lookup("abs", false).unwrap();
+    assert!(link.is_symlink());
+    assert_eq!(link.readlink().as_deref(), Some("/dir/up"));
+    assert_eq!(link.stat().mode, easy_fs::StatMode::SYMLINK | 0o777);
+    assert!(file.readlink().is_none());
+    assert!(root_inode.symlink("abs", "elsewhere").is_none());
+
+    // loops and dangling links resolve to nothing
+    assert!(root_inode.symlink("ping", "pong").is_some());
+    assert!(root_inode.symlink("pong", "ping").is_some());
+    assert!(root_inode.symlink("dangling", "nowhere").is_some());
+    assert!(root_inode.cd("ping").is_none());
+    assert!(root_inode.cd("dangling").is_none());
+    assert!(root_inode.lookup("ping", false).unwrap().is_symlink());
+    let mut chain = String::from("alias");
+    for i in 0..9 {
+        let name = format!("chain{}", i);
+        root_inode.symlink(&name, &chain).unwrap();
+        chain = name;
+    }
+    assert!(root_inode.cd("chain7").is_some());
+    assert!(root_inode.cd("chain8").is_none());
+
+    // moving or removing a link leaves its target alone
+    assert!(root_inode.mv("to_dir", "dir/back"));
+    assert!(Inode::same_inode(&root_inode.cd("dir/back").unwrap(), &dir));
+    assert!(dir.remove("back"));
+    assert!(root_inode.cd("dir").is_some());
+    assert!(root_inode.remove("abs"));
+    assert_eq!(alias.stat().nlink, 1);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    Ok(())
+}
+
+#[test]
+fn efs_path_test() -> std::io::Result<()> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("target/path.img")?;
+    f.set_len(2048 * 512).unwrap();
+    let efs = EasyFileSystem::create(Arc::new(BlockFile::new(f)), 2048, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let a = root_inode.mkdir("a").unwrap();
+    let b = a.mkdir("b").unwrap();
+    b.create("file").unwrap();
+    assert_eq!(root_inode.path().as_deref(), Some("/"));
+    assert_eq!(b.path().as_deref(), Some("/a/b"));
+    assert!(Inode::same_inode(&b.cd("../../a/./b").unwrap(), &b));
+    assert!(Inode::same_inode(&b.cd("/a").unwrap(), &a));
+    assert!(b.cd("file/..").is_none());
+
+    let (dir, name) = root_inode.lookup_parent("a/b/new").unwrap();
+    assert!(Inode::same_inode(&dir, &b));
+    assert_eq!(name, "new");
+    let (dir, name) = b.lookup_parent("/a/").unwrap();
+    assert!(Inode::same_inode(&dir, &root_inode));
+    assert_eq!(name, "a");
+    assert!(root_inode.lookup_parent("/").is_none());
+    assert!(root_inode.lookup_parent("a/..").is_none());
+    assert!(root_inode.lookup_parent("missing/new").is_none());
+    assert!(root_inode.lookup_parent("a/b/file/new").is_none());
+
+    assert!(root_inode.mv("a/b", "b"));
+    assert_eq!(b.path().as_deref(), Some("/b"));
+    Ok(())
+}
+
+/// Read the `index`th u32 of a block straight from the device
+#[test]
+fn efs_truncate_test() -> std::io::Result<()> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("target/truncate.img")?;
+    f.set_len(4096 * 512).unwrap();
+    let efs = EasyFileSystem::create(Arc::new(BlockFile::new(f)), 4096, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let file = root_inode.create("file").unwrap();
+    let data: Vec<u8> = (0..400 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    file.write_at(0, &data);
+    assert_eq!(file.stat().blocks, 400 + 1 + 1 + 2);
+
+    // down through each level of indirection, the freed blocks accounted for
+    for blocks in [300, 200, 149, 100, 21, 3, 0] {
+        let size = (blocks * BLOCK_SZ).saturating_sub(100);
+        assert!(file.truncate(size));
+        assert_eq!(file.size(), size);
+        let mut buf = vec![0u8; size];
+        assert_eq!(file.read_at(0, &mut buf), size);
+        assert!(buf == data[..size]);
+        assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    }
+
+    // growing again reads back zeros, the old tail included, and leaves a
+    // hole
+    file.write_at(0, &data[..10]);
+    assert!(file.truncate(10));
+    assert!(file.truncate(200 * BLOCK_SZ));
+    let mut buf = vec![1u8; 200 * BLOCK_SZ];
+    assert_eq!(file.read_at(0, &mut buf), 200 * BLOCK_SZ);
+    assert_eq!(buf[..10], data[..10]);
+    assert!(buf[10..].iter().all(|byte| *byte == 0));
+    assert_eq!(file.stat().blocks, 1);
+    assert!(!file.truncate(usize::MAX));
+    assert_eq!(file.size(), 200 * BLOCK_SZ);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    Ok(())
+}
+
+#[test]
+fn efs_dir_test() -> std::io::Result<()> {
+    let image = "target/dir.img";
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image)?;
+    f.set_len(8192 * 512).unwrap();
+    let efs = EasyFileSystem::create(Arc::new(BlockFile::new(f)), 8192, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let dir = root_inode.mkdir("dir").unwrap();
+
+    // names up to 255 bytes
+    let long = "n".repeat(255);
+    assert!(dir.create(&long).is_some());
+    assert!(dir.find(&long).is_some());
+    assert!(dir.create(&"n".repeat(256)).is_none());
+    assert!(dir.mkdir("").is_none());
+    assert!(dir.mv(&long, &"m".repeat(255)));
+    assert!(dir.find(&long).is_none());
+    assert!(dir.remove(&"m".repeat(255)));
+
+    let name = |i: usize| format!("build-artifact-{}.o", i);
+    for i in 0..3000 {
+        assert!(dir.create(&name(i)).is_some(), "create {}", name(i));
+    }
+    for i in (0..3000).step_by(2) {
+        assert!(dir.remove(&name(i)));
+    }
+    for i in 0..3000 {
+        assert_eq!(dir.find(&name(i)).is_some(), i % 2 == 1);
+    }
+    assert_eq!(dir.ls().len(), 1500);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    drop((dir, root_inode, efs));
+
+    // a lookup reads the index and one leaf, not the whole directory
+    let block_file = open_image(image)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let dir = root_inode.find("dir").unwrap();
+    for i in [1, 777, 1501, 2999] {
+        let reads = block_file.reads.load(Ordering::SeqCst);
+        assert!(dir.find(&name(i)).is_some());
+        assert!(dir.find(&name(i - 1)).is_none());
+        assert!(block_file.reads.load(Ordering::SeqCst) - reads <= 8);
+    }
+    assert!(dir.size() > 100 * BLOCK_SZ);
+    assert!(!root_inode.remove("dir"));
+    Ok(())
+}
+
+#[test]
+fn efs_extent_test() -> std::io::Result<()> {
+    use easy_fs::MAX_FILE_SIZE;
+    let image = "target/extent.img";
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image)?;
+    f.set_len(8192 * 512).unwrap();
+    let efs =
+        EasyFileSystem::create_with(Arc::new(BlockFile::new(f)), 8192, 1, BlockMapping::Extents);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+
+    // a file written in one go is a single run, mapped from the inode alone
+    let big = root_inode.create("big").unwrap();
+    let data: Vec<u8> = (0..2000 * BLOCK_SZ).map(|i| (i % 253) as u8).collect();
+    assert_eq!(big.write_at(0, &data), data.len());
+    assert_eq!(big.stat().blocks, 2000);
+    assert!(big.max_size() > MAX_FILE_SIZE);
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(big.read_at(0, &mut buf), data.len());
+    assert!(buf == data);
+
+    // two files growing in turns fragment into a tree of several levels
+    let a = root_inode.create("a").unwrap();
+    let b = root_inode.create("b").unwrap();
+    for i in 0..600 {
+        a.write_at(i * BLOCK_SZ, &data[i * BLOCK_SZ..(i + 1) * BLOCK_SZ]);
+        b.write_at(i * BLOCK_SZ, &data[(i + 1) * BLOCK_SZ..(i + 2) * BLOCK_SZ]);
+    }
+    assert!(a.stat().blocks > 600 + 15);
+    let mut buf = vec![0u8; 600 * BLOCK_SZ];
+    assert_eq!(b.read_at(0, &mut buf), buf.len());
+    assert!(buf == data[BLOCK_SZ..601 * BLOCK_SZ]);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+
+    for blocks in [599, 400, 50, 7, 1, 0] {
+        let size = (blocks * BLOCK_SZ).saturating_sub(10);
+        assert!(a.truncate(size));
+        let mut buf = vec![0u8; size];
+        assert_eq!(a.read_at(0, &mut buf), size);
+        assert!(buf == data[..size]);
+        assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    }
+    assert_eq!(a.stat().blocks, 0);
+    assert!(root_inode.remove("b"));
+    assert!(root_inode.remove("big"));
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    drop((a, b, big, root_inode, efs));
+
+    // the mode is kept in the super block
+    let efs = EasyFileSystem::open(open_image(image)?);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let file = root_inode.create("again").unwrap();
+    file.write_at(0, &data[..300 * BLOCK_SZ]);
+    assert_eq!(file.stat().blocks, 300);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    Ok(())
+}
+
+#[test]
+fn efs_sparse_test() -> std::io::Result<()> {
+    use easy_fs::BlockMapping;
+    // blocks in use after each step, for either mapping
+    for (mapping, expected) in [
+        (BlockMapping::Indirect, [3, 5, 5, 2]),
+        (BlockMapping::Extents, [1, 2, 2, 1]),
+    ] {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("target/sparse.img")?;
+        f.set_len(2048 * 512).unwrap();
+        let efs = EasyFileSystem::create_with(Arc::new(BlockFile::new(f)), 2048, 1, mapping);
+        let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+        let file = root_inode.create("log").unwrap();
+
+        // far past the end of a disk of 1MiB, only the last block is backed
+        let far = 6 * 1024 * 1024 + 100;
+        assert_eq!(file.write_at(far, b"tail"), 4);
+        assert_eq!(file.size(), far + 4);
+        assert_eq!(file.stat().blocks, expected[0]);
+        let mut buf = vec![1u8; 3 * BLOCK_SZ];
+        assert_eq!(file.read_at(far - 2 * BLOCK_SZ, &mut buf), 2 * BLOCK_SZ + 4);
+        assert!(buf[..2 * BLOCK_SZ].iter().all(|byte| *byte == 0));
+        assert_eq!(&buf[2 * BLOCK_SZ..2 * BLOCK_SZ + 4], b"tail");
+        assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+
+        // filling in part of a hole maps just that block
+        assert_eq!(file.write_at(100 * BLOCK_SZ + 7, b"middle"), 6);
+        assert_eq!(file.stat().blocks, expected[1]);
+        let mut buf = vec![1u8; BLOCK_SZ];
+        assert_eq!(file.read_at(100 * BLOCK_SZ, &mut buf), BLOCK_SZ);
+        assert_eq!(&buf[7..13], b"middle");
+        assert!(buf[..7].iter().chain(&buf[13..]).all(|byte| *byte == 0));
+
+        // growing by truncate takes no blocks, shrinking frees the cut ones
+        assert!(file.truncate(7 * 1024 * 1024));
+        assert_eq!(file.stat().blocks, expected[2]);
+        assert!(file.truncate(101 * BLOCK_SZ));
+        assert_eq!(file.stat().blocks, expected[3]);
+        assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+        assert!(file.truncate(100 * BLOCK_SZ + 9));
+        let mut buf = vec![1u8; BLOCK_SZ];
+        assert_eq!(file.read_at(100 * BLOCK_SZ, &mut buf), 9);
+        assert_eq!(&buf[7..9], b"mi");
+        assert!(root_inode.remove("log"));
+        assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    }
+    Ok(())
+}
+
+#[test]
+fn efs_readahead_test() -> std::io::Result<()> {
+    let image = "target/readahead.img";
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image)?;
+    f.set_len(2048 * 512).unwrap();
+    let efs = EasyFileSystem::create(Arc::new(BlockFile::new(f)), 2048, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let file = root_inode.create("seq").unwrap();
+    let data: Vec<u8> = (0..200 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    assert_eq!(file.write_at(0, &data), data.len());
+    drop((file, root_inode, efs));
+
+    // reading block after block pulls the next ones in with the same request;
+    // other tests share the cache, so only a loose bound holds
+    let block_file = open_image(image)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let file = Arc::new(EasyFileSystem::root_inode(&efs))
+        .find("seq")
+        .unwrap();
+    let reads = block_file.reads.load(Ordering::SeqCst);
+    let mut buf = [0u8; BLOCK_SZ];
+    for i in 0..200 {
+        assert_eq!(file.read_at(i * BLOCK_SZ, &mut buf), BLOCK_SZ);
+        assert!(buf[..] == data[i * BLOCK_SZ..(i + 1) * BLOCK_SZ]);
+    }
+    assert!(block_file.reads.load(Ordering::SeqCst) - reads <= 100);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    Ok(())
+}
+
+#[cfg(test)]
+fn raw_u32(block_file: &BlockFile, block_id: usize, index: usize) -> u32 {
+    let mut buf = [0u8; BLOCK_SZ];
+    block_file.read_block(block_id, &mut buf);
+    u32::from_le_bytes(buf[index * 4..index * 4 + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+fn flip_raw_bit(block_file: &BlockFile, start_block: usize, bit: usize) {
+    let block_id = start_block + bit / (BLOCK_SZ * 8);
+    let mut buf = [0u8; BLOCK_SZ];
+    block_file.read_block(block_id, &mut buf);
+    buf[bit % (BLOCK_SZ * 8) / 8] ^= 1 << (bit % 8);
+    block_file.write_block(block_id, &buf);
+}
+
+#[test]
+fn efs_fsck_test() -> std::io::Result<()> {
+    use easy_fs::FsckProblem;
+    let image = "target/fsck.img";
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image)?;
+    f.set_len(2048 * 512).unwrap();
+    let efs = EasyFileSystem::create(Arc::new(BlockFile::new(f)), 2048, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let dir = root_inode.mkdir("dir").unwrap();
+    let file = dir.create("file").unwrap();
+    file.write_at(0, &[1u8; 40 * BLOCK_SZ]);
+    let doomed = root_inode.create("doomed").unwrap();
+    doomed.write_at(0, &[2u8; 3 * BLOCK_SZ]);
+    let stray = root_inode.mkdir("stray").unwrap();
+    root_inode
+        .create("scratch")
+        .unwrap()
+        .write_at(0, b"scratch");
+    assert!(root_inode.remove("scratch"));
+    assert!(root_inode.mv("stray", "dir/stray"));
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+
+    // break it: a stray parent pointer, a leaked block and a freed inode
+    stray.edit_parent(root_inode.clone());
+    let (stray_id, doomed_id) = (stray.self_id(), doomed.self_id());
+    drop((root_inode, dir, file, doomed, stray, efs));
+    let block_file = open_image(image)?;
+    let inode_bitmap_start = 1 + raw_u32(&block_file, 0, 2) as usize;
+    let data_bitmap_start =
+        inode_bitmap_start + (raw_u32(&block_file, 0, 3) + raw_u32(&block_file, 0, 4)) as usize;
+    let data_blocks = raw_u32(&block_file, 0, 6) as usize;
+    flip_raw_bit(&block_file, data_bitmap_start, data_blocks - 1);
+    flip_raw_bit(&block_file, inode_bitmap_start, doomed_id as usize);
+
+    let efs = EasyFileSystem::open(block_file);
+    let problems = EasyFileSystem::fsck(&efs, false);
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    assert!(problems.contains(&FsckProblem::WrongParent {
+        inode: stray_id,
+        parent: 0,
+        expected: 1,
+    }));
+    assert!(problems.contains(&FsckProblem::DanglingEntry {
+        dir: 0,
+        inode: doomed_id,
+    }));
+    let data_start = efs.lock().get_data_block_id(0);
+    assert!(problems.contains(&FsckProblem::LeakedBlock(
+        data_start + data_blocks as u32 - 1
+    )));
+    // the blocks of the lost file leak with it
+    let leaked = problems
+        .iter()
+        .filter(|problem| matches!(problem, FsckProblem::LeakedBlock(_)))
+        .count();
+    assert_eq!(leaked, 1 + 3);
+    assert_eq!(problems.len(), 2 + leaked);
+
+    assert_eq!(EasyFileSystem::fsck(&efs, true), problems);
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    assert!(root_inode.find("doomed").is_none());
+    let reborn = root_inode.create("doomed").unwrap();
+    reborn.write_at(0, &[3u8; 5 * BLOCK_SZ]);
+    assert!(root_inode.cd("dir/stray").is_some());
+    assert_eq!(EasyFileSystem::fsck(&efs, false), []);
+    Ok(())
+}
+
+fn easy_fs_pack(matches: &ArgMatches) -> std::io::Result<()> {
     let src_path = matches.value_of("source").unwrap();
     let target_path = matches.value_of("target").unwrap();
     println!("src_path: {}, target_path: {}", src_path, target_path);
-    let block_file = Arc::new(BlockFile(Mutex::new({
+    let block_file = Arc::new(BlockFile::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
@@ -312,8 +965,13 @@ fn easy_fs_pack() -> std::io::Result<()> {
             .open(format!("{}{}", target_path, "fs.img"))?;
         f.set_len(16 * 2048 * 512).unwrap();
         f
-    })));
-    let efs = EasyFileSystem::create(block_file, 16 * 2048, 1);
+    }));
+    let mapping = if matches.is_present("extents") {
+        BlockMapping::Extents
+    } else {
+        BlockMapping::Indirect
+    };
+    let efs = EasyFileSystem::create_with(block_file, 16 * 2048, 1, mapping);
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
     let apps: Vec<_> = read_dir(src_path)
         .unwrap()
@@ -337,6 +995,72 @@ fn easy_fs_pack() -> std::io::Result<()> {
     Ok(())
 }
 
+/// Check an image, and repair it when asked to. Exits with 1 if problems
+/// were found and left alone.
+fn easy_fs_fsck(matches: &ArgMatches) -> std::io::Result<()> {
+    let image = matches.value_of("image").unwrap();
+    let repair = matches.is_present("repair");
+    let efs = EasyFileSystem::open(open_image(image)?);
+    let problems = EasyFileSystem::fsck(&efs, repair);
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{}: clean", image);
+    } else if repair {
+        println!("{}: repaired {} problems", image, problems.len());
+    } else {
+        println!("{}: {} problems", image, problems.len());
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
 fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
+    easy_fs::set_clock(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() as u32)
+    });
+    let matches = App::new("EasyFileSystem packer")
+        .arg(
+            Arg::with_name("source")
+                .short("s")
+                .long("source")
+                .takes_value(true)
+                .help("Executable source dir(with backslash)"),
+        )
+        .arg(
+            Arg::with_name("target")
+                .short("t")
+                .long("target")
+                .takes_value(true)
+                .help("Executable target dir(with backslash)"),
+        )
+        .arg(
+            Arg::with_name("extents")
+                .short("e")
+                .long("extents")
+                .help("Map file blocks through extent trees"),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Check an easy-fs image for inconsistencies")
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the image"),
+                )
+                .arg(
+                    Arg::with_name("repair")
+                        .short("r")
+                        .long("repair")
+                        .help("Fix the problems found"),
+                ),
+        )
+        .get_matches();
+    match matches.subcommand_matches("fsck") {
+        Some(matches) => easy_fs_fsck(matches).expect("Error when checking easy-fs!"),
+        None => easy_fs_pack(&matches).expect("Error when packing easy-fs!"),
+    }
 }
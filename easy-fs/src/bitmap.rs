@@ -53,6 +53,51 @@ impl Bitmap {
         None
     }
 
+    /// First free bit from `goal` on, wrapping around to the start, so that
+    /// runs of allocations come out contiguous. Bits from `limit` on are
+    /// never handed out.
+    pub fn alloc_near(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        goal: usize,
+        limit: usize,
+    ) -> Option<usize> {
+        let limit = limit.min(self.maximum());
+        let goal = if goal < limit { goal } else { 0 };
+        let (goal_block, goal_bits64, goal_inner) = decomposition(goal);
+        // the block holding `goal` is visited twice, from `goal` on and then
+        // the bits before it
+        for i in 0..=self.blocks {
+            let block_id = (goal_block + i) % self.blocks;
+            let pos = get_block_cache(block_id + self.start_block_id, Arc::clone(block_device))
+                .lock()
+                .modify(0, |bitmap_block: &mut BitmapBlock| {
+                    let from = if i == 0 { goal_bits64 } else { 0 };
+                    for (bits64_pos, word) in bitmap_block.iter_mut().enumerate().skip(from) {
+                        let mut bits64 = *word;
+                        if i == 0 && bits64_pos == goal_bits64 {
+                            bits64 |= (1u64 << goal_inner) - 1;
+                        }
+                        let inner_pos = bits64.trailing_ones() as usize;
+                        let bit = block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos;
+                        if inner_pos == 64 {
+                            continue;
+                        }
+                        if bit >= limit {
+                            return None;
+                        }
+                        *word |= 1u64 << inner_pos;
+                        return Some(bit);
+                    }
+                    None
+                });
+            if pos.is_some() {
+                return pos;
+            }
+        }
+        None
+    }
+
     pub fn dealloc(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
         let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
         get_block_cache(
@@ -65,6 +110,29 @@ impl Bitmap {
             bitmap_block[bits64_pos] &= !(1u64 << inner_pos);
         });
     }
+
+    pub fn is_set(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block[bits64_pos] & (1u64 << inner_pos) != 0
+            })
+    }
+
+    /// Force a bit to `value`, whatever it was
+    pub fn assign(&self, block_device: &Arc<dyn BlockDevice>, bit: usize, value: bool) {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .modify(0, |bitmap_block: &mut BitmapBlock| {
+                if value {
+                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+                } else {
+                    bitmap_block[bits64_pos] &= !(1u64 << inner_pos);
+                }
+            });
+    }
 }
 
 fn decomposition(mut bit: usize) -> (usize, usize, usize) {
@@ -1,5 +1,8 @@
+use crate::sleep_lock::SleepLock;
 use crate::{BLOCK_SZ, block_dev::BlockDevice};
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
 use lazy_static::lazy_static;
 use spin::Mutex;
 
@@ -11,9 +14,10 @@ pub struct BlockCache {
 }
 
 impl BlockCache {
-    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
+    /// A cache of a block already read in
+    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>, data: &[u8]) -> Self {
         let mut cache = [0u8; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
+        cache.copy_from_slice(data);
         Self {
             cache,
             block_id,
@@ -70,46 +74,243 @@ impl Drop for BlockCache {
 }
 
 const BLOCK_CACHE_SIZE: usize = 16;
-use alloc::collections::VecDeque;
+/// A power of two, twice the entries to keep chains short
+const BUCKETS: usize = 32;
+const NONE: usize = usize::MAX;
+/// Blocks read at once when misses come in order
+const READ_AHEAD_BLOCKS: usize = 8;
 
+fn device_id(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
+}
+
+fn bucket_of(block_id: usize, device: usize) -> usize {
+    let hash = (block_id ^ device.rotate_left(17)).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    hash >> (usize::BITS - BUCKETS.trailing_zeros())
+}
+
+struct Slot {
+    block_id: usize,
+    device: usize,
+    cache: Arc<SleepLock<BlockCache>>,
+    /// Next slot in the same bucket
+    chain: usize,
+    /// Neighbours in recency order, towards the most and the least recently
+    /// used
+    newer: usize,
+    older: usize,
+}
+
+/// A block is cached once per device. Slots are found through a hash table
+/// and kept in a list from the most to the least recently used. Only clean
+/// blocks are evicted, a dirty one stays in until written back.
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    slots: Vec<Slot>,
+    buckets: [usize; BUCKETS],
+    newest: usize,
+    oldest: usize,
+    /// (device, block) of the last block read in, a miss right after it
+    /// reads ahead
+    last_read: Option<(usize, usize)>,
+    /// Devices whose size is known, only those are read ahead on. Holding
+    /// on to them keeps their address from naming another device.
+    sizes: Vec<(Weak<dyn BlockDevice>, usize)>,
 }
 
 impl BlockCacheManager {
     pub fn new() -> Self {
         Self {
-            queue: VecDeque::new(),
+            slots: Vec::with_capacity(BLOCK_CACHE_SIZE),
+            buckets: [NONE; BUCKETS],
+            newest: NONE,
+            oldest: NONE,
+            last_read: None,
+            sizes: Vec::new(),
+        }
+    }
+
+    pub fn set_device_size(&mut self, block_device: &Arc<dyn BlockDevice>, blocks: usize) {
+        let device = device_id(block_device);
+        self.sizes.retain(|(known, _)| {
+            known.strong_count() > 0 && known.as_ptr() as *const () as usize != device
+        });
+        self.sizes.push((Arc::downgrade(block_device), blocks));
+    }
+
+    /// How many blocks to read for a miss on `block_id`, the block itself
+    /// and any read-ahead up to the next one cached
+    fn read_window(&self, block_id: usize, device: usize) -> usize {
+        if self.last_read != Some((device, block_id.wrapping_sub(1))) {
+            return 1;
+        }
+        let Some(&(_, size)) = self
+            .sizes
+            .iter()
+            .find(|(known, _)| known.as_ptr() as *const () as usize == device)
+        else {
+            return 1;
+        };
+        let window = READ_AHEAD_BLOCKS.min(size.saturating_sub(block_id)).max(1);
+        (1..window)
+            .find(|i| self.find(block_id + i, device).is_some())
+            .unwrap_or(window)
+    }
+
+    fn find(&self, block_id: usize, device: usize) -> Option<usize> {
+        let mut i = self.buckets[bucket_of(block_id, device)];
+        while i != NONE {
+            let slot = &self.slots[i];
+            if slot.block_id == block_id && slot.device == device {
+                return Some(i);
+            }
+            i = slot.chain;
         }
+        None
     }
 
-    pub fn get_block_cache(
+    fn unlink(&mut self, i: usize) {
+        let (newer, older) = (self.slots[i].newer, self.slots[i].older);
+        match newer {
+            NONE => self.newest = older,
+            newer => self.slots[newer].older = older,
+        }
+        match older {
+            NONE => self.oldest = newer,
+            older => self.slots[older].newer = newer,
+        }
+    }
+
+    fn push_newest(&mut self, i: usize) {
+        self.slots[i].newer = NONE;
+        self.slots[i].older = self.newest;
+        match self.newest {
+            NONE => self.oldest = i,
+            newest => self.slots[newest].newer = i,
+        }
+        self.newest = i;
+    }
+
+    fn unhash(&mut self, i: usize) {
+        let (block_id, device) = (self.slots[i].block_id, self.slots[i].device);
+        let bucket = bucket_of(block_id, device);
+        if self.buckets[bucket] == i {
+            self.buckets[bucket] = self.slots[i].chain;
+            return;
+        }
+        let mut j = self.buckets[bucket];
+        while self.slots[j].chain != i {
+            j = self.slots[j].chain;
+        }
+        self.slots[j].chain = self.slots[i].chain;
+    }
+
+    /// Up to `count` clean slots nobody holds, the least recently used first,
+    /// and the dirty ones passed over on the way. Those have to be written
+    /// back before they can go, which is left to the caller with the manager
+    /// unlocked.
+    fn victims(&self, count: usize) -> (Vec<usize>, Vec<Arc<SleepLock<BlockCache>>>) {
+        let mut clean = Vec::new();
+        let mut dirty = Vec::new();
+        let mut i = self.oldest;
+        while i != NONE && clean.len() < count {
+            let cache = &self.slots[i].cache;
+            if Arc::strong_count(cache) == 1 {
+                match cache.try_lock() {
+                    Some(block) if !block.modified => clean.push(i),
+                    Some(_) => dirty.push(Arc::clone(cache)),
+                    None => {}
+                }
+            }
+            i = self.slots[i].newer;
+        }
+        (clean, dirty)
+    }
+
+    /// The cache of `block_id` if it is in, made the most recently used
+    fn lookup(&mut self, block_id: usize, device: usize) -> Option<Arc<SleepLock<BlockCache>>> {
+        let i = self.find(block_id, device)?;
+        self.unlink(i);
+        self.push_newest(i);
+        Some(Arc::clone(&self.slots[i].cache))
+    }
+
+    /// Blocks to read for a miss on `block_id`, or the dirty ones to write
+    /// back first to make room for them
+    fn prepare_read(
+        &self,
+        block_id: usize,
+        device: usize,
+    ) -> Result<usize, Vec<Arc<SleepLock<BlockCache>>>> {
+        let wanted = self.read_window(block_id, device);
+        let fresh = wanted.min(BLOCK_CACHE_SIZE.saturating_sub(self.slots.len()));
+        let (clean, dirty) = self.victims(wanted - fresh);
+        if fresh + clean.len() < wanted && !dirty.is_empty() {
+            return Err(dirty);
+        }
+        Ok(wanted)
+    }
+
+    /// Cache the blocks read from `block_id` on. Read-ahead blocks only take
+    /// a free or clean slot. The block asked for takes one anyway, so the
+    /// cache grows past its size when every slot is in use. A block cached
+    /// since it was read keeps that cache, it may be newer.
+    fn insert(
         &mut self,
         block_id: usize,
-        block_device: Arc<dyn BlockDevice>,
-    ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            return Arc::clone(&pair.1);
-        } else {
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("block cache queue full!");
-                }
+        block_device: &Arc<dyn BlockDevice>,
+        data: &[u8],
+    ) -> Arc<SleepLock<BlockCache>> {
+        let device = device_id(block_device);
+        self.last_read = Some((device, block_id + data.len() / BLOCK_SZ - 1));
+        // the block asked for ends up the most recently used
+        let mut requested = None;
+        for (n, data) in data.chunks_exact(BLOCK_SZ).enumerate().rev() {
+            if let Some(cache) = self.lookup(block_id + n, device) {
+                requested = Some(cache);
+                continue;
             }
+            let reused = if self.slots.len() < BLOCK_CACHE_SIZE {
+                None
+            } else {
+                match self.victims(1).0.first() {
+                    Some(&i) => {
+                        self.unhash(i);
+                        self.unlink(i);
+                        Some(i)
+                    }
+                    None if n == 0 => None,
+                    None => continue,
+                }
+            };
+            let cache = Arc::new(SleepLock::new(BlockCache::new(
+                block_id + n,
+                Arc::clone(block_device),
+                data,
+            )));
+            let bucket = bucket_of(block_id + n, device);
+            let slot = Slot {
+                block_id: block_id + n,
+                device,
+                cache: Arc::clone(&cache),
+                chain: self.buckets[bucket],
+                newer: NONE,
+                older: NONE,
+            };
+            let i = match reused {
+                Some(i) => {
+                    self.slots[i] = slot;
+                    i
+                }
+                None => {
+                    self.slots.push(slot);
+                    self.slots.len() - 1
+                }
+            };
+            self.buckets[bucket] = i;
+            self.push_newest(i);
+            requested = Some(cache);
         }
-        let block_cache = Arc::new(Mutex::new(BlockCache::new(
-            block_id,
-            Arc::clone(&block_device),
-        )));
-        self.queue.push_back((block_id, Arc::clone(&block_cache)));
-        block_cache
+        requested.unwrap()
     }
 }
 
@@ -118,18 +319,72 @@ lazy_static! {
         Mutex::new(BlockCacheManager::new());
 }
 
+/// The manager is only held to look blocks up and hand out slots, never over
+/// device I/O. Callers hold the file system lock, so a miss can't sleep
+/// waiting for a block to be let go of, and the one holding it may be the
+/// caller itself. Rather than wait, the cache grows past its size, by no
+/// more than the blocks held at once.
 pub fn get_block_cache(
     block_id: usize,
     block_device: Arc<dyn BlockDevice>,
-) -> Arc<Mutex<BlockCache>> {
-    BLOCK_CACHE_MANAGER
-        .lock()
-        .get_block_cache(block_id, block_device)
+) -> Arc<SleepLock<BlockCache>> {
+    let device = device_id(&block_device);
+    loop {
+        let mut manager = BLOCK_CACHE_MANAGER.lock();
+        if let Some(cache) = manager.lookup(block_id, device) {
+            return cache;
+        }
+        let prepared = manager.prepare_read(block_id, device);
+        drop(manager);
+        match prepared {
+            Ok(count) => {
+                let mut data = vec![0u8; count * BLOCK_SZ];
+                block_device.read_blocks(block_id, &mut data);
+                return BLOCK_CACHE_MANAGER
+                    .lock()
+                    .insert(block_id, &block_device, &data);
+            }
+            Err(dirty) => {
+                for cache in dirty {
+                    cache.lock().sync();
+                }
+            }
+        }
+    }
 }
 
+/// Write back every dirty block. The manager is not held meanwhile, so
+/// blocks can be looked up by whoever holds one of the caches.
 pub fn block_cache_sync_all() {
-    let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
+    let caches: Vec<_> = BLOCK_CACHE_MANAGER
+        .lock()
+        .slots
+        .iter()
+        .map(|slot| Arc::clone(&slot.cache))
+        .collect();
+    for cache in caches {
         cache.lock().sync();
     }
 }
+
+/// Write back the dirty blocks of `block_device` only
+pub fn block_cache_sync(block_device: &Arc<dyn BlockDevice>) {
+    let device = device_id(block_device);
+    let caches: Vec<_> = BLOCK_CACHE_MANAGER
+        .lock()
+        .slots
+        .iter()
+        .filter(|slot| slot.device == device)
+        .map(|slot| Arc::clone(&slot.cache))
+        .collect();
+    for cache in caches {
+        cache.lock().sync();
+    }
+}
+
+/// Let misses on `block_device` read ahead, up to its last block
+pub fn set_device_size(block_device: &Arc<dyn BlockDevice>, blocks: usize) {
+    BLOCK_CACHE_MANAGER
+        .lock()
+        .set_device_size(block_device, blocks);
+}
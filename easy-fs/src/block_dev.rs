@@ -1,6 +1,21 @@
+use crate::BLOCK_SZ;
 use core::any::Any;
 
 pub trait BlockDevice: Send + Sync + Any {
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
     fn write_block(&self, block_id: usize, buf: &[u8]);
+
+    /// Read the consecutive blocks from `block_id` on that fill `buf`, in as
+    /// few requests as the device manages
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        for (i, block) in buf.chunks_exact_mut(BLOCK_SZ).enumerate() {
+            self.read_block(block_id + i, block);
+        }
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
+        for (i, block) in buf.chunks_exact(BLOCK_SZ).enumerate() {
+            self.write_block(block_id + i, block);
+        }
+    }
 }
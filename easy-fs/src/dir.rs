@@ -0,0 +1,492 @@
+//! Directory blocks. Every block is a run of variable-length records that
+//! tile it exactly. A directory of one block is a plain list, a larger one
+//! keeps a hash index in block 0 (and at most one more level of index blocks
+//! below it) pointing at the leaf each range of name hashes lives in. Index
+//! blocks start with a free record over the whole block, so walking every
+//! block as a list of records still finds each entry once.
+use crate::{BLOCK_SZ, block_cache::get_block_cache, block_dev::BlockDevice, layout::DiskInode};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+
+/// Longest name an entry can have
+const NAME_LENGTH_LIMIT: usize = 255;
+/// Inode, record length, name length and a spare byte
+const RECORD_HEADER_SZ: usize = 8;
+/// The free record covering an index block, then its entry count and depth
+const INDEX_HEADER_SZ: usize = 16;
+/// Hash and block of the first entry of the range it covers
+const INDEX_ENTRY_SZ: usize = 8;
+const INDEX_ENTRY_LIMIT: usize = (BLOCK_SZ - INDEX_HEADER_SZ) / INDEX_ENTRY_SZ;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+/// FNV-1a, stable across builds as it is stored in the index
+fn name_hash(name: &[u8]) -> u32 {
+    name.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn record_len(name_len: usize) -> usize {
+    (RECORD_HEADER_SZ + name_len).next_multiple_of(4)
+}
+
+fn read_u16(block: &DataBlock, offset: usize) -> u16 {
+    u16::from_le_bytes([block[offset], block[offset + 1]])
+}
+
+fn read_u32(block: &DataBlock, offset: usize) -> u32 {
+    u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+}
+
+struct Record<'a> {
+    offset: usize,
+    inode: u32,
+    len: usize,
+    /// Empty for free space
+    name: &'a [u8],
+}
+
+/// Records of a block in order, stopping at the first one that is broken
+struct Records<'a> {
+    block: &'a DataBlock,
+    offset: usize,
+}
+
+impl<'a> Records<'a> {
+    fn new(block: &'a DataBlock) -> Self {
+        Self { block, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Record<'a>> {
+        let offset = self.offset;
+        if offset + RECORD_HEADER_SZ > BLOCK_SZ {
+            return None;
+        }
+        let len = read_u16(self.block, offset + 4) as usize;
+        let name_len = self.block[offset + 6] as usize;
+        if len < RECORD_HEADER_SZ + name_len || !len.is_multiple_of(4) || offset + len > BLOCK_SZ {
+            self.offset = BLOCK_SZ;
+            return None;
+        }
+        self.offset += len;
+        Some(Record {
+            offset,
+            inode: read_u32(self.block, offset),
+            len,
+            name: &self.block[offset + RECORD_HEADER_SZ..offset + RECORD_HEADER_SZ + name_len],
+        })
+    }
+}
+
+fn write_record(block: &mut DataBlock, offset: usize, inode: u32, len: usize, name: &[u8]) {
+    block[offset..offset + 4].copy_from_slice(&inode.to_le_bytes());
+    block[offset + 4..offset + 6].copy_from_slice(&(len as u16).to_le_bytes());
+    block[offset + 6] = name.len() as u8;
+    block[offset + 7] = 0;
+    block[offset + RECORD_HEADER_SZ..offset + RECORD_HEADER_SZ + name.len()].copy_from_slice(name);
+}
+
+/// Make a block one free record
+pub fn init_block(block: &mut DataBlock) {
+    write_record(block, 0, 0, BLOCK_SZ, &[]);
+}
+
+/// Offset, name and inode of the entries in a block, None if its records do
+/// not tile it
+pub fn block_entries(block: &DataBlock) -> Option<Vec<(usize, Vec<u8>, u32)>> {
+    let mut end = 0;
+    let mut entries = Vec::new();
+    for record in Records::new(block) {
+        end = record.offset + record.len;
+        if !record.name.is_empty() {
+            entries.push((record.offset, record.name.to_vec(), record.inode));
+        }
+    }
+    (end == BLOCK_SZ).then_some(entries)
+}
+
+/// Free the record at `offset`, its space goes to the record before it
+pub fn remove_at(block: &mut DataBlock, offset: usize) {
+    let Some(prev) = Records::new(block)
+        .take_while(|record| record.offset < offset)
+        .last()
+        .map(|record| (record.offset, record.len))
+    else {
+        block[offset + 6] = 0;
+        return;
+    };
+    let len = read_u16(block, offset + 4) as usize;
+    block[prev.0 + 4..prev.0 + 6].copy_from_slice(&((prev.1 + len) as u16).to_le_bytes());
+}
+
+/// Put a record in the spare room of a block, false if there is none
+fn leaf_insert(block: &mut DataBlock, name: &[u8], inode: u32) -> bool {
+    let need = record_len(name.len());
+    let Some((offset, len, used)) = Records::new(block).find_map(|record| {
+        let used = if record.name.is_empty() {
+            0
+        } else {
+            record_len(record.name.len())
+        };
+        (record.len - used >= need).then_some((record.offset, record.len, used))
+    }) else {
+        return false;
+    };
+    if used > 0 {
+        block[offset + 4..offset + 6].copy_from_slice(&(used as u16).to_le_bytes());
+    }
+    write_record(block, offset + used, inode, len - used, name);
+    true
+}
+
+fn index_count(block: &DataBlock) -> usize {
+    read_u16(block, RECORD_HEADER_SZ) as usize
+}
+
+/// Levels of index blocks under the root
+fn index_depth(block: &DataBlock) -> usize {
+    block[RECORD_HEADER_SZ + 2] as usize
+}
+
+fn index_entry(block: &DataBlock, i: usize) -> (u32, usize) {
+    let offset = INDEX_HEADER_SZ + i * INDEX_ENTRY_SZ;
+    (
+        read_u32(block, offset),
+        read_u32(block, offset + 4) as usize,
+    )
+}
+
+fn index_entries(block: &DataBlock) -> Vec<(u32, usize)> {
+    (0..index_count(block).min(INDEX_ENTRY_LIMIT))
+        .map(|i| index_entry(block, i))
+        .collect()
+}
+
+fn write_index(block: &mut DataBlock, depth: usize, entries: &[(u32, usize)]) {
+    block.fill(0);
+    init_block(block);
+    block[RECORD_HEADER_SZ..RECORD_HEADER_SZ + 2]
+        .copy_from_slice(&(entries.len() as u16).to_le_bytes());
+    block[RECORD_HEADER_SZ + 2] = depth as u8;
+    for (i, (hash, child)) in entries.iter().enumerate() {
+        let offset = INDEX_HEADER_SZ + i * INDEX_ENTRY_SZ;
+        block[offset..offset + 4].copy_from_slice(&hash.to_le_bytes());
+        block[offset + 4..offset + 8].copy_from_slice(&(*child as u32).to_le_bytes());
+    }
+}
+
+/// Whether the entries of an index block are sorted, start at hash 0 when
+/// `first` and point inside a directory of `blocks` blocks
+fn index_sane(entries: &[(u32, usize)], first: bool, blocks: usize) -> bool {
+    !entries.is_empty()
+        && (!first || entries[0].0 == 0)
+        && entries.windows(2).all(|pair| pair[0].0 < pair[1].0)
+        && entries
+            .iter()
+            .all(|(_, child)| *child > 0 && *child < blocks)
+}
+
+impl DiskInode {
+    fn dir_blocks(&self) -> usize {
+        self.size as usize / BLOCK_SZ
+    }
+
+    fn read_dir_block<V>(
+        &self,
+        n: usize,
+        block_device: &Arc<dyn BlockDevice>,
+        f: impl FnOnce(&DataBlock) -> V,
+    ) -> V {
+        get_block_cache(
+            self.get_block_id(n as u32, block_device) as usize,
+            Arc::clone(block_device),
+        )
+        .lock()
+        .read(0, f)
+    }
+
+    fn modify_dir_block<V>(
+        &self,
+        n: usize,
+        block_device: &Arc<dyn BlockDevice>,
+        f: impl FnOnce(&mut DataBlock) -> V,
+    ) -> V {
+        get_block_cache(
+            self.get_block_id(n as u32, block_device) as usize,
+            Arc::clone(block_device),
+        )
+        .lock()
+        .modify(0, f)
+    }
+
+    /// Append an empty block to a directory, returns its number
+    fn grow_dir(
+        &mut self,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut impl FnMut(u32) -> u32,
+    ) -> usize {
+        let n = self.dir_blocks();
+        let new_size = ((n + 1) * BLOCK_SZ) as u32;
+        self.increase_size(new_size, block_device, alloc);
+        self.modify_dir_block(n, block_device, init_block);
+        n
+    }
+
+    /// Follow the index down to the leaf for `hash`. Returns the index
+    /// blocks passed with the position of the entry taken in each, and the
+    /// leaf. None for an index that points nowhere sensible.
+    fn route(
+        &self,
+        hash: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Option<(Vec<(usize, usize)>, usize)> {
+        let blocks = self.dir_blocks();
+        if blocks <= 1 {
+            return Some((Vec::new(), 0));
+        }
+        let mut path = Vec::new();
+        let mut current = 0;
+        let mut depth = 0;
+        loop {
+            let (pos, child, root_depth) = self.read_dir_block(current, block_device, |block| {
+                let count = index_count(block).min(INDEX_ENTRY_LIMIT);
+                let pos = (1..count)
+                    .take_while(|i| index_entry(block, *i).0 <= hash)
+                    .last()
+                    .unwrap_or(0);
+                (pos, index_entry(block, pos).1, index_depth(block))
+            });
+            if child == 0 || child >= blocks {
+                return None;
+            }
+            path.push((current, pos));
+            if path.len() == 1 {
+                depth = root_depth.min(1);
+            }
+            if path.len() > depth {
+                return Some((path, child));
+            }
+            current = child;
+        }
+    }
+
+    /// Inode of the entry named `name`
+    pub fn find_entry(&self, name: &str, block_device: &Arc<dyn BlockDevice>) -> Option<u32> {
+        if self.dir_blocks() == 0 {
+            return None;
+        }
+        let (_, leaf) = self.route(name_hash(name.as_bytes()), block_device)?;
+        self.read_dir_block(leaf, block_device, |block| {
+            Records::new(block)
+                .find(|record| record.name == name.as_bytes())
+                .map(|record| record.inode)
+        })
+    }
+
+    /// Names and inodes of every entry, block by block
+    pub fn entries(&self, block_device: &Arc<dyn BlockDevice>) -> Vec<(String, u32)> {
+        let mut entries = Vec::new();
+        for n in 0..self.dir_blocks() {
+            self.read_dir_block(n, block_device, |block| {
+                for record in Records::new(block).filter(|record| !record.name.is_empty()) {
+                    entries.push((
+                        String::from_utf8_lossy(record.name).into_owned(),
+                        record.inode,
+                    ));
+                }
+            });
+        }
+        entries
+    }
+
+    /// Add an entry, false if the name is empty or too long or the
+    /// directory cannot take more. Blocks the directory grows by come from
+    /// `alloc`.
+    pub fn insert_entry(
+        &mut self,
+        name: &str,
+        inode: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut impl FnMut(u32) -> u32,
+    ) -> bool {
+        let name = name.as_bytes();
+        if name.is_empty() || name.len() > NAME_LENGTH_LIMIT {
+            return false;
+        }
+        if self.dir_blocks() == 0 {
+            self.grow_dir(block_device, alloc);
+        }
+        if self.dir_blocks() == 1 {
+            if self.modify_dir_block(0, block_device, |block| leaf_insert(block, name, inode)) {
+                return true;
+            }
+            // outgrown one block, its records move to the first leaf under
+            // a new index
+            let leaf = self.grow_dir(block_device, alloc);
+            let records = self.read_dir_block(0, block_device, |block| block.to_vec());
+            self.modify_dir_block(leaf, block_device, |block| block.copy_from_slice(&records));
+            self.modify_dir_block(0, block_device, |block| write_index(block, 0, &[(0, leaf)]));
+        }
+        let hash = name_hash(name);
+        let Some((_, leaf)) = self.route(hash, block_device) else {
+            return false;
+        };
+        if self.modify_dir_block(leaf, block_device, |block| leaf_insert(block, name, inode)) {
+            return true;
+        }
+        // the leaf splits, which takes an entry in the index block above it
+        if !self.make_index_room(hash, block_device, alloc) {
+            return false;
+        }
+        let Some((path, leaf)) = self.route(hash, block_device) else {
+            return false;
+        };
+        self.split_leaf(
+            *path.last().unwrap(),
+            leaf,
+            name,
+            inode,
+            block_device,
+            alloc,
+        )
+    }
+
+    /// Make sure the index block above the leaf for `hash` has a free entry
+    fn make_index_room(
+        &mut self,
+        hash: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut impl FnMut(u32) -> u32,
+    ) -> bool {
+        let Some((path, _)) = self.route(hash, block_device) else {
+            return false;
+        };
+        let (parent, _) = *path.last().unwrap();
+        let entries = self.read_dir_block(parent, block_device, index_entries);
+        if entries.len() < INDEX_ENTRY_LIMIT {
+            return true;
+        }
+        if path.len() == 1 {
+            // the root is full, its entries move down to a node that is
+            // then split like any other
+            let node = self.grow_dir(block_device, alloc);
+            self.modify_dir_block(node, block_device, |block| write_index(block, 0, &entries));
+            self.modify_dir_block(0, block_device, |block| write_index(block, 1, &[(0, node)]));
+            return self.make_index_room(hash, block_device, alloc);
+        }
+        let (_, pos) = path[0];
+        let mut root = self.read_dir_block(0, block_device, index_entries);
+        if root.len() == INDEX_ENTRY_LIMIT {
+            return false;
+        }
+        let (lower, upper) = entries.split_at(entries.len() / 2);
+        let node = self.grow_dir(block_device, alloc);
+        self.modify_dir_block(node, block_device, |block| write_index(block, 0, upper));
+        self.modify_dir_block(parent, block_device, |block| write_index(block, 0, lower));
+        root.insert(pos + 1, (upper[0].0, node));
+        self.modify_dir_block(0, block_device, |block| write_index(block, 1, &root));
+        true
+    }
+
+    /// Share the records of a full leaf and a new one between it and a new
+    /// leaf, split at a hash so that each hash stays in one leaf
+    fn split_leaf(
+        &mut self,
+        (parent, pos): (usize, usize),
+        leaf: usize,
+        name: &[u8],
+        inode: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut impl FnMut(u32) -> u32,
+    ) -> bool {
+        let mut records = self.read_dir_block(leaf, block_device, |block| {
+            Records::new(block)
+                .filter(|record| !record.name.is_empty())
+                .map(|record| (name_hash(record.name), record.name.to_vec(), record.inode))
+                .collect::<Vec<_>>()
+        });
+        records.push((name_hash(name), name.to_vec(), inode));
+        records.sort_by_key(|record| record.0);
+        let total: usize = records
+            .iter()
+            .map(|record| record_len(record.1.len()))
+            .sum();
+        let mut lower = 0;
+        let mut best: Option<(usize, usize)> = None;
+        for k in 1..records.len() {
+            lower += record_len(records[k - 1].1.len());
+            if records[k - 1].0 == records[k].0 || lower > BLOCK_SZ || total - lower > BLOCK_SZ {
+                continue;
+            }
+            let imbalance = lower.abs_diff(total - lower);
+            if best.is_none_or(|(least, _)| imbalance < least) {
+                best = Some((imbalance, k));
+            }
+        }
+        // too many names sharing a hash
+        let Some((_, k)) = best else {
+            return false;
+        };
+        let new_leaf = self.grow_dir(block_device, alloc);
+        for (n, part) in [(leaf, &records[..k]), (new_leaf, &records[k..])] {
+            self.modify_dir_block(n, block_device, |block| {
+                init_block(block);
+                for (_, name, inode) in part {
+                    assert!(leaf_insert(block, name, *inode));
+                }
+            });
+        }
+        self.modify_dir_block(parent, block_device, |block| {
+            let mut entries = index_entries(block);
+            entries.insert(pos + 1, (records[k].0, new_leaf));
+            write_index(block, index_depth(block), &entries);
+        });
+        true
+    }
+
+    /// Drop the entry named `name`, returns its inode. Blocks stay with the
+    /// directory.
+    pub fn remove_entry(&self, name: &str, block_device: &Arc<dyn BlockDevice>) -> Option<u32> {
+        if self.dir_blocks() == 0 {
+            return None;
+        }
+        let (_, leaf) = self.route(name_hash(name.as_bytes()), block_device)?;
+        self.modify_dir_block(leaf, block_device, |block| {
+            let (offset, inode) = Records::new(block)
+                .find(|record| record.name == name.as_bytes())
+                .map(|record| (record.offset, record.inode))?;
+            remove_at(block, offset);
+            Some(inode)
+        })
+    }
+
+    /// Numbers of the index blocks of a directory, None if the index is
+    /// broken
+    pub fn index_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> Option<Vec<usize>> {
+        let blocks = self.dir_blocks();
+        if blocks <= 1 {
+            return Some(Vec::new());
+        }
+        let (root, depth) = self.read_dir_block(0, block_device, |block| {
+            (index_entries(block), index_depth(block))
+        });
+        if depth > 1 || !index_sane(&root, true, blocks) {
+            return None;
+        }
+        let mut index = vec![0];
+        if depth == 1 {
+            for (_, node) in root {
+                let entries = self.read_dir_block(node, block_device, index_entries);
+                if !index_sane(&entries, false, blocks) || index.contains(&node) {
+                    return None;
+                }
+                index.push(node);
+            }
+        }
+        Some(index)
+    }
+}
@@ -1,20 +1,45 @@
 use crate::BLOCK_SZ;
 use crate::bitmap::Bitmap;
-use crate::block_cache::get_block_cache;
+use crate::block_cache::{
+    block_cache_sync, block_cache_sync_all, get_block_cache, set_device_size,
+};
 use crate::block_dev::BlockDevice;
-use crate::layout::{DiskInode, DiskInodeType, SuperBlock};
+use crate::journal::{JOURNAL_BLOCKS, Journal};
+use crate::layout::{BlockMapping, DiskInode, DiskInodeType, SuperBlock};
+use crate::sleep_lock::SleepLock;
 use crate::vfs::Inode;
 use alloc::sync::Arc;
 use spin::Mutex;
 
 type DataBlock = [u8; BLOCK_SZ];
 
+fn no_clock() -> u32 {
+    0
+}
+
+static CLOCK: Mutex<fn() -> u32> = Mutex::new(no_clock);
+
+/// Where inode timestamps come from, seconds since the epoch. Without a clock
+/// they stay 0.
+pub fn set_clock(clock: fn() -> u32) {
+    *CLOCK.lock() = clock;
+}
+
+pub fn now() -> u32 {
+    (CLOCK.lock())()
+}
+
 pub struct EasyFileSystem {
+    /// The journal in front of the disk, all metadata goes through it
     pub block_device: Arc<dyn BlockDevice>,
+    journal: Arc<Journal>,
     pub inode_bitmap: Bitmap,
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    data_area_blocks: u32,
+    /// How new inodes map their blocks
+    mapping: BlockMapping,
 }
 
 impl EasyFileSystem {
@@ -22,25 +47,49 @@ impl EasyFileSystem {
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
-    ) -> Arc<Mutex<Self>> {
-        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
+    ) -> Arc<SleepLock<Self>> {
+        Self::create_with(
+            block_device,
+            total_blocks,
+            inode_bitmap_blocks,
+            BlockMapping::Indirect,
+        )
+    }
+
+    /// Like `create`, with every inode mapping its blocks the `mapping` way
+    pub fn create_with(
+        block_device: Arc<dyn BlockDevice>,
+        total_blocks: u32,
+        inode_bitmap_blocks: u32,
+        mapping: BlockMapping,
+    ) -> Arc<SleepLock<Self>> {
+        let inode_bitmap_start = 1 + JOURNAL_BLOCKS;
+        let inode_bitmap = Bitmap::new(inode_bitmap_start as usize, inode_bitmap_blocks as usize);
         let inode_num = inode_bitmap.maximum();
         let inode_area_blocks =
             ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
+        let data_total_blocks = total_blocks - inode_bitmap_start - inode_total_blocks;
         let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
         let data_bitmap = Bitmap::new(
-            (1 + inode_total_blocks) as usize,
+            (inode_bitmap_start + inode_total_blocks) as usize,
             data_bitmap_blocks as usize,
         );
+        let journal = Arc::new(Journal::new(Arc::clone(&block_device), 1));
+        set_device_size(
+            &(journal.clone() as Arc<dyn BlockDevice>),
+            total_blocks as usize,
+        );
         let mut efs = Self {
-            block_device: Arc::clone(&block_device),
+            block_device: journal.clone(),
+            journal,
             inode_bitmap,
             data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks,
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            inode_area_start_block: inode_bitmap_start + inode_bitmap_blocks,
+            data_area_start_block: inode_bitmap_start + inode_total_blocks + data_bitmap_blocks,
+            data_area_blocks,
+            mapping,
         };
         for i in 0..total_blocks {
             get_block_cache(i as usize, Arc::clone(&block_device))
@@ -56,21 +105,36 @@ impl EasyFileSystem {
             |super_block: &mut SuperBlock| {
                 super_block.initialize(
                     total_blocks,
+                    JOURNAL_BLOCKS,
                     inode_bitmap_blocks,
                     inode_area_blocks,
                     data_bitmap_blocks,
                     data_area_blocks,
+                    mapping,
                 )
             },
         );
+        block_cache_sync_all();
+        efs.begin_transaction();
         assert_eq!(efs.alloc_inode(), 0);
         let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
-        get_block_cache(root_inode_block_id as usize, Arc::clone(&block_device))
+        get_block_cache(root_inode_block_id as usize, Arc::clone(&efs.block_device))
             .lock()
             .modify(root_inode_offset, |root_inode: &mut DiskInode| {
-                root_inode.initialize(DiskInodeType::Directory);
+                root_inode.initialize(DiskInodeType::Directory, now(), mapping);
             });
-        Arc::new(Mutex::new(efs))
+        efs.commit_transaction();
+        Arc::new(SleepLock::new(efs))
+    }
+
+    /// Group the following block writes until `commit_transaction`
+    pub fn begin_transaction(&self) {
+        self.journal.begin();
+    }
+
+    pub fn commit_transaction(&self) {
+        block_cache_sync(&self.block_device);
+        self.journal.commit();
     }
 
     pub fn alloc_inode(&mut self) -> u32 {
@@ -103,44 +167,88 @@ impl EasyFileSystem {
         self.data_area_start_block + data_block_id
     }
 
-    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
-        get_block_cache(0, Arc::clone(&block_device))
-            .lock()
-            .read(0, |super_block: &SuperBlock| {
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<SleepLock<Self>> {
+        let (
+            total_blocks,
+            journal_blocks,
+            inode_bitmap_blocks,
+            inode_area_blocks,
+            data_bitmap_blocks,
+            data_area_blocks,
+            mapping,
+        ) = get_block_cache(0, Arc::clone(&block_device)).lock().read(
+            0,
+            |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "super_block is invalid");
-                let inode_total_blocks =
-                    super_block.inode_area_blocks + super_block.inode_bitmap_blocks;
-                let efs = Self {
-                    block_device,
-                    inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
-                    data_bitmap: Bitmap::new(
-                        (1 + inode_total_blocks) as usize,
-                        super_block.data_bitmap_blocks as usize,
-                    ),
-                    inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-                    data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
-                };
-                Arc::new(Mutex::new(efs))
-            })
+                (
+                    super_block.total_blocks,
+                    super_block.journal_blocks,
+                    super_block.inode_bitmap_blocks,
+                    super_block.inode_area_blocks,
+                    super_block.data_bitmap_blocks,
+                    super_block.data_area_blocks,
+                    super_block.mapping(),
+                )
+            },
+        );
+        let journal = Arc::new(Journal::new(block_device, 1));
+        journal.replay();
+        set_device_size(
+            &(journal.clone() as Arc<dyn BlockDevice>),
+            total_blocks as usize,
+        );
+        let inode_bitmap_start = 1 + journal_blocks;
+        let inode_total_blocks = inode_area_blocks + inode_bitmap_blocks;
+        let efs = Self {
+            block_device: journal.clone(),
+            journal,
+            inode_bitmap: Bitmap::new(inode_bitmap_start as usize, inode_bitmap_blocks as usize),
+            data_bitmap: Bitmap::new(
+                (inode_bitmap_start + inode_total_blocks) as usize,
+                data_bitmap_blocks as usize,
+            ),
+            inode_area_start_block: inode_bitmap_start + inode_bitmap_blocks,
+            data_area_start_block: inode_bitmap_start + inode_total_blocks + data_bitmap_blocks,
+            data_area_blocks,
+            mapping,
+        };
+        Arc::new(SleepLock::new(efs))
+    }
+
+    pub fn mapping(&self) -> BlockMapping {
+        self.mapping
     }
 
+    /// Allocated blocks start out zeroed, freed ones are left as they are
     pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+        self.alloc_data_near(0)
     }
 
-    pub fn dealloc_data(&mut self, block_id: u32) {
+    /// The free block nearest after `goal`, so a file growing one block at a
+    /// time stays contiguous
+    pub fn alloc_data_near(&mut self, goal: u32) -> u32 {
+        let goal = goal.saturating_sub(self.data_area_start_block) as usize;
+        let block_id = self
+            .data_bitmap
+            .alloc_near(&self.block_device, goal, self.data_area_blocks as usize)
+            .unwrap() as u32
+            + self.data_area_start_block;
         get_block_cache(block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(0, |data_block: &mut DataBlock| {
                 data_block.iter_mut().for_each(|byte| *byte = 0);
             });
+        block_id
+    }
+
+    pub fn dealloc_data(&mut self, block_id: u32) {
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize,
         )
     }
 
-    pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
+    pub fn root_inode(efs: &Arc<SleepLock<Self>>) -> Inode {
         let block_device = Arc::clone(&efs.lock().block_device);
         let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
         Inode::new(block_id, block_offset, Arc::clone(efs), block_device)
@@ -0,0 +1,375 @@
+//! Extent trees. An extent-mapped inode keeps the root of a B+ tree in the
+//! words of its block pointers. Leaves hold runs of contiguous blocks, index
+//! nodes the first logical block under each child, and every node below the
+//! root takes a block of its own.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::mem::offset_of;
+
+use crate::{
+    BLOCK_SZ,
+    block_cache::get_block_cache,
+    block_dev::BlockDevice,
+    layout::{DiskInode, INODE_DIRECT_COUNT},
+};
+
+/// The direct pointers and both indirect ones
+pub const ROOT_WORDS: usize = INODE_DIRECT_COUNT + 2;
+const BLOCK_WORDS: usize = BLOCK_SZ / 4;
+const EXTENT_WORDS: usize = 3;
+const CHILD_WORDS: usize = 2;
+/// Set in the header word of index nodes, the low half is the entry count
+const INDEX_NODE: u32 = 1 << 16;
+
+const _: () = assert!(
+    offset_of!(DiskInode, indirect2) == offset_of!(DiskInode, direct) + (ROOT_WORDS - 1) * 4
+);
+
+type NodeBlock = [u32; BLOCK_WORDS];
+
+#[derive(Clone, Copy)]
+struct Extent {
+    logical: u32,
+    physical: u32,
+    len: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Child {
+    logical: u32,
+    block: u32,
+}
+
+enum Node {
+    Leaf(Vec<Extent>),
+    Index(Vec<Child>),
+}
+
+impl Node {
+    /// Counts past what `words` can hold are clamped, so a garbage node never
+    /// reads out of bounds
+    fn decode(words: &[u32]) -> Self {
+        let count = (words[0] & 0xffff) as usize;
+        if words[0] & INDEX_NODE == 0 {
+            let count = count.min((words.len() - 1) / EXTENT_WORDS);
+            Self::Leaf(
+                words[1..]
+                    .chunks_exact(EXTENT_WORDS)
+                    .take(count)
+                    .map(|w| Extent {
+                        logical: w[0],
+                        physical: w[1],
+                        len: w[2],
+                    })
+                    .collect(),
+            )
+        } else {
+            let count = count.min((words.len() - 1) / CHILD_WORDS);
+            Self::Index(
+                words[1..]
+                    .chunks_exact(CHILD_WORDS)
+                    .take(count)
+                    .map(|w| Child {
+                        logical: w[0],
+                        block: w[1],
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    fn encode(&self, words: &mut [u32]) {
+        words.fill(0);
+        match self {
+            Self::Leaf(extents) => {
+                words[0] = extents.len() as u32;
+                for (w, e) in words[1..].chunks_exact_mut(EXTENT_WORDS).zip(extents) {
+                    w.copy_from_slice(&[e.logical, e.physical, e.len]);
+                }
+            }
+            Self::Index(children) => {
+                words[0] = INDEX_NODE | children.len() as u32;
+                for (w, c) in words[1..].chunks_exact_mut(CHILD_WORDS).zip(children) {
+                    w.copy_from_slice(&[c.logical, c.block]);
+                }
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Leaf(extents) => extents.len(),
+            Self::Index(children) => children.len(),
+        }
+    }
+
+    fn fits(&self, words: usize) -> bool {
+        match self {
+            Self::Leaf(extents) => extents.len() <= (words - 1) / EXTENT_WORDS,
+            Self::Index(children) => children.len() <= (words - 1) / CHILD_WORDS,
+        }
+    }
+
+    fn first_logical(&self) -> u32 {
+        match self {
+            Self::Leaf(extents) => extents[0].logical,
+            Self::Index(children) => children[0].logical,
+        }
+    }
+
+    /// Move the upper half of the entries to a new node
+    fn split_off(&mut self) -> Self {
+        match self {
+            Self::Leaf(extents) => Self::Leaf(extents.split_off(extents.len() / 2)),
+            Self::Index(children) => Self::Index(children.split_off(children.len() / 2)),
+        }
+    }
+}
+
+/// The child whose range holds `logical`, the first one for anything before
+fn route(children: &[Child], logical: u32) -> usize {
+    children
+        .iter()
+        .rposition(|child| child.logical <= logical)
+        .unwrap_or(0)
+}
+
+fn read_node(block: u32, block_device: &Arc<dyn BlockDevice>) -> Node {
+    get_block_cache(block as usize, Arc::clone(block_device))
+        .lock()
+        .read(0, |words: &NodeBlock| Node::decode(words))
+}
+
+fn write_node(block: u32, node: &Node, block_device: &Arc<dyn BlockDevice>) {
+    get_block_cache(block as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |words: &mut NodeBlock| node.encode(words));
+}
+
+/// Map `logical` to `physical` somewhere under `node`, growing an existing
+/// extent where the two line up. The caller splits `node` if it overflows.
+fn insert(
+    node: &mut Node,
+    logical: u32,
+    physical: u32,
+    block_device: &Arc<dyn BlockDevice>,
+    alloc: &mut impl FnMut(u32) -> u32,
+) {
+    match node {
+        Node::Leaf(extents) => {
+            let pos = extents.partition_point(|e| e.logical < logical);
+            if let Some(prev) = pos.checked_sub(1).map(|i| &mut extents[i])
+                && prev.logical + prev.len == logical
+                && prev.physical + prev.len == physical
+            {
+                prev.len += 1;
+                return;
+            }
+            extents.insert(
+                pos,
+                Extent {
+                    logical,
+                    physical,
+                    len: 1,
+                },
+            );
+        }
+        Node::Index(children) => {
+            let i = route(children, logical);
+            let block = children[i].block;
+            let mut child = read_node(block, block_device);
+            insert(&mut child, logical, physical, block_device, alloc);
+            children[i].logical = children[i].logical.min(logical);
+            if !child.fits(BLOCK_WORDS) {
+                let right = child.split_off();
+                let right_block = alloc(0);
+                write_node(right_block, &right, block_device);
+                children.insert(
+                    i + 1,
+                    Child {
+                        logical: right.first_logical(),
+                        block: right_block,
+                    },
+                );
+            }
+            write_node(block, &child, block_device);
+        }
+    }
+}
+
+/// Drop the mappings from logical block `blocks` on, pushing the data and
+/// node blocks that go to `freed`
+fn truncate(
+    node: &mut Node,
+    blocks: u32,
+    block_device: &Arc<dyn BlockDevice>,
+    freed: &mut Vec<u32>,
+) {
+    match node {
+        Node::Leaf(extents) => extents.retain_mut(|e| {
+            let keep = blocks.saturating_sub(e.logical).min(e.len);
+            freed.extend(e.physical + keep..e.physical + e.len);
+            e.len = keep;
+            keep > 0
+        }),
+        Node::Index(children) => {
+            let mut kept = Vec::new();
+            for (i, child) in children.iter().enumerate() {
+                // children wholly below the cut stay as they are
+                if children
+                    .get(i + 1)
+                    .is_some_and(|next| next.logical <= blocks)
+                {
+                    kept.push(*child);
+                    continue;
+                }
+                let mut node = read_node(child.block, block_device);
+                truncate(&mut node, blocks, block_device, freed);
+                if node.len() == 0 {
+                    freed.push(child.block);
+                } else {
+                    write_node(child.block, &node, block_device);
+                    kept.push(*child);
+                }
+            }
+            *children = kept;
+        }
+    }
+}
+
+/// Every extent under the root in `root`, as (logical, physical, len) in
+/// logical order. `visit` sees each node block before it is read and may
+/// refuse it, in which case the walk stops there.
+pub fn extent_map(
+    root: &[u32; ROOT_WORDS],
+    block_device: &Arc<dyn BlockDevice>,
+    visit: &mut impl FnMut(u32) -> bool,
+) -> Vec<(u32, u32, u32)> {
+    fn walk(
+        node: Node,
+        block_device: &Arc<dyn BlockDevice>,
+        visit: &mut impl FnMut(u32) -> bool,
+        out: &mut Vec<(u32, u32, u32)>,
+    ) -> bool {
+        match node {
+            Node::Leaf(extents) => {
+                out.extend(extents.iter().map(|e| (e.logical, e.physical, e.len)));
+                true
+            }
+            Node::Index(children) => children.iter().all(|child| {
+                visit(child.block)
+                    && walk(
+                        read_node(child.block, block_device),
+                        block_device,
+                        visit,
+                        out,
+                    )
+            }),
+        }
+    }
+    let mut out = Vec::new();
+    walk(Node::decode(root), block_device, visit, &mut out);
+    out
+}
+
+impl DiskInode {
+    /// The block pointers as one array of words, the root node in extent mode
+    pub fn extent_root(&self) -> &[u32; ROOT_WORDS] {
+        // the pointers are consecutive u32s, see the assertion above
+        unsafe {
+            &*((self as *const Self as *const u8).add(offset_of!(DiskInode, direct))
+                as *const [u32; ROOT_WORDS])
+        }
+    }
+
+    pub(crate) fn extent_root_mut(&mut self) -> &mut [u32; ROOT_WORDS] {
+        unsafe {
+            &mut *((self as *mut Self as *mut u8).add(offset_of!(DiskInode, direct))
+                as *mut [u32; ROOT_WORDS])
+        }
+    }
+
+    /// 0 where nothing is mapped
+    pub(crate) fn extent_block_id(
+        &self,
+        inner_id: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> u32 {
+        let mut node = Node::decode(self.extent_root());
+        loop {
+            match node {
+                Node::Leaf(extents) => {
+                    return extents
+                        .iter()
+                        .find(|e| e.logical <= inner_id && inner_id - e.logical < e.len)
+                        .map_or(0, |e| e.physical + inner_id - e.logical);
+                }
+                Node::Index(children) if !children.is_empty() => {
+                    node = read_node(children[route(&children, inner_id)].block, block_device);
+                }
+                Node::Index(_) => return 0,
+            }
+        }
+    }
+
+    /// Map data block `inner_id` to `block`, node blocks come from `alloc`
+    pub(crate) fn extent_insert(
+        &mut self,
+        inner_id: u32,
+        block: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut impl FnMut(u32) -> u32,
+    ) {
+        let mut root = Node::decode(self.extent_root());
+        insert(&mut root, inner_id, block, block_device, alloc);
+        if !root.fits(ROOT_WORDS) {
+            // a full root moves to a block of its own, it is small enough
+            // to fit there whole
+            let child = alloc(0);
+            write_node(child, &root, block_device);
+            root = Node::Index(vec![Child {
+                logical: root.first_logical(),
+                block: child,
+            }]);
+        }
+        root.encode(self.extent_root_mut());
+    }
+
+    /// Unmap the data blocks from `blocks` on, returns the blocks freed
+    pub(crate) fn extent_truncate(
+        &mut self,
+        blocks: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        let mut freed = Vec::new();
+        let mut root = Node::decode(self.extent_root());
+        truncate(&mut root, blocks, block_device, &mut freed);
+        // pull a lone child back into the root while it fits
+        while let Node::Index(children) = &root {
+            match children[..] {
+                [] => root = Node::Leaf(Vec::new()),
+                [child] => {
+                    let node = read_node(child.block, block_device);
+                    if !node.fits(ROOT_WORDS) {
+                        break;
+                    }
+                    freed.push(child.block);
+                    root = node;
+                }
+                _ => break,
+            }
+        }
+        root.encode(self.extent_root_mut());
+        freed
+    }
+
+    /// Data and node blocks in use
+    pub(crate) fn extent_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        let mut nodes = 0;
+        let extents = extent_map(self.extent_root(), block_device, &mut |_| {
+            nodes += 1;
+            true
+        });
+        nodes + extents.iter().map(|(_, _, len)| len).sum::<u32>()
+    }
+}
@@ -0,0 +1,493 @@
+use crate::BLOCK_SZ;
+use crate::block_cache::{block_cache_sync_all, get_block_cache};
+use crate::block_dev::BlockDevice;
+use crate::dir::{block_entries, init_block, remove_at};
+use crate::efs::EasyFileSystem;
+use crate::extent::extent_map;
+use crate::layout::{
+    DiskInode, INDIRECT1_BOUND, INODE_DIRECT_COUNT, INODE_INDIRECT1_COUNT, SuperBlock,
+};
+use crate::sleep_lock::SleepLock;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+type IndirectBlock = [u32; BLOCK_SZ / 4];
+type DataBlock = [u8; BLOCK_SZ];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// The areas in the super block do not add up, nothing else is checked
+    BadSuperBlock,
+    /// A pointer outside the data area, the inode is cut short before it or
+    /// emptied if it maps through extents
+    BadBlockPointer { inode: u32, block: u32 },
+    /// A block already used elsewhere, repaired like a bad pointer
+    SharedBlock { inode: u32, block: u32 },
+    /// Used by an inode but free in the data bitmap
+    UnallocatedBlock(u32),
+    /// Marked in the data bitmap but used by nobody
+    LeakedBlock(u32),
+    /// Reachable but free in the inode bitmap
+    UnallocatedInode(u32),
+    /// Allocated but not reachable from the root
+    LeakedInode(u32),
+    /// An entry with a garbage name or naming a free inode
+    DanglingEntry { dir: u32, inode: u32 },
+    /// A second entry for a directory that is already linked
+    ExtraLink { dir: u32, name: String, inode: u32 },
+    WrongLinkCount {
+        inode: u32,
+        nlink: u32,
+        expected: u32,
+    },
+    WrongParent {
+        inode: u32,
+        parent: u32,
+        expected: u32,
+    },
+    /// A directory whose size is not a whole number of blocks
+    BadDirSize { inode: u32, size: u32 },
+    /// A directory block whose records do not tile it, emptied on repair. A
+    /// hole in a directory shows up as block 0 and is left alone.
+    BadDirBlock { dir: u32, block: u32 },
+    /// A directory whose hash index is broken, only reported
+    BadDirIndex(u32),
+}
+
+impl fmt::Display for FsckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadSuperBlock => write!(f, "super block areas do not match the image"),
+            Self::BadBlockPointer { inode, block } => {
+                write!(
+                    f,
+                    "inode {} points outside the data area at {}",
+                    inode, block
+                )
+            }
+            Self::SharedBlock { inode, block } => {
+                write!(f, "inode {} uses block {} already in use", inode, block)
+            }
+            Self::UnallocatedBlock(block) => write!(f, "block {} is used but free", block),
+            Self::LeakedBlock(block) => write!(f, "block {} is allocated but unused", block),
+            Self::UnallocatedInode(inode) => write!(f, "inode {} is used but free", inode),
+            Self::LeakedInode(inode) => write!(f, "inode {} is allocated but unreachable", inode),
+            Self::DanglingEntry { dir, inode } => {
+                write!(
+                    f,
+                    "directory {} has a dangling entry for inode {}",
+                    dir, inode
+                )
+            }
+            Self::ExtraLink { dir, name, inode } => write!(
+                f,
+                "directory {} links inode {} again as {}",
+                dir, inode, name
+            ),
+            Self::WrongLinkCount {
+                inode,
+                nlink,
+                expected,
+            } => write!(
+                f,
+                "inode {} has {} links, expected {}",
+                inode, nlink, expected
+            ),
+            Self::WrongParent {
+                inode,
+                parent,
+                expected,
+            } => write!(
+                f,
+                "inode {} has parent {}, expected {}",
+                inode, parent, expected
+            ),
+            Self::BadDirSize { inode, size } => {
+                write!(f, "directory {} has size {}", inode, size)
+            }
+            Self::BadDirBlock { dir, block } => {
+                write!(f, "directory {} has a broken block {}", dir, block)
+            }
+            Self::BadDirIndex(dir) => write!(f, "directory {} has a broken index", dir),
+        }
+    }
+}
+
+struct Checker<'a> {
+    fs: &'a EasyFileSystem,
+    block_device: Arc<dyn BlockDevice>,
+    repair: bool,
+    inode_count: u32,
+    data_start: u32,
+    /// Blocks of the data area some inode already uses
+    used: Vec<bool>,
+    reachable: BTreeSet<u32>,
+    /// Entries naming each file, subdirectories of each directory
+    links: BTreeMap<u32, u32>,
+    problems: Vec<FsckProblem>,
+}
+
+impl Checker<'_> {
+    fn read_inode<V>(&self, inode: u32, f: impl FnOnce(&DiskInode) -> V) -> V {
+        let (block_id, offset) = self.fs.get_disk_inode_pos(inode);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(offset, f)
+    }
+
+    fn modify_inode<V>(&self, inode: u32, f: impl FnOnce(&mut DiskInode) -> V) -> V {
+        let (block_id, offset) = self.fs.get_disk_inode_pos(inode);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(offset, f)
+    }
+
+    /// 0 within a missing indirect block
+    fn read_pointer(&self, block: u32, index: usize) -> u32 {
+        if block == 0 {
+            return 0;
+        }
+        get_block_cache(block as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |indirect: &IndirectBlock| indirect[index])
+    }
+
+    /// Mark a block pointed to by `inode` as used, false if it must not be
+    fn claim(&mut self, inode: u32, block: u32) -> bool {
+        let Some(pos) = block
+            .checked_sub(self.data_start)
+            .filter(|pos| (*pos as usize) < self.used.len())
+        else {
+            self.problems
+                .push(FsckProblem::BadBlockPointer { inode, block });
+            return false;
+        };
+        if self.used[pos as usize] {
+            self.problems
+                .push(FsckProblem::SharedBlock { inode, block });
+            return false;
+        }
+        self.used[pos as usize] = true;
+        true
+    }
+
+    /// Claim the blocks of an inode and return its data blocks in order, 0
+    /// for holes. The walk stops at the first bad pointer, when repairing the
+    /// size is cut back to the blocks before it.
+    fn walk_blocks(&mut self, inode: u32) -> Vec<u32> {
+        if self.read_inode(inode, |disk_inode| disk_inode.uses_extents()) {
+            return self.walk_extents(inode);
+        }
+        let (size, direct, indirect1, indirect2) = self.read_inode(inode, |disk_inode| {
+            (
+                disk_inode.size,
+                disk_inode.direct,
+                disk_inode.indirect1,
+                disk_inode.indirect2,
+            )
+        });
+        let total = size.div_ceil(BLOCK_SZ as u32) as usize;
+        let mut data = Vec::new();
+        let mut child = 0;
+        for i in 0..total {
+            if i == INODE_DIRECT_COUNT && indirect1 != 0 && !self.claim(inode, indirect1) {
+                break;
+            }
+            if i == INDIRECT1_BOUND && indirect2 != 0 && !self.claim(inode, indirect2) {
+                break;
+            }
+            if i >= INDIRECT1_BOUND && (i - INDIRECT1_BOUND).is_multiple_of(INODE_INDIRECT1_COUNT) {
+                child = self.read_pointer(indirect2, (i - INDIRECT1_BOUND) / INODE_INDIRECT1_COUNT);
+                if child != 0 && !self.claim(inode, child) {
+                    break;
+                }
+            }
+            let block = if let Some(&block) = direct.get(i) {
+                block
+            } else if i < INDIRECT1_BOUND {
+                self.read_pointer(indirect1, i - INODE_DIRECT_COUNT)
+            } else {
+                self.read_pointer(child, (i - INDIRECT1_BOUND) % INODE_INDIRECT1_COUNT)
+            };
+            if block != 0 && !self.claim(inode, block) {
+                break;
+            }
+            data.push(block);
+        }
+        if data.len() < total && self.repair {
+            let cut = (data.len() * BLOCK_SZ) as u32;
+            self.modify_inode(inode, |disk_inode| disk_inode.size = cut);
+        }
+        data
+    }
+
+    /// `walk_blocks` for an inode mapping through extents. Past a bad pointer
+    /// nothing in the tree can be trusted, so repairing empties the inode.
+    fn walk_extents(&mut self, inode: u32) -> Vec<u32> {
+        let (size, root) = self.read_inode(inode, |disk_inode| {
+            (disk_inode.size, *disk_inode.extent_root())
+        });
+        let total = size.div_ceil(BLOCK_SZ as u32) as usize;
+        let block_device = Arc::clone(&self.block_device);
+        let mut claimed = Vec::new();
+        let mut good = true;
+        let extents = extent_map(&root, &block_device, &mut |block| {
+            good = self.claim(inode, block);
+            if good {
+                claimed.push(block);
+            }
+            good
+        });
+        let mut data = vec![0; total];
+        'walk: for (logical, physical, len) in extents.into_iter().take_while(|_| good) {
+            for i in 0..len {
+                let block = physical.saturating_add(i);
+                if !self.claim(inode, block) {
+                    good = false;
+                    break 'walk;
+                }
+                claimed.push(block);
+                if let Some(slot) = data.get_mut(logical.saturating_add(i) as usize) {
+                    *slot = block;
+                }
+            }
+        }
+        if !good && self.repair {
+            self.modify_inode(inode, |disk_inode| {
+                disk_inode.size = 0;
+                disk_inode.extent_root_mut().fill(0);
+            });
+            for block in claimed {
+                self.used[(block - self.data_start) as usize] = false;
+            }
+            data.clear();
+        }
+        data
+    }
+
+    fn inode_allocated(&self, inode: u32) -> bool {
+        self.fs
+            .inode_bitmap
+            .is_set(&self.block_device, inode as usize)
+    }
+
+    fn check_parent(&mut self, inode: u32, expected: u32) {
+        let parent = self.read_inode(inode, |disk_inode| disk_inode.parent);
+        if parent != expected {
+            self.problems.push(FsckProblem::WrongParent {
+                inode,
+                parent,
+                expected,
+            });
+            if self.repair {
+                self.modify_inode(inode, |disk_inode| disk_inode.parent = expected);
+            }
+        }
+    }
+
+    /// Check the entries of a directory, returns the subdirectories
+    fn check_dir(&mut self, dir: u32) -> Vec<u32> {
+        let mut blocks = self.walk_blocks(dir);
+        let size = self.read_inode(dir, |disk_inode| disk_inode.size);
+        if !(size as usize).is_multiple_of(BLOCK_SZ) {
+            self.problems
+                .push(FsckProblem::BadDirSize { inode: dir, size });
+            if self.repair {
+                self.cut_dir(dir, size - size % BLOCK_SZ as u32);
+            }
+        }
+        blocks.truncate(size as usize / BLOCK_SZ);
+        let block_device = Arc::clone(&self.block_device);
+        let index = self.read_inode(dir, |disk_inode| disk_inode.index_blocks(&block_device));
+        if index.is_none() {
+            self.problems.push(FsckProblem::BadDirIndex(dir));
+        }
+        let index = index.unwrap_or_default();
+        let mut subdirs = Vec::new();
+        for (n, block) in blocks.into_iter().enumerate() {
+            if index.contains(&n) {
+                continue;
+            }
+            if block == 0 {
+                // nothing to put back in a hole, only reported
+                self.problems.push(FsckProblem::BadDirBlock { dir, block });
+                continue;
+            }
+            let cache = get_block_cache(block as usize, Arc::clone(&self.block_device));
+            let Some(entries) = cache.lock().read(0, block_entries) else {
+                self.problems.push(FsckProblem::BadDirBlock { dir, block });
+                if self.repair {
+                    cache.lock().modify(0, init_block);
+                }
+                continue;
+            };
+            for (offset, name, inode) in entries {
+                if !self.check_entry(dir, name, inode, &mut subdirs) && self.repair {
+                    cache.lock().modify(0, |data_block: &mut DataBlock| {
+                        remove_at(data_block, offset)
+                    });
+                }
+            }
+        }
+        subdirs
+    }
+
+    /// Account for one entry of `dir`, false if it has to go
+    fn check_entry(&mut self, dir: u32, name: Vec<u8>, inode: u32, subdirs: &mut Vec<u32>) -> bool {
+        let Ok(name) = String::from_utf8(name) else {
+            self.problems
+                .push(FsckProblem::DanglingEntry { dir, inode });
+            return false;
+        };
+        if inode >= self.inode_count || !self.inode_allocated(inode) {
+            self.problems
+                .push(FsckProblem::DanglingEntry { dir, inode });
+            return false;
+        }
+        let is_dir = self.read_inode(inode, |disk_inode| disk_inode.is_dir());
+        let first = self.reachable.insert(inode);
+        if is_dir && !first {
+            self.problems
+                .push(FsckProblem::ExtraLink { dir, name, inode });
+            return false;
+        }
+        if is_dir {
+            // only directories keep their parent, a file may have several
+            self.check_parent(inode, dir);
+            *self.links.entry(dir).or_default() += 1;
+            subdirs.push(inode);
+        } else {
+            *self.links.entry(inode).or_default() += 1;
+            if first {
+                self.walk_blocks(inode);
+            }
+        }
+        true
+    }
+
+    /// Cut a directory back to `size`, blocks it no longer needs become free
+    /// again
+    fn cut_dir(&mut self, dir: u32, size: u32) {
+        let block_device = Arc::clone(&self.block_device);
+        let freed = self.modify_inode(dir, |disk_inode| {
+            disk_inode.decrease_size(size, &block_device)
+        });
+        for block in freed {
+            self.used[(block - self.data_start) as usize] = false;
+        }
+    }
+
+    fn check_links(&mut self) {
+        for inode in self.reachable.clone() {
+            let (nlink, is_dir) =
+                self.read_inode(inode, |disk_inode| (disk_inode.nlink, disk_inode.is_dir()));
+            let counted = self.links.get(&inode).copied().unwrap_or(0);
+            let expected = if is_dir { 2 + counted } else { counted };
+            if nlink != expected {
+                self.problems.push(FsckProblem::WrongLinkCount {
+                    inode,
+                    nlink,
+                    expected,
+                });
+                if self.repair {
+                    self.modify_inode(inode, |disk_inode| disk_inode.nlink = expected);
+                }
+            }
+        }
+    }
+
+    fn check_bitmaps(&mut self) {
+        for inode in 0..self.inode_count {
+            let allocated = self.inode_allocated(inode);
+            let reachable = self.reachable.contains(&inode);
+            if allocated == reachable {
+                continue;
+            }
+            self.problems.push(if reachable {
+                FsckProblem::UnallocatedInode(inode)
+            } else {
+                FsckProblem::LeakedInode(inode)
+            });
+            if self.repair {
+                self.fs
+                    .inode_bitmap
+                    .assign(&self.block_device, inode as usize, reachable);
+            }
+        }
+        for pos in 0..self.used.len() {
+            let allocated = self.fs.data_bitmap.is_set(&self.block_device, pos);
+            let used = self.used[pos];
+            if allocated == used {
+                continue;
+            }
+            let block = self.data_start + pos as u32;
+            self.problems.push(if used {
+                FsckProblem::UnallocatedBlock(block)
+            } else {
+                FsckProblem::LeakedBlock(block)
+            });
+            if self.repair {
+                self.fs.data_bitmap.assign(&self.block_device, pos, used);
+            }
+        }
+    }
+}
+
+impl EasyFileSystem {
+    /// Walk every directory from the root and cross-check the bitmaps. With
+    /// `repair` the problems found are fixed in place, the ones reported are
+    /// those found before fixing.
+    pub fn fsck(efs: &Arc<SleepLock<Self>>, repair: bool) -> Vec<FsckProblem> {
+        let fs = efs.lock();
+        let (total_blocks, journal_blocks, inode_blocks, data_bitmap_blocks, data_area_blocks) =
+            get_block_cache(0, Arc::clone(&fs.block_device))
+                .lock()
+                .read(0, |super_block: &SuperBlock| {
+                    (
+                        super_block.total_blocks,
+                        super_block.journal_blocks,
+                        super_block.inode_bitmap_blocks + super_block.inode_area_blocks,
+                        super_block.data_bitmap_blocks,
+                        super_block.data_area_blocks,
+                    )
+                });
+        let data_start = fs.get_data_block_id(0);
+        if 1 + journal_blocks + inode_blocks + data_bitmap_blocks + data_area_blocks != total_blocks
+            || data_start + data_area_blocks != total_blocks
+        {
+            return vec![FsckProblem::BadSuperBlock];
+        }
+        let inodes_per_block = (BLOCK_SZ / core::mem::size_of::<DiskInode>()) as u32;
+        let inode_area_start = fs.get_disk_inode_pos(0).0;
+        let mut checker = Checker {
+            fs: &fs,
+            block_device: Arc::clone(&fs.block_device),
+            repair,
+            inode_count: (fs.inode_bitmap.maximum() as u32)
+                .min((data_start - data_bitmap_blocks - inode_area_start) * inodes_per_block),
+            data_start,
+            used: vec![false; data_area_blocks as usize],
+            reachable: BTreeSet::new(),
+            links: BTreeMap::new(),
+            problems: Vec::new(),
+        };
+        // the root is its own parent
+        checker.reachable.insert(0);
+        checker.check_parent(0, 0);
+        let mut dirs = VecDeque::from([0]);
+        while let Some(dir) = dirs.pop_front() {
+            dirs.extend(checker.check_dir(dir));
+        }
+        checker.check_links();
+        checker.check_bitmaps();
+        let problems = checker.problems;
+        if repair {
+            block_cache_sync_all();
+        }
+        problems
+    }
+}
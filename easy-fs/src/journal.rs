@@ -0,0 +1,213 @@
+use crate::BLOCK_SZ;
+use crate::block_dev::BlockDevice;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const JOURNAL_MAGIC: u32 = 0x4a524e4c;
+/// Blocks one transaction may log, as many as the header can name
+pub const JOURNAL_LOG_BLOCKS: usize = BLOCK_SZ / 4 - 3;
+/// The header followed by the log
+pub const JOURNAL_BLOCKS: u32 = 1 + JOURNAL_LOG_BLOCKS as u32;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+/// First block of the journal, a non-zero `count` with a matching checksum is
+/// the commit record of the transaction in the log
+#[repr(C)]
+struct JournalHeader {
+    magic: u32,
+    count: u32,
+    checksum: u32,
+    blocks: [u32; JOURNAL_LOG_BLOCKS],
+}
+
+impl JournalHeader {
+    fn empty() -> Self {
+        Self {
+            magic: JOURNAL_MAGIC,
+            count: 0,
+            checksum: 0,
+            blocks: [0; JOURNAL_LOG_BLOCKS],
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as usize as *const u8, BLOCK_SZ) }
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, BLOCK_SZ) }
+    }
+}
+
+/// FNV-1a over the logged block ids and contents
+fn checksum<'a>(blocks: impl Iterator<Item = (u32, &'a DataBlock)>) -> u32 {
+    let mut hash = 0x811c9dc5u32;
+    for (block_id, data) in blocks {
+        for byte in block_id.to_le_bytes().iter().chain(data.iter()) {
+            hash ^= *byte as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+    }
+    hash
+}
+
+struct JournalInner {
+    /// A transaction is open, writes are held back until it commits
+    active: bool,
+    /// Blocks written by the open transaction
+    pending: BTreeMap<usize, Box<DataBlock>>,
+}
+
+/// Write-ahead log in front of the disk. While a transaction is open the block
+/// caches write here instead of the disk, and the commit puts everything
+/// written in the log before any of it reaches its home block.
+pub struct Journal {
+    device: Arc<dyn BlockDevice>,
+    start_block: usize,
+    inner: Mutex<JournalInner>,
+}
+
+impl Journal {
+    pub fn new(device: Arc<dyn BlockDevice>, start_block: usize) -> Self {
+        Self {
+            device,
+            start_block,
+            inner: Mutex::new(JournalInner {
+                active: false,
+                pending: BTreeMap::new(),
+            }),
+        }
+    }
+
+    fn read_header(&self) -> JournalHeader {
+        let mut header = JournalHeader::empty();
+        self.device
+            .read_block(self.start_block, header.as_bytes_mut());
+        header
+    }
+
+    pub fn begin(&self) {
+        let mut inner = self.inner.lock();
+        assert!(!inner.active, "nested transaction");
+        inner.active = true;
+    }
+
+    /// Make every block written since `begin` durable as a whole. The block
+    /// caches of the journal must have been written back to it. The device
+    /// I/O runs without the journal locked, the file system lock keeps the
+    /// blocks of the transaction from being read before they are home.
+    pub fn commit(&self) {
+        let mut inner = self.inner.lock();
+        assert!(inner.active, "commit without a transaction");
+        inner.active = false;
+        let pending = core::mem::take(&mut inner.pending);
+        drop(inner);
+        if pending.is_empty() {
+            return;
+        }
+        assert!(
+            pending.len() <= JOURNAL_LOG_BLOCKS,
+            "transaction too large for the journal"
+        );
+        let mut header = JournalHeader::empty();
+        let mut log = Vec::with_capacity(pending.len() * BLOCK_SZ);
+        for (i, (&block_id, data)) in pending.iter().enumerate() {
+            log.extend_from_slice(data.as_ref());
+            header.blocks[i] = block_id as u32;
+        }
+        self.device.write_blocks(self.start_block + 1, &log);
+        header.count = pending.len() as u32;
+        header.checksum = checksum(pending.iter().map(|(&id, data)| (id as u32, data.as_ref())));
+        self.device.write_block(self.start_block, header.as_bytes());
+        // home blocks go out a run of consecutive ones at a time
+        let mut run: Vec<u8> = Vec::new();
+        let mut run_start = 0;
+        for (&block_id, data) in pending.iter() {
+            if !run.is_empty() && run_start + run.len() / BLOCK_SZ != block_id {
+                self.device.write_blocks(run_start, &run);
+                run.clear();
+            }
+            if run.is_empty() {
+                run_start = block_id;
+            }
+            run.extend_from_slice(data.as_ref());
+        }
+        self.device.write_blocks(run_start, &run);
+        self.device
+            .write_block(self.start_block, JournalHeader::empty().as_bytes());
+    }
+
+    /// Install a transaction that committed before a crash, returns how many
+    /// blocks were copied home
+    pub fn replay(&self) -> usize {
+        let header = self.read_header();
+        if header.magic != JOURNAL_MAGIC || header.count == 0 {
+            return 0;
+        }
+        let count = (header.count as usize).min(JOURNAL_LOG_BLOCKS);
+        let mut log: Vec<Box<DataBlock>> = Vec::new();
+        for i in 0..count {
+            let mut data = Box::new([0u8; BLOCK_SZ]);
+            self.device
+                .read_block(self.start_block + 1 + i, data.as_mut());
+            log.push(data);
+        }
+        let blocks = header.blocks[..count].iter().copied();
+        let committed = header.count as usize == count
+            && checksum(blocks.clone().zip(log.iter().map(|data| data.as_ref())))
+                == header.checksum;
+        if committed {
+            for (block_id, data) in blocks.zip(log.iter()) {
+                self.device.write_block(block_id as usize, data.as_ref());
+            }
+        }
+        self.device
+            .write_block(self.start_block, JournalHeader::empty().as_bytes());
+        if committed { count } else { 0 }
+    }
+}
+
+impl BlockDevice for Journal {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let inner = self.inner.lock();
+        if let Some(data) = inner.pending.get(&block_id) {
+            buf.copy_from_slice(data.as_ref());
+            return;
+        }
+        drop(inner);
+        self.device.read_block(block_id, buf);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut inner = self.inner.lock();
+        if inner.active {
+            let mut data = Box::new([0u8; BLOCK_SZ]);
+            data.copy_from_slice(buf);
+            inner.pending.insert(block_id, data);
+            return;
+        }
+        drop(inner);
+        self.device.write_block(block_id, buf);
+    }
+
+    /// From the device, then the blocks the open transaction wrote on top
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        let blocks = buf.len() / BLOCK_SZ;
+        let written: Vec<(usize, Box<DataBlock>)> = self
+            .inner
+            .lock()
+            .pending
+            .range(block_id..block_id + blocks)
+            .map(|(&id, data)| (id, data.clone()))
+            .collect();
+        self.device.read_blocks(block_id, buf);
+        for (id, data) in written {
+            let offset = (id - block_id) * BLOCK_SZ;
+            buf[offset..offset + BLOCK_SZ].copy_from_slice(data.as_ref());
+        }
+    }
+}
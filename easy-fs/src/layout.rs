@@ -1,14 +1,22 @@
 use alloc::{sync::Arc, vec::Vec};
+use core::ops::Range;
 
 use crate::{BLOCK_SZ, block_cache::get_block_cache, block_dev::BlockDevice};
-const EFS_MAGIC: u32 = 0x3b800001;
-const INODE_DIRECT_COUNT: usize = 28;
+const EFS_MAGIC: u32 = 0x3b800004;
+/// As many as keep a `DiskInode` at 128 bytes
+pub const INODE_DIRECT_COUNT: usize = 21;
 const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
-const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
+pub const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
 const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
-const INDIRECT1_BOUND: usize = INODE_INDIRECT1_COUNT + DIRECT_BOUND;
-const NAME_LENGTH_LIMIT: usize = 27;
-pub const DIRENT_SZ: usize = 32;
+pub const INDIRECT1_BOUND: usize = INODE_INDIRECT1_COUNT + DIRECT_BOUND;
+/// Largest size the direct and indirect blocks of an inode can map
+pub const MAX_FILE_SIZE: usize = (INDIRECT1_BOUND + INODE_INDIRECT2_COUNT) * BLOCK_SZ;
+/// An extent tree maps as far as the size field goes
+const MAX_EXTENT_FILE_SIZE: usize = u32::MAX as usize / BLOCK_SZ * BLOCK_SZ;
+/// `SuperBlock::features` bit for new inodes mapping through extent trees
+const FEATURE_EXTENTS: u32 = 1;
+/// `DiskInode::flags` bit for an extent tree in place of the block pointers
+const INODE_EXTENTS: u8 = 1;
 type IndirectBlock = [u32; BLOCK_SZ / 4];
 type DataBlock = [u8; BLOCK_SZ];
 
@@ -16,31 +24,57 @@ type DataBlock = [u8; BLOCK_SZ];
 pub struct SuperBlock {
     magic: u32,
     pub total_blocks: u32,
+    pub journal_blocks: u32,
     pub inode_bitmap_blocks: u32,
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    features: u32,
+}
+
+/// How inodes find their data blocks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockMapping {
+    /// Direct, indirect and doubly indirect block pointers
+    Indirect,
+    /// Runs of contiguous blocks, kept in a tree
+    Extents,
 }
 
 impl SuperBlock {
     pub fn initialize(
         &mut self,
         total_blocks: u32,
+        journal_blocks: u32,
         inode_bitmap_blocks: u32,
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
         data_area_blocks: u32,
+        mapping: BlockMapping,
     ) {
         *self = Self {
             magic: EFS_MAGIC,
             total_blocks,
+            journal_blocks,
             inode_bitmap_blocks,
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            features: match mapping {
+                BlockMapping::Indirect => 0,
+                BlockMapping::Extents => FEATURE_EXTENTS,
+            },
         };
     }
 
+    pub fn mapping(&self) -> BlockMapping {
+        if self.features & FEATURE_EXTENTS != 0 {
+            BlockMapping::Extents
+        } else {
+            BlockMapping::Indirect
+        }
+    }
+
     pub fn is_valid(&self) -> bool {
         self.magic == EFS_MAGIC
     }
@@ -53,23 +87,61 @@ pub struct DiskInode {
     pub indirect1: u32,
     pub indirect2: u32,
     pub parent: u32,
+    /// Directory entries naming the inode, plus one per subdirectory and one
+    /// for itself on directories
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Seconds since the epoch
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+    /// Permission bits
+    pub mode: u16,
     type_: DiskInodeType,
+    flags: u8,
 }
 
+const _: () = assert!(core::mem::size_of::<DiskInode>() == 128);
+
 #[derive(PartialEq)]
+#[repr(u8)]
 pub enum DiskInodeType {
     File,
     Directory,
+    /// The data is the path it points to
+    SymLink,
 }
 
 impl DiskInode {
-    pub fn initialize(&mut self, type_: DiskInodeType) {
+    pub fn initialize(&mut self, type_: DiskInodeType, now: u32, mapping: BlockMapping) {
         self.size = 0;
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        (self.mode, self.nlink) = match type_ {
+            DiskInodeType::File => (0o644, 1),
+            DiskInodeType::Directory => (0o755, 2),
+            DiskInodeType::SymLink => (0o777, 1),
+        };
         self.type_ = type_;
+        // all zero pointers double as an empty extent tree
+        self.flags = match mapping {
+            BlockMapping::Indirect => 0,
+            BlockMapping::Extents => INODE_EXTENTS,
+        };
         self.parent = 0;
+        self.uid = 0;
+        self.gid = 0;
+        self.atime = now;
+        self.mtime = now;
+        self.ctime = now;
+    }
+
+    /// The contents changed
+    pub fn touch(&mut self, now: u32) {
+        self.mtime = now;
+        self.ctime = now;
     }
 
     pub fn is_dir(&self) -> bool {
@@ -80,29 +152,76 @@ impl DiskInode {
         self.type_ == DiskInodeType::File
     }
 
+    pub fn is_symlink(&self) -> bool {
+        self.type_ == DiskInodeType::SymLink
+    }
+
+    pub fn uses_extents(&self) -> bool {
+        self.flags & INODE_EXTENTS != 0
+    }
+
+    /// Largest size the mapping of the inode allows
+    pub fn max_size(&self) -> usize {
+        if self.uses_extents() {
+            MAX_EXTENT_FILE_SIZE
+        } else {
+            MAX_FILE_SIZE
+        }
+    }
+
+    /// 0 for a hole
     pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        if self.uses_extents() {
+            return self.extent_block_id(inner_id, block_device);
+        }
         let inner_id = inner_id as usize;
         if inner_id < INODE_DIRECT_COUNT {
             self.direct[inner_id]
         } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[inner_id - INODE_DIRECT_COUNT]
-                })
+            read_pointer(self.indirect1, inner_id - INODE_DIRECT_COUNT, block_device)
         } else {
             let last = inner_id - INDIRECT1_BOUND;
-            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect2: &IndirectBlock| {
-                    indirect2[last / INODE_INDIRECT1_COUNT]
-                });
-            get_block_cache(indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[last % INODE_INDIRECT1_COUNT]
-                })
+            let indirect1 =
+                read_pointer(self.indirect2, last / INODE_INDIRECT1_COUNT, block_device);
+            read_pointer(indirect1, last % INODE_INDIRECT1_COUNT, block_device)
+        }
+    }
+
+    /// Point `inner_id` at a new block, allocating the indirect blocks
+    /// missing on the way
+    fn map_indirect(
+        &mut self,
+        inner_id: u32,
+        goal: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut impl FnMut(u32) -> u32,
+    ) -> u32 {
+        let inner_id = inner_id as usize;
+        if inner_id < INODE_DIRECT_COUNT {
+            self.direct[inner_id] = alloc(goal);
+            return self.direct[inner_id];
         }
+        let (table, index) = if inner_id < INDIRECT1_BOUND {
+            if self.indirect1 == 0 {
+                self.indirect1 = alloc(goal);
+            }
+            (self.indirect1, inner_id - INODE_DIRECT_COUNT)
+        } else {
+            if self.indirect2 == 0 {
+                self.indirect2 = alloc(goal);
+            }
+            let last = inner_id - INDIRECT1_BOUND;
+            let a = last / INODE_INDIRECT1_COUNT;
+            let mut indirect1 = read_pointer(self.indirect2, a, block_device);
+            if indirect1 == 0 {
+                indirect1 = alloc(goal);
+                write_pointer(self.indirect2, a, indirect1, block_device);
+            }
+            (indirect1, last % INODE_INDIRECT1_COUNT)
+        };
+        let block = alloc(goal);
+        write_pointer(table, index, block, block_device);
+        block
     }
 
     pub fn data_blocks(&self) -> u32 {
@@ -113,154 +232,76 @@ impl DiskInode {
         (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
     }
 
-    pub fn total_blocks(size: u32) -> u32 {
-        let data_blocks = Self::_data_blocks(size) as usize;
-        let mut total = data_blocks;
-        if data_blocks > INODE_DIRECT_COUNT {
-            total += 1;
+    /// Data blocks plus the blocks that map them, holes taking none
+    pub fn allocated_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        if self.uses_extents() {
+            return self.extent_blocks(block_device);
         }
-        if data_blocks > INDIRECT1_BOUND {
+        let mapped = |table: u32| {
+            get_block_cache(table as usize, Arc::clone(block_device))
+                .lock()
+                .read(0, |indirect_block: &IndirectBlock| {
+                    indirect_block.iter().filter(|block| **block != 0).count() as u32
+                })
+        };
+        let mut total = self.direct.iter().filter(|block| **block != 0).count() as u32;
+        if self.indirect1 != 0 {
+            total += 1 + mapped(self.indirect1);
+        }
+        if self.indirect2 != 0 {
             total += 1;
-            total +=
-                (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+            for a in 0..INODE_INDIRECT1_COUNT {
+                let indirect1 = read_pointer(self.indirect2, a, block_device);
+                if indirect1 != 0 {
+                    total += 1 + mapped(indirect1);
+                }
+            }
         }
-        total as u32
-    }
-
-    pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-        assert!(new_size >= self.size);
-        Self::total_blocks(new_size) - Self::total_blocks(self.size)
+        total
     }
 
+    /// Grow to `new_size` with every new block mapped
     pub fn increase_size(
         &mut self,
         new_size: u32,
-        new_blocks: Vec<u32>,
         block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut impl FnMut(u32) -> u32,
     ) {
-        let mut current_blocks = self.data_blocks();
+        let current_blocks = self.data_blocks();
         self.size = new_size;
-        let mut total_blocks = self.data_blocks();
-        let mut new_blocks = new_blocks.into_iter();
-        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
-            self.direct[current_blocks as usize] = new_blocks.next().unwrap();
-            current_blocks += 1;
-        }
-        if total_blocks > INODE_DIRECT_COUNT as u32 {
-            if current_blocks == INODE_DIRECT_COUNT as u32 {
-                self.indirect1 = new_blocks.next().unwrap();
-            }
-            current_blocks -= INODE_DIRECT_COUNT as u32;
-            total_blocks -= INODE_DIRECT_COUNT as u32;
-        } else {
-            return;
-        }
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect_block: &mut IndirectBlock| {
-                while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
-                    indirect_block[current_blocks as usize] = new_blocks.next().unwrap();
-                    current_blocks += 1;
-                }
-            });
-        if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-            if current_blocks == INODE_INDIRECT1_COUNT as u32 {
-                self.indirect2 = new_blocks.next().unwrap();
+        self.map_blocks(current_blocks..self.data_blocks(), block_device, alloc);
+    }
+
+    /// Give each hole among the data blocks in `range` a block. `alloc` is
+    /// given the block that would continue the data, 0 if there is none, and
+    /// should prefer it.
+    pub fn map_blocks(
+        &mut self,
+        range: Range<u32>,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut impl FnMut(u32) -> u32,
+    ) {
+        let mut goal = match range.start {
+            0 => 0,
+            n => match self.get_block_id(n - 1, block_device) {
+                0 => 0,
+                block => block + 1,
+            },
+        };
+        for inner_id in range {
+            let mut block = self.get_block_id(inner_id, block_device);
+            if block == 0 && self.uses_extents() {
+                block = alloc(goal);
+                self.extent_insert(inner_id, block, block_device, alloc);
+            } else if block == 0 {
+                block = self.map_indirect(inner_id, goal, block_device, alloc);
             }
-            current_blocks -= INODE_INDIRECT1_COUNT as u32;
-            total_blocks -= INODE_INDIRECT1_COUNT as u32;
-        } else {
-            return;
+            goal = block + 1;
         }
-        let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
-        let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT;
-        let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
-        let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                    if b0 == 0 {
-                        indirect2[a0] = new_blocks.next().unwrap();
-                    }
-                    get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect_block: &mut IndirectBlock| {
-                            indirect_block[b0] = new_blocks.next().unwrap();
-                        });
-                    b0 += 1;
-                    if b0 == INODE_INDIRECT1_COUNT {
-                        b0 = 0;
-                        a0 += 1;
-                    }
-                }
-            })
     }
 
     pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
-        let mut v: Vec<u32> = Vec::new();
-        let mut data_blocks = self.data_blocks() as usize;
-        self.size = 0;
-        let mut current_blocks = 0usize;
-        while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-            v.push(self.direct[current_blocks]);
-            self.direct[current_blocks] = 0;
-            current_blocks += 1;
-        }
-
-        if data_blocks > INODE_DIRECT_COUNT {
-            v.push(self.indirect1);
-            data_blocks -= INODE_DIRECT_COUNT;
-            current_blocks = 0;
-        } else {
-            return v;
-        }
-
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect_block: &mut IndirectBlock| {
-                while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-                    v.push(indirect_block[current_blocks]);
-                    indirect_block[current_blocks] = 0;
-                    current_blocks += 1;
-                }
-            });
-        self.indirect1 = 0;
-        if data_blocks > INODE_INDIRECT1_COUNT {
-            v.push(self.indirect2);
-            data_blocks -= INODE_INDIRECT1_COUNT;
-        } else {
-            return v;
-        }
-        assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-        let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-        let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                for entry in indirect2.iter_mut().take(a1) {
-                    v.push(*entry);
-                    get_block_cache(*entry as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter() {
-                                v.push(*entry);
-                            }
-                        });
-                }
-                if b1 > 0 {
-                    v.push(indirect2[a1]);
-                    get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter().take(b1) {
-                                v.push(*entry);
-                            }
-                        });
-                }
-            });
-        self.indirect2 = 0;
-        v
+        self.decrease_size(0, block_device)
     }
 
     pub fn decrease_size(
@@ -268,172 +309,79 @@ impl DiskInode {
         new_size: u32,
         block_device: &Arc<dyn BlockDevice>,
     ) -> Vec<u32> {
-        // Calculate old and new block counts
-        let old_blocks = self.data_blocks();
+        assert!(new_size <= self.size);
+        let old_blocks = self.data_blocks() as usize;
         self.size = new_size;
-        let new_blocks = self.data_blocks();
-
-        if new_blocks >= old_blocks {
-            return Vec::new();
+        let new_blocks = self.data_blocks() as usize;
+        if self.uses_extents() {
+            return self.extent_truncate(new_blocks as u32, block_device);
         }
-
         let mut freed_blocks = Vec::new();
-        let mut current_blocks = new_blocks as usize;
-        let mut total_blocks = old_blocks as usize;
-
-        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT) {
-            freed_blocks.push(self.direct[current_blocks]);
-            self.direct[current_blocks] = 0;
-            current_blocks += 1;
-        }
 
-        if total_blocks > INODE_DIRECT_COUNT {
-            if current_blocks == INODE_DIRECT_COUNT {
-                if new_blocks <= INODE_DIRECT_COUNT as u32 {
-                    freed_blocks.push(self.indirect1);
-                    get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect_block: &mut IndirectBlock| {
-                            for block in indirect_block
-                                .iter()
-                                .take(total_blocks - INODE_DIRECT_COUNT)
-                            {
-                                freed_blocks.push(*block);
-                            }
-                        });
-                    self.indirect1 = 0;
-                    return freed_blocks;
-                }
-                current_blocks = 0;
-                total_blocks -= INODE_DIRECT_COUNT;
-            } else {
-                return freed_blocks;
-            }
-        } else {
-            return freed_blocks;
+        for block in self
+            .direct
+            .iter_mut()
+            .take(old_blocks.min(DIRECT_BOUND))
+            .skip(new_blocks)
+            .filter(|block| **block != 0)
+        {
+            freed_blocks.push(*block);
+            *block = 0;
         }
 
-        if total_blocks > 0 {
+        if old_blocks > DIRECT_BOUND && self.indirect1 != 0 {
+            let from = new_blocks.max(DIRECT_BOUND) - DIRECT_BOUND;
+            let to = old_blocks.min(INDIRECT1_BOUND) - DIRECT_BOUND;
             get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
                 .lock()
-                .modify(0, |indirect_block: &mut IndirectBlock| {
-                    while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT) {
-                        freed_blocks.push(indirect_block[current_blocks]);
-                        indirect_block[current_blocks] = 0;
-                        current_blocks += 1;
+                .modify(0, |indirect1: &mut IndirectBlock| {
+                    for block in indirect1.iter_mut().take(to).skip(from) {
+                        if *block != 0 {
+                            freed_blocks.push(*block);
+                            *block = 0;
+                        }
                     }
                 });
-
-            if total_blocks > INODE_INDIRECT1_COUNT {
-                if current_blocks == INODE_INDIRECT1_COUNT {
-                    if new_blocks <= (INODE_DIRECT_COUNT + INODE_INDIRECT1_COUNT) as u32 {
-                        freed_blocks.push(self.indirect2);
-                        let a1 = total_blocks / INODE_INDIRECT1_COUNT;
-                        let b1 = total_blocks % INODE_INDIRECT1_COUNT;
-
-                        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-                            .lock()
-                            .modify(0, |indirect2: &mut IndirectBlock| {
-                                for entry in indirect2.iter().take(a1) {
-                                    freed_blocks.push(*entry);
-                                    get_block_cache(*entry as usize, Arc::clone(block_device))
-                                        .lock()
-                                        .modify(0, |indirect1: &mut IndirectBlock| {
-                                            for block in indirect1.iter() {
-                                                freed_blocks.push(*block);
-                                            }
-                                        });
-                                }
-                                if b1 > 0 {
-                                    freed_blocks.push(indirect2[a1]);
-                                    get_block_cache(
-                                        indirect2[a1] as usize,
-                                        Arc::clone(block_device),
-                                    )
-                                    .lock()
-                                    .modify(
-                                        0,
-                                        |indirect1: &mut IndirectBlock| {
-                                            for block in indirect1.iter().take(b1) {
-                                                freed_blocks.push(*block);
-                                            }
-                                        },
-                                    );
-                                }
-                            });
-                        self.indirect2 = 0;
-                        return freed_blocks;
-                    }
-                    current_blocks = 0;
-                    total_blocks -= INODE_INDIRECT1_COUNT;
-                } else {
-                    return freed_blocks;
-                }
-            } else {
-                return freed_blocks;
+            if new_blocks <= DIRECT_BOUND {
+                freed_blocks.push(self.indirect1);
+                self.indirect1 = 0;
             }
-        } else {
-            return freed_blocks;
         }
 
-        if total_blocks > 0 {
-            let a0 = current_blocks / INODE_INDIRECT1_COUNT;
-            let b0 = current_blocks % INODE_INDIRECT1_COUNT;
-            let a1 = total_blocks / INODE_INDIRECT1_COUNT;
-            let b1 = total_blocks % INODE_INDIRECT1_COUNT;
-
+        if old_blocks > INDIRECT1_BOUND && self.indirect2 != 0 {
+            // blocks [from, to) of the doubly indirect range go, along with
+            // every indirect block left empty
+            let from = new_blocks.max(INDIRECT1_BOUND) - INDIRECT1_BOUND;
+            let to = old_blocks - INDIRECT1_BOUND;
             get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
                 .lock()
                 .modify(0, |indirect2: &mut IndirectBlock| {
-                    if a0 < a1 {
-                        for entry in indirect2.iter_mut().skip(a0 + 1).take(a1 - a0 - 1) {
-                            freed_blocks.push(*entry);
-                            get_block_cache(*entry as usize, Arc::clone(block_device))
-                                .lock()
-                                .modify(0, |indirect1: &mut IndirectBlock| {
-                                    for block in indirect1.iter() {
-                                        freed_blocks.push(*block);
-                                    }
-                                });
-                            *entry = 0;
+                    let first = from / INODE_INDIRECT1_COUNT;
+                    let last = to.div_ceil(INODE_INDIRECT1_COUNT);
+                    for (a, entry) in indirect2.iter_mut().enumerate().take(last).skip(first) {
+                        if *entry == 0 {
+                            continue;
                         }
-
-                        if b0 > 0 {
-                            freed_blocks.push(indirect2[a0]);
-                            get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
-                                .lock()
-                                .modify(0, |indirect1: &mut IndirectBlock| {
-                                    for block in indirect1.iter().skip(b0) {
-                                        freed_blocks.push(*block);
-                                    }
-                                });
-                            indirect2[a0] = 0;
-                        }
-
-                        if b1 > 0 {
-                            freed_blocks.push(indirect2[a1]);
-                            get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
-                                .lock()
-                                .modify(0, |indirect1: &mut IndirectBlock| {
-                                    for block in indirect1.iter().skip(b1) {
-                                        freed_blocks.push(*block);
-                                    }
-                                });
-                            indirect2[a1] = 0;
-                        }
-                    } else if b0 < b1 {
-                        get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
+                        let base = a * INODE_INDIRECT1_COUNT;
+                        let lo = from.max(base) - base;
+                        let hi = to.min(base + INODE_INDIRECT1_COUNT) - base;
+                        get_block_cache(*entry as usize, Arc::clone(block_device))
                             .lock()
                             .modify(0, |indirect1: &mut IndirectBlock| {
-                                for block in indirect1.iter_mut().skip(b0).take(b1 - b0) {
-                                    freed_blocks.push(*block);
-                                    *block = 0;
+                                for block in indirect1.iter_mut().take(hi).skip(lo) {
+                                    if *block != 0 {
+                                        freed_blocks.push(*block);
+                                        *block = 0;
+                                    }
                                 }
                             });
+                        if lo == 0 {
+                            freed_blocks.push(*entry);
+                            *entry = 0;
+                        }
                     }
                 });
-
-            if new_blocks <= (INODE_DIRECT_COUNT + INODE_INDIRECT1_COUNT) as u32 {
+            if new_blocks <= INDIRECT1_BOUND {
                 freed_blocks.push(self.indirect2);
                 self.indirect2 = 0;
             }
@@ -449,7 +397,7 @@ impl DiskInode {
         block_device: &Arc<dyn BlockDevice>,
     ) -> usize {
         let mut start = offset;
-        let end = (offset + buf.len()).min(self.size as usize);
+        let end = offset.saturating_add(buf.len()).min(self.size as usize);
         if start >= end {
             return 0;
         }
@@ -460,15 +408,15 @@ impl DiskInode {
             end_current_block = end_current_block.min(end);
             let block_read_size = end_current_block - start;
             let dst = &mut buf[read_size..read_size + block_read_size];
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .read(0, |data_block: &DataBlock| {
-                let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
-                dst.copy_from_slice(src);
-            });
+            match self.get_block_id(start_block as u32, block_device) {
+                0 => dst.fill(0),
+                block_id => get_block_cache(block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .read(0, |data_block: &DataBlock| {
+                        let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
+                        dst.copy_from_slice(src);
+                    }),
+            }
             read_size += block_read_size;
             if end_current_block >= end {
                 break;
@@ -486,24 +434,27 @@ impl DiskInode {
         block_device: &Arc<dyn BlockDevice>,
     ) -> usize {
         let mut start = offset;
-        let end = (offset + buf.len()).min(self.size as usize);
+        let end = offset.saturating_add(buf.len()).min(self.size as usize);
         assert!(start <= end);
+        if start == end {
+            return 0;
+        }
         let mut start_block = start / BLOCK_SZ;
         let mut write_size = 0usize;
         loop {
             let mut end_current_block = (start / BLOCK_SZ + 1) * BLOCK_SZ;
             end_current_block = end_current_block.min(end);
             let block_write_size = end_current_block - start;
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
-                let src = &buf[write_size..write_size + block_write_size];
-                let dst = &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
-                dst.copy_from_slice(src);
-            });
+            let block_id = self.get_block_id(start_block as u32, block_device);
+            assert_ne!(block_id, 0, "writing to a hole");
+            get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .modify(0, |data_block: &mut DataBlock| {
+                    let src = &buf[write_size..write_size + block_write_size];
+                    let dst =
+                        &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
+                    dst.copy_from_slice(src);
+                });
             write_size += block_write_size;
             if end_current_block == end {
                 break;
@@ -515,43 +466,20 @@ impl DiskInode {
     }
 }
 
-#[repr(C)]
-pub struct DirEntry {
-    name: [u8; NAME_LENGTH_LIMIT + 1],
-    inode_number: u32,
-}
-
-impl DirEntry {
-    pub fn empty() -> Self {
-        DirEntry {
-            name: [0; NAME_LENGTH_LIMIT + 1],
-            inode_number: 0,
-        }
-    }
-
-    pub fn new(name: &str, inode_number: u32) -> Self {
-        let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
-        bytes[..name.len()].copy_from_slice(name.as_bytes());
-        Self {
-            name: bytes,
-            inode_number,
-        }
-    }
-
-    pub fn as_bytes(&self) -> &[u8] {
-        unsafe { core::slice::from_raw_parts(self as *const _ as usize as *const u8, DIRENT_SZ) }
-    }
-
-    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
-        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, DIRENT_SZ) }
-    }
-
-    pub fn name(&self) -> &str {
-        let len = (0usize..).find(|i| self.name[*i] == 0).unwrap();
-        core::str::from_utf8(&self.name[..len]).unwrap()
+/// Entry `index` of an indirect block, 0 when the block itself is missing
+fn read_pointer(table: u32, index: usize, block_device: &Arc<dyn BlockDevice>) -> u32 {
+    if table == 0 {
+        return 0;
     }
+    get_block_cache(table as usize, Arc::clone(block_device))
+        .lock()
+        .read(0, |indirect_block: &IndirectBlock| indirect_block[index])
+}
 
-    pub fn inode_number(&self) -> u32 {
-        self.inode_number
-    }
+fn write_pointer(table: u32, index: usize, block: u32, block_device: &Arc<dyn BlockDevice>) {
+    get_block_cache(table as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |indirect_block: &mut IndirectBlock| {
+            indirect_block[index] = block
+        });
 }
@@ -3,13 +3,21 @@ extern crate alloc;
 mod bitmap;
 mod block_cache;
 mod block_dev;
+mod dir;
 mod efs;
+mod extent;
+mod fsck;
+mod journal;
 mod layout;
+mod sleep_lock;
 mod vfs;
 
 pub const BLOCK_SZ: usize = 512;
 
 pub use crate::block_cache::block_cache_sync_all;
 pub use crate::block_dev::BlockDevice;
-pub use crate::efs::EasyFileSystem;
-pub use crate::vfs::Inode;
+pub use crate::efs::{EasyFileSystem, set_clock};
+pub use crate::fsck::FsckProblem;
+pub use crate::layout::{BlockMapping, MAX_FILE_SIZE};
+pub use crate::sleep_lock::{SleepLock, SleepLockGuard, set_lock_hooks};
+pub use crate::vfs::{Inode, Stat, StatMode};
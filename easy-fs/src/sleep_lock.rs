@@ -0,0 +1,95 @@
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and someone may be waiting for it
+const CONTENDED: u32 = 2;
+
+fn spin(_state: &AtomicU32, _expected: u32) {
+    spin_loop();
+}
+
+fn no_wake(_state: &AtomicU32) {}
+
+type Wait = fn(&AtomicU32, u32);
+type Wake = fn(&AtomicU32);
+
+static HOOKS: Mutex<(Wait, Wake)> = Mutex::new((spin, no_wake));
+
+/// How a caller waits for a lock and how it is woken. `wait(state, expected)`
+/// may return early but must not sleep once `state` differs from `expected`,
+/// `wake(state)` wakes at least one caller waiting on `state`. Without them
+/// waiters spin.
+pub fn set_lock_hooks(wait: Wait, wake: Wake) {
+    *HOOKS.lock() = (wait, wake);
+}
+
+/// A lock whose waiters may sleep, for everything held over disk I/O. The
+/// lock word is 0 when free, 1 when taken and 2 when taken with waiters.
+pub struct SleepLock<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SleepLock<T> {}
+unsafe impl<T: Send> Send for SleepLock<T> {}
+
+impl<T> SleepLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> SleepLockGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            let wait = HOOKS.lock().0;
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                wait(&self.state, CONTENDED);
+            }
+        }
+        SleepLockGuard { lock: self }
+    }
+
+    pub fn try_lock(&self) -> Option<SleepLockGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SleepLockGuard { lock: self })
+    }
+}
+
+pub struct SleepLockGuard<'a, T> {
+    lock: &'a SleepLock<T>,
+}
+
+impl<T> Deref for SleepLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SleepLockGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.lock.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            let wake = HOOKS.lock().1;
+            wake(&self.lock.state);
+        }
+    }
+}
@@ -1,17 +1,59 @@
 use crate::layout::DiskInodeType;
+use crate::sleep_lock::{SleepLock, SleepLockGuard};
 use crate::{
+    BLOCK_SZ,
     block_cache::get_block_cache,
     block_dev::BlockDevice,
-    efs::EasyFileSystem,
-    layout::{DIRENT_SZ, DirEntry, DiskInode},
+    efs::{EasyFileSystem, now},
+    layout::DiskInode,
 };
-use alloc::{format, string::String, string::ToString, sync::Arc, vec::Vec};
-use spin::{Mutex, MutexGuard};
+use alloc::{format, string::String, string::ToString, sync::Arc, vec, vec::Vec};
+
+/// File type bits of `Stat::mode`, the low bits are permissions
+pub struct StatMode;
+
+impl StatMode {
+    pub const DIR: u32 = 0o040000;
+    pub const FILE: u32 = 0o100000;
+    pub const SYMLINK: u32 = 0o120000;
+}
+
+/// Metadata of an inode, laid out for handing to user programs
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stat {
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// Blocks allocated, index blocks included
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+/// Data blocks written per transaction by `write_at`
+const WRITE_CHUNK_BLOCKS: usize = 32;
+/// Symbolic links followed in one lookup before giving up on it as a loop
+const SYMLOOP_MAX: usize = 8;
+
+/// Split a path into its directory part and the last name
+fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(0) => ("/", &path[1..]),
+        Some(pos) => (&path[..pos], &path[pos + 1..]),
+        None => ("", path),
+    }
+}
 
 pub struct Inode {
     block_id: usize,
     block_offset: usize,
-    fs: Arc<Mutex<EasyFileSystem>>,
+    fs: Arc<SleepLock<EasyFileSystem>>,
     block_device: Arc<dyn BlockDevice>,
 }
 
@@ -19,7 +61,7 @@ impl Inode {
     pub fn new(
         block_id: u32,
         block_offset: usize,
-        fs: Arc<Mutex<EasyFileSystem>>,
+        fs: Arc<SleepLock<EasyFileSystem>>,
         block_device: Arc<dyn BlockDevice>,
     ) -> Self {
         Self {
@@ -64,20 +106,41 @@ impl Inode {
         self.read_dist_inode(|dist_inode| dist_inode.is_file())
     }
 
+    pub fn is_symlink(&self) -> bool {
+        self.read_dist_inode(|dist_inode| dist_inode.is_symlink())
+    }
+
+    pub fn size(&self) -> usize {
+        self.read_dist_inode(|dist_inode| dist_inode.size as usize)
+    }
+
+    /// Largest size the file can grow to
+    pub fn max_size(&self) -> usize {
+        self.read_dist_inode(|disk_inode| disk_inode.max_size())
+    }
+
     fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
         assert!(disk_inode.is_dir());
-        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-        let mut dirent = DirEntry::empty();
-        for i in 0..file_count {
-            assert_eq!(
-                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device),
-                DIRENT_SZ
-            );
-            if dirent.name() == name {
-                return Some(dirent.inode_number() as u32);
+        disk_inode.find_entry(name, &self.block_device)
+    }
+
+    /// Add an entry for `inode_id`, false if the name is too long or the
+    /// directory is full
+    fn add_entry(
+        &self,
+        name: &str,
+        inode_id: u32,
+        fs: &mut SleepLockGuard<EasyFileSystem>,
+    ) -> bool {
+        self.modify_dist_inode(|root_inode| {
+            let added = root_inode.insert_entry(name, inode_id, &self.block_device, &mut |goal| {
+                fs.alloc_data_near(goal)
+            });
+            if added {
+                root_inode.touch(now());
             }
-        }
-        None
+            added
+        })
     }
 
     pub fn parent(&self) -> Arc<Inode> {
@@ -95,10 +158,47 @@ impl Inode {
     pub fn edit_parent(&self, upper_inode: Arc<Inode>) {
         let fs = self.fs.lock();
         let inode_id = upper_inode.inode_id(&fs);
-        self.modify_dist_inode(|disk_inode| disk_inode.parent = inode_id);
+        fs.begin_transaction();
+        self.modify_dist_inode(|disk_inode| {
+            disk_inode.parent = inode_id;
+            disk_inode.ctime = now();
+        });
+        fs.commit_transaction();
+    }
+
+    /// Absolute path of a directory, found by climbing the parent links
+    pub fn path(self: &Arc<Self>) -> Option<String> {
+        let mut names = Vec::new();
+        let mut inode = self.clone();
+        loop {
+            let parent = inode.parent();
+            if Inode::same_inode(&parent, &inode) {
+                break;
+            }
+            names.push(parent.name_of(inode.self_id())?);
+            inode = parent;
+        }
+        if names.is_empty() {
+            return Some(String::from("/"));
+        }
+        Some(
+            names
+                .iter()
+                .rev()
+                .map(|name| format!("/{}", name))
+                .collect(),
+        )
     }
 
-    pub fn inode_id(&self, fs: &MutexGuard<EasyFileSystem>) -> u32 {
+    /// The name an entry of this directory gives to `inode_id`
+    fn name_of(&self, inode_id: u32) -> Option<String> {
+        self.entries()
+            .into_iter()
+            .find(|(_, id)| *id == inode_id)
+            .map(|(name, _)| name)
+    }
+
+    pub fn inode_id(&self, fs: &SleepLockGuard<EasyFileSystem>) -> u32 {
         fs.get_inode_id(self.block_id as u32, self.block_offset)
     }
 
@@ -109,20 +209,13 @@ impl Inode {
     }
 
     pub fn ls(&self) -> Vec<String> {
+        self.entries().into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// Names and inode ids of the entries of a directory, in order
+    pub fn entries(&self) -> Vec<(String, u32)> {
         let _fs = self.fs.lock();
-        self.read_dist_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut v: Vec<String> = Vec::new();
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device),
-                    DIRENT_SZ
-                );
-                v.push(String::from(dirent.name()));
-            }
-            v
-        })
+        self.read_dist_inode(|disk_inode| disk_inode.entries(&self.block_device))
     }
 
     pub fn create(self: &Arc<Self>, name: &str) -> Option<Arc<Inode>> {
@@ -133,24 +226,22 @@ impl Inode {
         {
             return None;
         }
+        fs.begin_transaction();
+        let now = now();
         let new_inode_id = fs.alloc_inode();
+        if !self.add_entry(name, new_inode_id, &mut fs) {
+            fs.dealloc_inode(new_inode_id);
+            fs.commit_transaction();
+            return None;
+        }
+        let parent_id = self.inode_id(&fs);
         let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
         get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
+                new_inode.initialize(DiskInodeType::File, now, fs.mapping());
+                new_inode.parent = parent_id;
             });
-        self.modify_dist_inode(|root_inode| {
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
-        });
 
         let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
         let new_inode = Arc::new(Self::new(
@@ -159,8 +250,7 @@ impl Inode {
             self.fs.clone(),
             self.block_device.clone(),
         ));
-        drop(fs);
-        new_inode.edit_parent(self.clone());
+        fs.commit_transaction();
         Some(new_inode)
     }
 
@@ -175,24 +265,23 @@ impl Inode {
         {
             return None;
         }
+        fs.begin_transaction();
+        let now = now();
         let new_inode_id = fs.alloc_inode();
+        if !self.add_entry(name, new_inode_id, &mut fs) {
+            fs.dealloc_inode(new_inode_id);
+            fs.commit_transaction();
+            return None;
+        }
+        let parent_id = self.inode_id(&fs);
         let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
         get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::Directory);
+                new_inode.initialize(DiskInodeType::Directory, now, fs.mapping());
+                new_inode.parent = parent_id;
             });
-        self.modify_dist_inode(|root_inode| {
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
-        });
+        self.modify_dist_inode(|root_inode| root_inode.nlink += 1);
 
         let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
         let new_inode = Arc::new(Self::new(
@@ -201,63 +290,216 @@ impl Inode {
             self.fs.clone(),
             self.block_device.clone(),
         ));
-        drop(fs);
-        new_inode.edit_parent(self.clone());
+        fs.commit_transaction();
         Some(new_inode)
     }
 
     pub fn cd(self: &Arc<Self>, path: &str) -> Option<Arc<Inode>> {
+        self.lookup(path, true)
+    }
+
+    /// Resolve `path` from this directory. Symbolic links met on the way are
+    /// followed, the last one only with `follow`.
+    pub fn lookup(self: &Arc<Self>, path: &str, follow: bool) -> Option<Arc<Inode>> {
+        let mut links_left = SYMLOOP_MAX;
+        self.walk(path, follow, &mut links_left)
+    }
+
+    fn walk(
+        self: &Arc<Self>,
+        path: &str,
+        follow: bool,
+        links_left: &mut usize,
+    ) -> Option<Arc<Inode>> {
         let mut inode = if path.starts_with('/') {
             Arc::new(EasyFileSystem::root_inode(&self.fs))
         } else {
             self.clone()
         };
-        for token in path.split('/') {
-            if token == "." || token == "" {
-                continue;
+        let mut tokens = path
+            .split('/')
+            .filter(|token| *token != "." && !token.is_empty())
+            .peekable();
+        while let Some(token) = tokens.next() {
+            if !inode.is_dir() {
+                return None;
             }
             if token == ".." {
                 inode = inode.parent();
                 continue;
             }
-            inode = match inode.find(token) {
-                Some(next_inode) => next_inode,
-                None => return None,
-            };
+            let next_inode = inode.find(token)?;
+            if next_inode.is_symlink() && (follow || tokens.peek().is_some()) {
+                if *links_left == 0 {
+                    return None;
+                }
+                *links_left -= 1;
+                // relative targets start from the directory holding the link
+                let target = next_inode.readlink()?;
+                inode = inode.walk(&target, true, links_left)?;
+            } else {
+                inode = next_inode;
+            }
         }
         Some(inode)
     }
 
-    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+    /// Resolve the directory `path` is in, along with the last name in it
+    pub fn lookup_parent<'a>(self: &Arc<Self>, path: &'a str) -> Option<(Arc<Inode>, &'a str)> {
+        let (dir, name) = split_path(path);
+        let dir = self.lookup(dir, true)?;
+        if !dir.is_dir() || name.is_empty() || name == "." || name == ".." {
+            return None;
+        }
+        Some((dir, name))
+    }
+
+    /// Add another entry for a file or symbolic link
+    pub fn link(self: &Arc<Self>, name: &str, inode: &Arc<Inode>) -> bool {
         let mut fs = self.fs.lock();
-        self.modify_dist_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
+        if inode.read_dist_inode(|disk_inode| disk_inode.is_dir())
+            || self
+                .read_dist_inode(|root_inode| self.find_inode_id(name, root_inode))
+                .is_some()
+        {
+            return false;
+        }
+        fs.begin_transaction();
+        let now = now();
+        let inode_id = inode.inode_id(&fs);
+        if !self.add_entry(name, inode_id, &mut fs) {
+            fs.commit_transaction();
+            return false;
+        }
+        inode.modify_dist_inode(|disk_inode| {
+            disk_inode.nlink += 1;
+            disk_inode.ctime = now;
+        });
+        fs.commit_transaction();
+        true
+    }
+
+    pub fn symlink(self: &Arc<Self>, name: &str, target: &str) -> Option<Arc<Inode>> {
+        let mut fs = self.fs.lock();
+        if target.is_empty()
+            || self
+                .read_dist_inode(|root_inode| self.find_inode_id(name, root_inode))
+                .is_some()
+        {
+            return None;
+        }
+        fs.begin_transaction();
+        let now = now();
+        let new_inode_id = fs.alloc_inode();
+        if !self.add_entry(name, new_inode_id, &mut fs) {
+            fs.dealloc_inode(new_inode_id);
+            fs.commit_transaction();
+            return None;
+        }
+        let parent_id = self.inode_id(&fs);
+        let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
+        let new_inode = Arc::new(Self::new(
+            block_id,
+            block_offset,
+            self.fs.clone(),
+            self.block_device.clone(),
+        ));
+        new_inode.modify_dist_inode(|disk_inode| {
+            disk_inode.initialize(DiskInodeType::SymLink, now, fs.mapping());
+            disk_inode.parent = parent_id;
+            self.map_range(0, target.len(), disk_inode, &mut fs);
+            disk_inode.write_at(0, target.as_bytes(), &self.block_device);
+        });
+        fs.commit_transaction();
+        Some(new_inode)
+    }
+
+    /// The path a symbolic link points to
+    pub fn readlink(&self) -> Option<String> {
+        let _fs = self.fs.lock();
+        self.read_dist_inode(|disk_inode| {
+            if !disk_inode.is_symlink() {
+                return None;
+            }
+            let mut buf = vec![0u8; disk_inode.size as usize];
+            disk_inode.read_at(0, &mut buf, &self.block_device);
+            String::from_utf8(buf).ok()
         })
     }
 
-    fn increase_size(
+    /// Large writes are split so each transaction fits in the journal
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut fs = self.fs.lock();
+        let mut written = 0;
+        for chunk in buf.chunks(WRITE_CHUNK_BLOCKS * BLOCK_SZ) {
+            let start = offset + written;
+            fs.begin_transaction();
+            written += self.modify_dist_inode(|disk_inode| {
+                disk_inode.touch(now());
+                self.map_range(start, start + chunk.len(), disk_inode, &mut fs);
+                disk_inode.write_at(start, chunk, &self.block_device)
+            });
+            fs.commit_transaction();
+        }
+        written
+    }
+
+    /// Cut the file down to `new_size` bytes or extend it with a hole, false
+    /// past the largest size an inode can map
+    pub fn truncate(&self, new_size: usize) -> bool {
+        if new_size > self.max_size() {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let size = self.read_dist_inode(|disk_inode| disk_inode.size as usize);
+        if new_size < size {
+            fs.begin_transaction();
+            self.modify_dist_inode(|disk_inode| {
+                // the rest of the last block has to read as zeros if the
+                // file grows again
+                let tail = size.min(new_size.next_multiple_of(BLOCK_SZ)) - new_size;
+                let last =
+                    disk_inode.get_block_id((new_size / BLOCK_SZ) as u32, &self.block_device);
+                if last != 0 {
+                    disk_inode.write_at(new_size, &[0; BLOCK_SZ][..tail], &self.block_device);
+                }
+                self.decrease_size(new_size as u32, disk_inode, &mut fs);
+                disk_inode.touch(now());
+            });
+            fs.commit_transaction();
+        } else if new_size > size {
+            fs.begin_transaction();
+            self.modify_dist_inode(|disk_inode| {
+                disk_inode.size = new_size as u32;
+                disk_inode.touch(now());
+            });
+            fs.commit_transaction();
+        }
+        true
+    }
+
+    /// Grow to at least `end` bytes and back `start..end` with data blocks,
+    /// whatever lies between the old end and `start` stays a hole
+    fn map_range(
         &self,
-        new_size: u32,
+        start: usize,
+        end: usize,
         disk_inode: &mut DiskInode,
-        fs: &mut MutexGuard<EasyFileSystem>,
+        fs: &mut SleepLockGuard<EasyFileSystem>,
     ) {
-        if new_size < disk_inode.size {
-            return;
-        }
-        let blocks_needed = disk_inode.blocks_num_needed(new_size);
-        let mut v: Vec<u32> = Vec::new();
-        for _ in 0..blocks_needed {
-            v.push(fs.alloc_data());
-        }
-        disk_inode.increase_size(new_size, v, &self.block_device);
+        disk_inode.size = disk_inode.size.max(end as u32);
+        disk_inode.map_blocks(
+            (start / BLOCK_SZ) as u32..end.div_ceil(BLOCK_SZ) as u32,
+            &self.block_device,
+            &mut |goal| fs.alloc_data_near(goal),
+        );
     }
 
     fn decrease_size(
         &self,
         new_size: u32,
         disk_inode: &mut DiskInode,
-        fs: &mut MutexGuard<EasyFileSystem>,
+        fs: &mut SleepLockGuard<EasyFileSystem>,
     ) {
         if new_size > disk_inode.size {
             return;
@@ -270,14 +512,17 @@ impl Inode {
 
     pub fn clear(&self) {
         let mut fs = self.fs.lock();
+        fs.begin_transaction();
         self.modify_dist_inode(|disk_inode| {
-            let size = disk_inode.size;
+            disk_inode.touch(now());
+            let blocks = disk_inode.allocated_blocks(&self.block_device);
             let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
+            assert!(data_blocks_dealloc.len() == blocks as usize);
             for data_block in data_blocks_dealloc.into_iter() {
                 fs.dealloc_data(data_block);
             }
         });
+        fs.commit_transaction();
     }
 
     pub fn remove(self: &Arc<Self>, name: &str) -> bool {
@@ -296,46 +541,39 @@ impl Inode {
             self.block_device.clone(),
         ));
 
-        if erase_inode.is_dir() {
-            if !erase_inode.read_dist_inode(|disk_inode| {
-                let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-                if file_count != 0 {
-                    return false;
-                }
-                return true;
-            }) {
-                return false;
-            }
+        let is_dir = erase_inode.is_dir();
+        if is_dir
+            && !erase_inode
+                .read_dist_inode(|disk_inode| disk_inode.entries(&self.block_device).is_empty())
+        {
+            return false;
         }
 
-        fs.dealloc_inode(inode.unwrap());
-
-        let mut v = self.read_dist_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut v: Vec<DirEntry> = Vec::new();
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device),
-                    DIRENT_SZ
-                );
-                v.push(dirent);
+        fs.begin_transaction();
+        // the inode goes once its last entry does
+        let unlinked = erase_inode.modify_dist_inode(|disk_inode| {
+            if !is_dir && disk_inode.nlink > 1 {
+                disk_inode.nlink -= 1;
+                disk_inode.ctime = now();
+                return false;
             }
-            v
+            for data_block in disk_inode.clear_size(&self.block_device) {
+                fs.dealloc_data(data_block);
+            }
+            true
         });
-
-        if let Some(pos) = v.iter().position(|x| x.name() == name) {
-            v.remove(pos);
+        if unlinked {
+            fs.dealloc_inode(inode.unwrap());
         }
 
         self.modify_dist_inode(|root_inode| {
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count - 1) * DIRENT_SZ;
-            self.decrease_size(new_size as u32, root_inode, &mut fs);
-            for i in 0..v.len() {
-                root_inode.write_at(i * DIRENT_SZ, v[i].as_bytes(), &self.block_device);
+            root_inode.remove_entry(name, &self.block_device);
+            if is_dir {
+                root_inode.nlink -= 1;
             }
+            root_inode.touch(now());
         });
+        fs.commit_transaction();
 
         return true;
     }
@@ -347,101 +585,60 @@ impl Inode {
     }
 
     pub fn mv(self: &Arc<Self>, src: &str, dst: &str) -> bool {
-        let src_inode = match self.cd(src) {
-            Some(inode) => inode,
-            None => return false,
+        // the entry itself moves, a symbolic link is not followed
+        let Some((parent, name)) = self.lookup_parent(src) else {
+            return false;
         };
-        let parent = src_inode.parent();
-        if Inode::same_inode(&parent, &src_inode) {
+        let Some(src_inode) = parent.find(name) else {
             return false;
-        }
+        };
         let new_dst_string;
         if dst.ends_with("/") {
-            let src_name = src.split('/').last().unwrap();
-            new_dst_string = format!("{}{}", dst, src_name);
+            new_dst_string = format!("{}{}", dst, name);
         } else {
             new_dst_string = dst.to_string();
         }
-        let new_dst = new_dst_string.as_str();
-        if self.cd(new_dst).is_some() {
+        let Some((dst_inode, last)) = self.lookup_parent(new_dst_string.as_str()) else {
             return false;
-        }
-        let mut dst_inode = if new_dst.starts_with('/') {
-            Arc::new(EasyFileSystem::root_inode(&self.fs))
-        } else {
-            self.clone()
         };
-        let tokens: Vec<_> = new_dst.split('/').collect();
-        for &token in &tokens[..tokens.len().saturating_sub(1)] {
-            if token == "." || token == "" {
-                continue;
-            }
-            if token == ".." {
-                dst_inode = dst_inode.parent();
-                continue;
-            }
-            dst_inode = match dst_inode.find(token) {
-                Some(next_inode) => next_inode,
-                None => return false,
-            };
-        }
 
-        if dst_inode.find(tokens.last().unwrap()).is_some() {
+        if dst_inode.find(last).is_some() {
             return false;
         }
 
-        if Inode::is_ancestor(&src_inode, dst_inode.clone()) {
+        if Inode::same_inode(&src_inode, &dst_inode)
+            || Inode::is_ancestor(&src_inode, dst_inode.clone())
+        {
             return false;
         };
 
-        let &last = tokens.last().unwrap();
-        let tokens: Vec<_> = src.split('/').collect();
-        let &name = tokens.last().unwrap();
-
-        let mut v = parent.read_dist_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut v: Vec<DirEntry> = Vec::new();
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device),
-                    DIRENT_SZ
-                );
-                v.push(dirent);
-            }
-            v
-        });
-
-        if let Some(pos) = v.iter().position(|x| x.name() == name) {
-            v.remove(pos);
+        let is_dir = src_inode.is_dir();
+        let mut fs = self.fs.lock();
+        fs.begin_transaction();
+        let now = now();
+        // the new entry goes in first, so a full destination changes nothing
+        let inode_id = src_inode.inode_id(&fs);
+        if !dst_inode.add_entry(last, inode_id, &mut fs) {
+            fs.commit_transaction();
+            return false;
+        }
+        if is_dir {
+            dst_inode.modify_dist_inode(|root_inode| root_inode.nlink += 1);
         }
 
-        let mut fs = self.fs.lock();
         parent.modify_dist_inode(|root_inode| {
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count - 1) * DIRENT_SZ;
-            parent.decrease_size(new_size as u32, root_inode, &mut fs);
-            for i in 0..v.len() {
-                root_inode.write_at(i * DIRENT_SZ, v[i].as_bytes(), &parent.block_device);
+            root_inode.remove_entry(name, &parent.block_device);
+            if is_dir {
+                root_inode.nlink -= 1;
             }
+            root_inode.touch(now);
         });
-
-        let inode_id = src_inode.inode_id(&fs);
-
-        dst_inode.modify_dist_inode(|root_inode| {
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            dst_inode.increase_size(new_size as u32, root_inode, &mut fs);
-            let dirent = DirEntry::new(last, inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &src_inode.block_device,
-            );
+        let dst_id = dst_inode.inode_id(&fs);
+        src_inode.modify_dist_inode(|disk_inode| {
+            disk_inode.parent = dst_id;
+            disk_inode.ctime = now;
         });
-        drop(fs);
-
-        src_inode.edit_parent(dst_inode);
+        fs.commit_transaction();
 
         return true;
     }
@@ -460,9 +657,44 @@ impl Inode {
         false
     }
 
+    /// Reading updates the access time, outside any transaction as losing it
+    /// in a crash does no harm
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         let _fs = self.fs.lock();
-        self.read_dist_inode(|dist_inode| dist_inode.read_at(offset, buf, &self.block_device))
+        let now = now();
+        let (read_size, atime) = self.read_dist_inode(|dist_inode| {
+            (
+                dist_inode.read_at(offset, buf, &self.block_device),
+                dist_inode.atime,
+            )
+        });
+        if read_size > 0 && atime != now {
+            self.modify_dist_inode(|dist_inode| dist_inode.atime = now);
+        }
+        read_size
+    }
+
+    pub fn stat(&self) -> Stat {
+        let fs = self.fs.lock();
+        let ino = self.inode_id(&fs) as u64;
+        self.read_dist_inode(|disk_inode| Stat {
+            ino,
+            mode: if disk_inode.is_dir() {
+                StatMode::DIR
+            } else if disk_inode.is_symlink() {
+                StatMode::SYMLINK
+            } else {
+                StatMode::FILE
+            } | disk_inode.mode as u32,
+            nlink: disk_inode.nlink,
+            uid: disk_inode.uid,
+            gid: disk_inode.gid,
+            size: disk_inode.size as u64,
+            blocks: disk_inode.allocated_blocks(&self.block_device) as u64,
+            atime: disk_inode.atime as u64,
+            mtime: disk_inode.mtime as u64,
+            ctime: disk_inode.ctime as u64,
+        })
     }
 
     pub fn same_inode(node1: &Arc<Self>, node2: &Arc<Self>) -> bool {
@@ -11,12 +11,33 @@ pub const SLAB_PER_BLOCK: usize = BLOCK_SIZE / SLAB_SIZE;
 pub const MEMORY_END: usize = 0x8800_0000;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+/// The heap grows from here with brk, up to `MMAP_BASE`
+pub const USER_HEAP_BASE: usize = 0x1000_0000;
+/// Region where mmap places its mappings
+pub const MMAP_BASE: usize = 0x10_0000_0000;
+pub const MMAP_END: usize = 0x20_0000_0000;
+/// Size of the swap disk in pages, 64 MiB
+pub const SWAP_PAGES: usize = 16384;
+/// User page faults evict pages to swap once fewer frames are free
+pub const RECLAIM_WATERMARK: usize = 64;
+pub const RECLAIM_BATCH: usize = 32;
 
 pub const CLOCK_FREQ: usize = 12500000;
 pub const TICK_PER_SEC: usize = 100;
+/// Cycles between two timer interrupts, reprogrammed by the M-mode handler
+pub const TIMER_INTERVAL: usize = 1_000_000;
 pub const MICRO_PER_SEC: usize = 1_000;
 // pub const MTIME_ADDR: usize = 0x0200bff8;
 pub const MTIMECMP_ADDR: usize = 0x02004000;
+pub const CLINT_BASE: usize = 0x0200_0000;
+pub const CLINT_SIZE: usize = 0x10000;
+pub const MSIP_ADDR: usize = CLINT_BASE;
+pub const PLIC_BASE: usize = 0x0c00_0000;
+pub const PLIC_SIZE: usize = 0x40_0000;
+/// Goldfish RTC, covered by the virt_test mapping
+pub const RTC_BASE: usize = 0x0010_1000;
+
+pub const MAX_HARTS: usize = 4;
 
 pub const PAGE_SIZE_BITS: usize = 12;
 pub const PAGE_SIZE: usize = 1 << PAGE_SIZE_BITS;
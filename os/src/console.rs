@@ -1,9 +1,13 @@
 // os/src/console.rs
 use crate::sbi::console_putchar;
+use crate::sync::SpinLock;
 use core::fmt::{self, Write};
 
 struct Stdout;
 
+/// Keeps lines printed by different harts from interleaving.
+static PRINT_LOCK: SpinLock<()> = SpinLock::new(());
+
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for c in s.chars() {
@@ -14,6 +18,7 @@ impl Write for Stdout {
 }
 
 pub fn print(args: fmt::Arguments) {
+    let _guard = PRINT_LOCK.exclusive_access();
     Stdout.write_fmt(args).unwrap();
 }
 
@@ -29,4 +34,4 @@ macro_rules! println {
     ($fmt: literal $(, $($arg: tt)+)?) => {
         $crate::console::print(format_args!(concat!($fmt, "\n") $(, $($arg)+)?));
     }
-}
\ No newline at end of file
+}
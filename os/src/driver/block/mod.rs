@@ -6,8 +6,27 @@ use lazy_static::*;
 
 mod virtio_blk;
 
+/// PLIC sources of the two virtio disks
+pub const BLOCK_IRQ: usize = 1;
+pub const SWAP_IRQ: usize = 2;
+
 lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    /// easy-fs holds only its sleeping locks over I/O, readers sleep on it
+    static ref BLOCK_DISK: Arc<BlockDeviceImpl> =
+        Arc::new(BlockDeviceImpl::new(virtio_blk::VIRTIO0, true));
+    static ref SWAP_DISK: Arc<BlockDeviceImpl> =
+        Arc::new(BlockDeviceImpl::new(virtio_blk::VIRTIO1, true));
+    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = BLOCK_DISK.clone();
+    /// Second disk, user pages are evicted to it under memory pressure
+    pub static ref SWAP_DEVICE: Arc<dyn BlockDevice> = SWAP_DISK.clone();
+}
+
+pub fn handle_block_irq(irq: usize) {
+    match irq {
+        BLOCK_IRQ => BLOCK_DISK.handle_irq(),
+        SWAP_IRQ => SWAP_DISK.handle_irq(),
+        _ => unreachable!(),
+    }
 }
 
 #[allow(unused)]
@@ -2,41 +2,178 @@ use crate::mm::{
     frame_alloc, frame_dealloc, kernel_token, FrameTracker, PageTable, PhysAddr, PhysPageNum,
     StepByOne, VirtAddr,
 };
-use crate::sync::UPSafeCell;
+use crate::sync::{held_locks, SpinLock};
+use crate::task::manager::wakeup_task;
+use crate::task::processor::current_task;
+use crate::task::task::{TaskControlBlock, TaskStatus};
+use crate::task::{block_current_and_run_next, suspend_current_and_run_next};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use easy_fs::BlockDevice;
+use core::hint::spin_loop;
+use easy_fs::{BlockDevice, BLOCK_SZ};
 use lazy_static::lazy_static;
-use virtio_drivers::{Hal, VirtIOBlk, VirtIOHeader};
+use virtio_drivers::{BlkResp, Hal, RespStatus, VirtIOBlk, VirtIOHeader};
 
-const VIRTIO0: usize = 0x10001000;
+pub const VIRTIO0: usize = 0x10001000;
+pub const VIRTIO1: usize = 0x10002000;
 
-pub struct VirtIOBlock(UPSafeCell<VirtIOBlk<'static, VirtioHal>>);
+/// A virtio disk whose requests complete through its interrupt. Several can
+/// be in flight, the caller sleeps until its own is done or polls for it.
+pub struct VirtIOBlock {
+    inner: SpinLock<VirtIOBlockInner>,
+    /// Callers may sleep on their requests
+    sleepable: bool,
+}
+
+struct VirtIOBlockInner {
+    blk: VirtIOBlk<'static, VirtioHal>,
+    /// In-flight requests by descriptor token, with the task sleeping on each
+    waiters: BTreeMap<u16, Option<Arc<TaskControlBlock>>>,
+    /// Requests completed but not collected by their caller yet
+    done: BTreeSet<u16>,
+}
+
+impl VirtIOBlockInner {
+    /// Collect the finished requests and wake their callers
+    fn reap(&mut self) {
+        while let Ok(token) = self.blk.pop_used() {
+            if let Some(Some(task)) = self.waiters.remove(&token) {
+                wakeup_task(task);
+            }
+            self.done.insert(token);
+        }
+    }
+}
 
 lazy_static! {
-    static ref QUEUE_FRAMES: UPSafeCell<Vec<FrameTracker>> = unsafe { UPSafeCell::new(Vec::new()) };
+    static ref QUEUE_FRAMES: SpinLock<Vec<FrameTracker>> = SpinLock::new(Vec::new());
 }
 
 impl VirtIOBlock {
-    pub fn new() -> Self {
-        Self(unsafe {
-            UPSafeCell::new(VirtIOBlk::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap())
-        })
+    /// A disk whose callers may hold spin locks `held_locks` doesn't count
+    /// has to be polled and must not be `sleepable`.
+    pub fn new(base: usize, sleepable: bool) -> Self {
+        Self {
+            inner: SpinLock::new(VirtIOBlockInner {
+                blk: unsafe { VirtIOBlk::new(&mut *(base as *mut VirtIOHeader)).unwrap() },
+                waiters: BTreeMap::new(),
+                done: BTreeSet::new(),
+            }),
+            sleepable,
+        }
+    }
+
+    pub fn handle_irq(&self) {
+        self.inner.exclusive_access().reap();
+    }
+
+    /// Submit `count` requests with `submit` and wait for all of them. On a
+    /// sleepable disk a running task holding no lock sleeps, anyone else
+    /// polls: the kernel while booting, a task on its way out, or one holding
+    /// a lock another task on this hart could spin on for good.
+    fn request(
+        &self,
+        count: usize,
+        submit: impl Fn(&mut VirtIOBlk<'static, VirtioHal>, usize) -> Option<u16>,
+    ) {
+        let waiter = current_task().filter(|task| {
+            self.sleepable
+                && held_locks() == 0
+                && task.inner_exclusive_access().task_status == TaskStatus::Running
+        });
+        let mut tokens = Vec::with_capacity(count);
+        while tokens.len() < count {
+            let mut inner = self.inner.exclusive_access();
+            if let Some(token) = submit(&mut inner.blk, tokens.len()) {
+                // nobody to wake yet, the waiter goes on one token at a time
+                inner.waiters.insert(token, None);
+                tokens.push(token);
+                continue;
+            }
+            // the queue is full, wait for a slot
+            assert!(!inner.waiters.is_empty(), "virtio-blk request failed");
+            inner.reap();
+            drop(inner);
+            if waiter.is_some() {
+                suspend_current_and_run_next();
+            } else {
+                spin_loop();
+            }
+        }
+        while let Some(&token) = tokens.last() {
+            let mut inner = self.inner.exclusive_access();
+            if waiter.is_none() {
+                inner.reap();
+            }
+            if inner.done.remove(&token) {
+                tokens.pop();
+                continue;
+            }
+            if let Some(slot) = inner.waiters.get_mut(&token) {
+                *slot = waiter.clone();
+            }
+            drop(inner);
+            if waiter.is_some() {
+                // queued as the waiter before the lock was released, so the
+                // interrupt cannot be missed
+                block_current_and_run_next();
+            } else {
+                spin_loop();
+            }
+        }
     }
 }
 
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.0
-            .exclusive_access()
-            .read_block(block_id, buf)
-            .expect("Error when reading VirtIOBlk");
+        self.read_blocks(block_id, buf);
     }
 
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        self.0
-            .exclusive_access()
-            .write_block(block_id, buf)
-            .expect("Error when writing VirtIOBlk");
+        self.write_blocks(block_id, buf);
+    }
+
+    /// Every block goes out at once, then they are waited for together
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        let mut resps: Vec<BlkResp> = (0..buf.len() / BLOCK_SZ)
+            .map(|_| BlkResp::default())
+            .collect();
+        let buf_ptr = buf.as_mut_ptr();
+        let resps_ptr = resps.as_mut_ptr();
+        // the buffers and the responses stay in place until the requests
+        // are done, and each request touches only its own block and response
+        self.request(resps.len(), |blk, i| unsafe {
+            let block = core::slice::from_raw_parts_mut(buf_ptr.add(i * BLOCK_SZ), BLOCK_SZ);
+            blk.read_block_nb(block_id + i, block, &mut *resps_ptr.add(i))
+                .ok()
+        });
+        for resp in &resps {
+            assert_eq!(
+                resp.status(),
+                RespStatus::Ok,
+                "Error when reading VirtIOBlk"
+            );
+        }
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
+        let mut resps: Vec<BlkResp> = (0..buf.len() / BLOCK_SZ)
+            .map(|_| BlkResp::default())
+            .collect();
+        let resps_ptr = resps.as_mut_ptr();
+        self.request(resps.len(), |blk, i| unsafe {
+            let block = &buf[i * BLOCK_SZ..(i + 1) * BLOCK_SZ];
+            blk.write_block_nb(block_id + i, block, &mut *resps_ptr.add(i))
+                .ok()
+        });
+        for resp in &resps {
+            assert_eq!(
+                resp.status(),
+                RespStatus::Ok,
+                "Error when writing VirtIOBlk"
+            );
+        }
     }
 }
 pub struct VirtioHal;
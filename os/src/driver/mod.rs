@@ -1,3 +1,40 @@
 pub mod block;
+pub mod plic;
 
-pub use block::BLOCK_DEVICE;
+pub use block::{BLOCK_DEVICE, SWAP_DEVICE};
+
+use crate::fs::console_interrupt;
+use crate::sbi::UART_IRQ;
+use crate::smp::hart_id;
+use block::{handle_block_irq, BLOCK_IRQ, SWAP_IRQ};
+
+const IRQS: [usize; 3] = [UART_IRQ, BLOCK_IRQ, SWAP_IRQ];
+
+/// Give every device interrupt the same priority, once at boot
+pub fn init() {
+    for irq in IRQS {
+        plic::set_priority(irq, 1);
+    }
+}
+
+/// Let the PLIC interrupt the calling hart, any hart may serve any device
+pub fn init_hart() {
+    let hart = hart_id();
+    for irq in IRQS {
+        plic::enable(hart, irq);
+    }
+    plic::set_threshold(hart, 0);
+}
+
+/// Serve the device interrupts pending on the calling hart
+pub fn handle_irqs() {
+    let hart = hart_id();
+    while let Some(irq) = plic::claim(hart) {
+        match irq {
+            UART_IRQ => console_interrupt(),
+            BLOCK_IRQ | SWAP_IRQ => handle_block_irq(irq),
+            _ => println!("[kernel] unexpected interrupt {}", irq),
+        }
+        plic::complete(hart, irq);
+    }
+}
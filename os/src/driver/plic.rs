@@ -0,0 +1,58 @@
+//! Platform-level interrupt controller of the QEMU virt machine
+//!
+//! Each hart has an M-mode and an S-mode context, the kernel only uses the
+//! S-mode ones: context `2 * hart + 1`.
+
+use crate::config::PLIC_BASE;
+
+const PRIORITY: usize = 0x0;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const THRESHOLD: usize = 0x20_0000;
+const CLAIM: usize = 0x20_0004;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+fn s_context(hart: usize) -> usize {
+    2 * hart + 1
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (PLIC_BASE + offset) as *mut u32
+}
+
+/// Priority 0 masks the source on every hart
+pub fn set_priority(irq: usize, priority: u32) {
+    unsafe {
+        reg(PRIORITY + 4 * irq).write_volatile(priority);
+    }
+}
+
+pub fn enable(hart: usize, irq: usize) {
+    let enable = reg(ENABLE + ENABLE_STRIDE * s_context(hart) + 4 * (irq / 32));
+    unsafe {
+        enable.write_volatile(enable.read_volatile() | 1 << (irq % 32));
+    }
+}
+
+/// Sources at or below `threshold` do not interrupt `hart`
+pub fn set_threshold(hart: usize, threshold: u32) {
+    unsafe {
+        reg(THRESHOLD + CONTEXT_STRIDE * s_context(hart)).write_volatile(threshold);
+    }
+}
+
+/// The highest-priority pending source, claimed by `hart` until `complete`
+pub fn claim(hart: usize) -> Option<usize> {
+    let irq = unsafe { reg(CLAIM + CONTEXT_STRIDE * s_context(hart)).read_volatile() };
+    if irq == 0 {
+        None
+    } else {
+        Some(irq as usize)
+    }
+}
+
+pub fn complete(hart: usize, irq: usize) {
+    unsafe {
+        reg(CLAIM + CONTEXT_STRIDE * s_context(hart)).write_volatile(irq as u32);
+    }
+}
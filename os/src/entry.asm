@@ -2,12 +2,23 @@
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
+    # every hart enters here, park the ones we have no room for
+    csrr a0, mhartid
+    li t0, {MAX_HARTS}
+    bgeu a0, t0, park
+    # sp = boot_stack_lower_bound + (hartid + 1) * 64K
+    addi t0, a0, 1
+    slli t0, t0, 16
+    la sp, boot_stack_lower_bound
+    add sp, sp, t0
     call init
+park:
+    wfi
+    j park
 
     .section .bss.stack
     .globl boot_stack_lower_bound
 boot_stack_lower_bound:
-    .space 4096 * 16
+    .space 4096 * 16 * {MAX_HARTS}
     .globl boot_stack_top
-boot_stack_top:
\ No newline at end of file
+boot_stack_top:
@@ -1,52 +1,95 @@
 use crate::driver::BLOCK_DEVICE;
-use crate::sync::UPSafeCell;
+use crate::sync::{held_locks, SpinLock};
+use crate::task::block_current_and_run_next;
+use crate::task::manager::wakeup_task;
+use crate::task::processor::current_task;
+use crate::task::task::{TaskControlBlock, TaskStatus};
+use crate::timer::{get_time_ms, get_wall_time_sec};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::bitflags;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use easy_fs::block_cache_sync_all;
 use easy_fs::EasyFileSystem;
 use easy_fs::Inode;
+use easy_fs::SleepLock;
+use easy_fs::Stat;
 use lazy_static::lazy_static;
 
 use crate::mm::UserBuffer;
 
-use super::File;
+use super::{File, SEEK_CUR, SEEK_END, SEEK_SET};
 
 pub struct OSInode {
     readable: bool,
     writable: bool,
-    inner: UPSafeCell<OSInodeInner>,
-}
-
-pub struct OSInodeInner {
-    offset: usize,
+    /// Every write goes to the end of the file
+    append: bool,
     inode: Arc<Inode>,
+    /// Held across the file I/O, which may sleep
+    offset: SleepLock<usize>,
 }
 
 impl OSInode {
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+    pub fn new(readable: bool, writable: bool, append: bool, inode: Arc<Inode>) -> Self {
         Self {
             readable,
             writable,
-            inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0, inode }) },
+            append,
+            inode,
+            offset: SleepLock::new(0),
         }
     }
 
     pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
+        let mut offset = self.offset.lock();
         let mut buf = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buf);
+            let len = self.inode.read_at(*offset, &mut buf);
             if len == 0 {
                 break;
             }
-            inner.offset += len;
+            *offset += len;
             v.extend_from_slice(&buf[..len]);
         }
         v
     }
 }
 
+fn read_buffers(inode: &Inode, mut offset: usize, buf: &mut UserBuffer) -> usize {
+    let mut total_read_size = 0usize;
+    for slice in buf.buffers.iter_mut() {
+        let read_size = inode.read_at(offset, *slice);
+        if read_size == 0 {
+            break;
+        }
+        offset += read_size;
+        total_read_size += read_size;
+    }
+    total_read_size
+}
+
+/// Writes stop short at the largest size a file can have
+fn write_buffers(inode: &Inode, mut offset: usize, buf: &UserBuffer) -> usize {
+    let mut total_write_size = 0usize;
+    let max_size = inode.max_size();
+    for slice in buf.buffers.iter() {
+        let len = slice.len().min(max_size.saturating_sub(offset));
+        if len == 0 {
+            break;
+        }
+        let write_size = inode.write_at(offset, &slice[..len]);
+        assert_eq!(write_size, len);
+        offset += write_size;
+        total_write_size += write_size;
+    }
+    total_write_size
+}
+
 impl File for OSInode {
     fn readable(&self) -> bool {
         self.readable
@@ -56,40 +99,177 @@ impl File for OSInode {
         self.writable
     }
 
+    fn inode(&self) -> Option<Arc<Inode>> {
+        Some(Arc::clone(&self.inode))
+    }
+
     fn read(&self, mut buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
-        let mut total_read_size = 0usize;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
+        let mut offset = self.offset.lock();
+        let read_size = read_buffers(&self.inode, *offset, &mut buf);
+        *offset += read_size;
+        read_size
+    }
+
+    /// The offset of a directory counts the entries already returned
+    fn getdents(&self, len: usize) -> Option<Vec<u8>> {
+        let mut offset = self.offset.lock();
+        if !self.inode.is_dir() {
+            return None;
+        }
+        let mut buf = Vec::new();
+        for (name, inode_id) in self.inode.entries().into_iter().skip(*offset) {
+            // ino, off, reclen and type, then the name and a NUL, padded to 8
+            let reclen = (19 + name.len() + 1).next_multiple_of(8);
+            if buf.len() + reclen > len {
+                // not even one entry fits
+                if buf.is_empty() {
+                    return None;
+                }
                 break;
             }
-            inner.offset += read_size;
-            total_read_size += read_size;
+            let child = self.inode.get_inode(inode_id);
+            let d_type = if child.is_dir() {
+                DT_DIR
+            } else if child.is_symlink() {
+                DT_LNK
+            } else {
+                DT_REG
+            };
+            *offset += 1;
+            let start = buf.len();
+            buf.extend_from_slice(&(inode_id as u64).to_le_bytes());
+            buf.extend_from_slice(&(*offset as i64).to_le_bytes());
+            buf.extend_from_slice(&(reclen as u16).to_le_bytes());
+            buf.push(d_type);
+            buf.extend_from_slice(name.as_bytes());
+            buf.resize(start + reclen, 0);
         }
-        total_read_size
+        Some(buf)
     }
 
     fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
+        let mut offset = self.offset.lock();
+        if self.append {
+            *offset = self.inode.size();
+        }
+        let write_size = write_buffers(&self.inode, *offset, &buf);
+        *offset += write_size;
+        write_size
+    }
+
+    /// Directories seek by entry, and have no end to seek from
+    fn seek(&self, offset: isize, whence: usize) -> Option<usize> {
+        let mut pos = self.offset.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *pos,
+            SEEK_END if !self.inode.is_dir() => self.inode.size(),
+            _ => return None,
+        };
+        *pos = base.checked_add_signed(offset)?;
+        Some(*pos)
+    }
+
+    fn read_at(&self, offset: usize, mut buf: UserBuffer) -> Option<usize> {
+        if !self.readable || self.inode.is_dir() {
+            return None;
+        }
+        Some(read_buffers(&self.inode, offset, &mut buf))
+    }
+
+    /// Writes at `offset` even when appending
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        if !self.writable {
+            return None;
+        }
+        Some(write_buffers(&self.inode, offset, &buf))
+    }
+
+    fn truncate(&self, len: usize) -> bool {
+        self.writable && self.inode.truncate(len)
+    }
+}
+
+lazy_static! {
+    /// Tasks sleeping on an easy-fs lock, by the address of its lock word
+    static ref LOCK_WAITERS: SpinLock<BTreeMap<usize, VecDeque<Arc<TaskControlBlock>>>> =
+        SpinLock::new(BTreeMap::new());
+}
+
+/// Wait for an easy-fs lock. A task holding no lock sleeps until the holder
+/// lets go, the kernel while booting and a task on its way out spin.
+fn lock_wait(state: &AtomicU32, expected: u32) {
+    let Some(task) = current_task().filter(|task| {
+        held_locks() == 0 && task.inner_exclusive_access().task_status != TaskStatus::Exited
+    }) else {
+        spin_loop();
+        return;
+    };
+    let key = state as *const AtomicU32 as usize;
+    let mut waiters = LOCK_WAITERS.exclusive_access();
+    // the holder lets go before it takes this lock to wake us, so a release
+    // after the check finds us queued
+    if state.load(Ordering::Relaxed) != expected {
+        return;
+    }
+    waiters.entry(key).or_default().push_back(Arc::clone(&task));
+    drop(waiters);
+    block_current_and_run_next();
+    // a stale wakeup may have let us go still queued, a later release must
+    // not spend its wakeup on us
+    let mut waiters = LOCK_WAITERS.exclusive_access();
+    if let Some(queue) = waiters.get_mut(&key) {
+        queue.retain(|waiter| !Arc::ptr_eq(waiter, &task));
+        if queue.is_empty() {
+            waiters.remove(&key);
         }
-        total_write_size
+    }
+}
+
+fn lock_wake(state: &AtomicU32) {
+    let key = state as *const AtomicU32 as usize;
+    let mut waiters = LOCK_WAITERS.exclusive_access();
+    let Some(queue) = waiters.get_mut(&key) else {
+        return;
+    };
+    let task = queue.pop_front();
+    if queue.is_empty() {
+        waiters.remove(&key);
+    }
+    drop(waiters);
+    if let Some(task) = task {
+        wakeup_task(task);
     }
 }
 
 lazy_static! {
     pub static ref ROOT_INODE: Arc<Inode> = {
+        easy_fs::set_clock(get_wall_time_sec);
+        easy_fs::set_lock_hooks(lock_wait, lock_wake);
         let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
         Arc::new(EasyFileSystem::root_inode(&efs))
     };
 }
 
+/// Longest a dirty block stays in the cache before the flusher writes it
+const FLUSH_INTERVAL_MS: usize = 1000;
+
+static LAST_FLUSH: AtomicUsize = AtomicUsize::new(0);
+
+/// The block cache flusher, called on timer ticks. Whichever hart comes by
+/// once the interval is up writes the dirty blocks back.
+pub fn flush_if_due() {
+    let now = get_time_ms();
+    let last = LAST_FLUSH.load(Ordering::Relaxed);
+    if now.wrapping_sub(last) >= FLUSH_INTERVAL_MS
+        && LAST_FLUSH
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    {
+        block_cache_sync_all();
+    }
+}
+
 pub fn list_apps() {
     println!("/**** APPS ****/");
     for app in ROOT_INODE.ls() {
@@ -105,6 +285,7 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const APPEND = 1 << 11;
     }
 }
 
@@ -120,65 +301,99 @@ impl OpenFlags {
     }
 }
 
-pub fn open_file(id: usize, name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// Open `path` relative to `dir`. Directories may only be opened to read, as
+/// a base for other lookups.
+pub fn open_file(dir: &Arc<Inode>, path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
-    let root_inode = Arc::new(ROOT_INODE.get_inode(id as u32));
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = root_inode.find(name) {
-            inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
-        } else {
-            root_inode
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+    let inode = match dir.cd(path) {
+        Some(inode) if inode.is_dir() => {
+            if writable || flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC) {
+                return None;
+            }
+            inode
         }
-    } else {
-        root_inode.find(name).map(|inode| {
+        Some(inode) => {
             if flags.contains(OpenFlags::TRUNC) {
                 inode.clear();
             }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+            inode
+        }
+        None if flags.contains(OpenFlags::CREATE) => {
+            let (parent, name) = dir.lookup_parent(path)?;
+            parent.create(name)?
+        }
+        None => return None,
+    };
+    Some(Arc::new(OSInode::new(
+        readable,
+        writable,
+        flags.contains(OpenFlags::APPEND),
+        inode,
+    )))
+}
+
+pub fn mkdir(dir: &Arc<Inode>, path: &str) -> isize {
+    match dir.lookup_parent(path) {
+        Some((parent, name)) if parent.mkdir(name).is_some() => 0,
+        _ => -1,
     }
 }
 
-pub fn mkdir(id: usize, name: &str) -> isize {
-    let node = ROOT_INODE.get_inode(id as u32).mkdir(name);
-    if node.is_none() {
-        -1
-    } else {
-        node.unwrap().self_id() as isize
+pub fn rm(dir: &Arc<Inode>, path: &str) -> isize {
+    match dir.lookup_parent(path) {
+        Some((parent, name)) if parent.remove(name) => 0,
+        _ => -1,
     }
 }
 
-pub fn ls(id: usize) -> isize {
-    let node = ROOT_INODE.get_inode(id as u32);
-    for name in node.ls() {
-        println!("{}", name);
+/// `d_type` of the records `getdents` packs
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+
+/// Don't follow a symbolic link at the end of the path
+pub const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+/// Remove a directory rather than a file
+pub const AT_REMOVEDIR: u32 = 0x200;
+
+pub fn stat(dir: &Arc<Inode>, path: &str, flags: u32) -> Option<Stat> {
+    dir.lookup(path, flags & AT_SYMLINK_NOFOLLOW == 0)
+        .map(|inode| inode.stat())
+}
+
+pub fn link(old_dir: &Arc<Inode>, old_path: &str, new_dir: &Arc<Inode>, new_path: &str) -> isize {
+    let Some(inode) = old_dir.lookup(old_path, false) else {
+        return -1;
+    };
+    match new_dir.lookup_parent(new_path) {
+        Some((dir, name)) if dir.link(name, &inode) => 0,
+        _ => -1,
     }
-    0
 }
 
-pub fn cd(id: usize, path: &str) -> isize {
-    let node = ROOT_INODE.get_inode(id as u32).cd(path);
-    if node.is_none() {
-        -1
-    } else {
-        node.unwrap().self_id() as isize
+pub fn unlink(dir: &Arc<Inode>, path: &str, flags: u32) -> isize {
+    let Some((parent, name)) = dir.lookup_parent(path) else {
+        return -1;
+    };
+    match parent.find(name) {
+        Some(inode) if inode.is_dir() == (flags & AT_REMOVEDIR != 0) && parent.remove(name) => 0,
+        _ => -1,
     }
 }
 
-pub fn rm(id: usize, path: &str) -> isize {
-    let node = ROOT_INODE.get_inode(id as u32);
-    if node.remove(path) {
-        0
-    } else {
-        -1
+pub fn symlink(target: &str, dir: &Arc<Inode>, path: &str) -> isize {
+    match dir.lookup_parent(path) {
+        Some((parent, name)) if parent.symlink(name, target).is_some() => 0,
+        _ => -1,
     }
 }
-pub fn mv(id: usize, src: &str, dst: &str) -> isize {
-    let node = ROOT_INODE.get_inode(id as u32);
-    if node.mv(src, dst) {
+
+pub fn readlink(dir: &Arc<Inode>, path: &str) -> Option<String> {
+    dir.lookup(path, false)?.readlink()
+}
+
+pub fn mv(dir: &Arc<Inode>, src: &str, dst: &str) -> isize {
+    if dir.mv(src, dst) {
         0
     } else {
         -1
@@ -1,16 +1,58 @@
 use crate::mm::UserBuffer;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::Inode;
 
 mod inode;
 mod pipe;
 mod stdio;
+mod tty;
 
 pub use inode::*;
 pub use pipe::make_pipe;
 pub use stdio::{Stdin, Stdout};
+pub use tty::*;
+
+/// `whence` of `File::seek`
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
 
 pub trait File: Send + Sync {
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
+    /// The inode behind the file, for files that can be mapped into memory
+    /// or stat-ed
+    fn inode(&self) -> Option<Arc<Inode>> {
+        None
+    }
+    /// Whether the file is the console, which the terminal syscalls act on
+    fn is_tty(&self) -> bool {
+        false
+    }
+    /// Pack the next directory entries into at most `len` bytes of
+    /// `linux_dirent64` records, for files that are directories. None as well
+    /// when not even one entry fits.
+    fn getdents(&self, _len: usize) -> Option<Vec<u8>> {
+        None
+    }
+    /// Move the offset relative to `whence`, returns the new offset. None for
+    /// files without one, like pipes and the console.
+    fn seek(&self, _offset: isize, _whence: usize) -> Option<usize> {
+        None
+    }
+    /// Read at `offset` leaving the file offset alone
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Write at `offset` leaving the file offset alone
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Cut the file down or extend it with zeros to `len` bytes
+    fn truncate(&self, _len: usize) -> bool {
+        false
+    }
 }
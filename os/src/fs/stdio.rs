@@ -1,5 +1,6 @@
+use super::tty::tty_read;
+use crate::fs::File;
 use crate::mm::UserBuffer;
-use crate::{fs::File, sbi::console_getchar, task::suspend_current_and_run_next};
 pub struct Stdin;
 ///Standard output
 pub struct Stdout;
@@ -11,28 +12,15 @@ impl File for Stdin {
     fn writable(&self) -> bool {
         false
     }
-    fn read(&self, mut user_buf: UserBuffer) -> usize {
-        assert_eq!(user_buf.len(), 1);
-        // busy loop
-        let mut c: usize;
-        loop {
-            c = console_getchar();
-            if c == 0 {
-                suspend_current_and_run_next();
-                continue;
-            } else {
-                break;
-            }
-        }
-        let ch = c as u8;
-        unsafe {
-            user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
-        }
-        1
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        tty_read(user_buf)
     }
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn is_tty(&self) -> bool {
+        true
+    }
 }
 
 impl File for Stdout {
@@ -51,4 +39,7 @@ impl File for Stdout {
         }
         user_buf.len()
     }
+    fn is_tty(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,250 @@
+use crate::mm::UserBuffer;
+use crate::sbi::console_getchar;
+use crate::sync::SpinLock;
+use crate::task::manager::wakeup_task;
+use crate::task::processor::{current_process, current_task};
+use crate::task::signal::{SIGINT, SIGQUIT, SIGTSTP};
+use crate::task::task::TaskControlBlock;
+use crate::task::{block_current_and_run_next, current_signal_pending, send_group_signal};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use lazy_static::lazy_static;
+
+bitflags! {
+    /// Local modes of the console, same bits as `c_lflag` of termios
+    pub struct TtyFlags: u32 {
+        /// Ctrl-C, Ctrl-\ and Ctrl-Z signal the foreground group
+        const ISIG = 0o1;
+        /// Input is edited and handed out a line at a time
+        const ICANON = 0o2;
+        const ECHO = 0o10;
+    }
+}
+
+const INTR: u8 = 0x03;
+const EOF: u8 = 0x04;
+const BS: u8 = 0x08;
+const LF: u8 = 0x0a;
+const CR: u8 = 0x0d;
+const KILL: u8 = 0x15;
+const SUSP: u8 = 0x1a;
+const QUIT: u8 = 0x1c;
+const DEL: u8 = 0x7f;
+
+/// Received bytes the line discipline has not seen yet, more are dropped
+const RX_BUFFER_SIZE: usize = 256;
+
+pub struct Tty {
+    flags: TtyFlags,
+    /// Input `read` can hand out, only whole lines in canonical mode
+    ready: VecDeque<u8>,
+    /// The line being edited in canonical mode
+    line: Vec<u8>,
+    /// Ctrl-D on an empty line, the next `read` returns 0
+    eof: bool,
+    /// Process group allowed to read, 0 before anyone claims the console
+    foreground: usize,
+    /// Tasks blocked in `tty_read`
+    readers: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Tty {
+    fn new() -> Self {
+        Self {
+            flags: TtyFlags::ISIG | TtyFlags::ICANON | TtyFlags::ECHO,
+            ready: VecDeque::new(),
+            line: Vec::new(),
+            eof: false,
+            foreground: 0,
+            readers: VecDeque::new(),
+        }
+    }
+
+    fn echo(&self, bytes: &[u8]) {
+        if self.flags.contains(TtyFlags::ECHO) {
+            print!("{}", core::str::from_utf8(bytes).unwrap_or("?"));
+        }
+    }
+
+    fn erase(&mut self) {
+        if self.line.pop().is_some() {
+            self.echo(&[BS, b' ', BS]);
+        }
+    }
+
+    /// Run one typed character through the line discipline, returns the
+    /// signal it raises
+    fn receive(&mut self, c: u8) -> Option<usize> {
+        let c = if c == CR { LF } else { c };
+        if self.flags.contains(TtyFlags::ISIG) {
+            let signum = match c {
+                INTR => Some((SIGINT, "^C\n")),
+                QUIT => Some((SIGQUIT, "^\\\n")),
+                SUSP => Some((SIGTSTP, "^Z\n")),
+                _ => None,
+            };
+            if let Some((signum, echo)) = signum {
+                self.echo(echo.as_bytes());
+                self.line.clear();
+                self.ready.clear();
+                return Some(signum);
+            }
+        }
+        if !self.flags.contains(TtyFlags::ICANON) {
+            self.echo(&[c]);
+            self.ready.push_back(c);
+            return None;
+        }
+        match c {
+            BS | DEL => self.erase(),
+            KILL => {
+                while !self.line.is_empty() {
+                    self.erase();
+                }
+            }
+            EOF => {
+                if self.line.is_empty() {
+                    self.eof = true;
+                }
+                self.ready.extend(self.line.drain(..));
+            }
+            LF => {
+                self.echo(&[LF]);
+                self.line.push(LF);
+                self.ready.extend(self.line.drain(..));
+            }
+            _ => {
+                self.echo(&[c]);
+                self.line.push(c);
+            }
+        }
+        None
+    }
+
+    fn readable(&self) -> bool {
+        !self.ready.is_empty() || self.eof
+    }
+
+    /// Copy available input to `buf`, at most one line in canonical mode
+    fn read(&mut self, buf: UserBuffer) -> usize {
+        if self.ready.is_empty() {
+            self.eof = false;
+            return 0;
+        }
+        let canonical = self.flags.contains(TtyFlags::ICANON);
+        let mut read_size = 0;
+        for byte_ref in buf {
+            let Some(c) = self.ready.pop_front() else {
+                break;
+            };
+            unsafe {
+                *byte_ref = c;
+            }
+            read_size += 1;
+            if canonical && c == LF {
+                break;
+            }
+        }
+        read_size
+    }
+}
+
+lazy_static! {
+    pub static ref TTY: SpinLock<Tty> = SpinLock::new(Tty::new());
+    static ref RX_BUFFER: SpinLock<VecDeque<u8>> =
+        SpinLock::new(VecDeque::with_capacity(RX_BUFFER_SIZE));
+}
+
+/// UART interrupt: drain the receive FIFO, then run what arrived through the
+/// line discipline
+pub fn console_interrupt() {
+    let mut rx = RX_BUFFER.exclusive_access();
+    while let Some(c) = console_getchar() {
+        if rx.len() < RX_BUFFER_SIZE {
+            rx.push_back(c);
+        }
+    }
+    drop(rx);
+    poll_console();
+}
+
+/// Feed the received bytes to the line discipline and wake the readers
+fn poll_console() {
+    let mut tty = TTY.exclusive_access();
+    let received: Vec<u8> = RX_BUFFER.exclusive_access().drain(..).collect();
+    if received.is_empty() {
+        return;
+    }
+    let mut signals = Vec::new();
+    for c in received {
+        if let Some(signum) = tty.receive(c) {
+            signals.push((tty.foreground, signum));
+        }
+    }
+    let readers = core::mem::take(&mut tty.readers);
+    drop(tty);
+    for task in readers {
+        wakeup_task(task);
+    }
+    // signalling locks processes, which must not nest inside the console
+    for (pgid, signum) in signals {
+        if pgid != 0 {
+            send_group_signal(pgid, signum);
+        }
+    }
+}
+
+/// Read from the console on behalf of the current process, job control is
+/// left to the caller. A process moved out of the foreground group while it
+/// waits goes on waiting until it is back, and a signal waiting for the
+/// reader cuts the wait short.
+pub fn tty_read(buf: UserBuffer) -> usize {
+    let pgid = current_process().inner_exclusive_access().pgid;
+    loop {
+        let mut tty = TTY.exclusive_access();
+        let foreground = tty.foreground == 0 || tty.foreground == pgid;
+        if foreground && tty.readable() {
+            return tty.read(buf);
+        }
+        if current_signal_pending() {
+            return 0;
+        }
+        // queued before the console is released, so no wakeup is missed
+        tty.readers.push_back(current_task().unwrap());
+        drop(tty);
+        block_current_and_run_next();
+    }
+}
+
+pub fn tty_get_flags() -> TtyFlags {
+    TTY.exclusive_access().flags
+}
+
+/// Switch modes, leaving canonical mode hands the line being edited to the
+/// reader
+pub fn tty_set_flags(flags: TtyFlags) {
+    let mut tty = TTY.exclusive_access();
+    if !flags.contains(TtyFlags::ICANON) {
+        let line = core::mem::take(&mut tty.line);
+        tty.ready.extend(line);
+    }
+    tty.flags = flags;
+}
+
+pub fn tty_get_foreground() -> usize {
+    TTY.exclusive_access().foreground
+}
+
+/// Hand the console to `pgid`, readers waiting in the background get to
+/// check whether it is theirs now
+pub fn tty_set_foreground(pgid: usize) {
+    let mut tty = TTY.exclusive_access();
+    tty.foreground = pgid;
+    let readers = core::mem::take(&mut tty.readers);
+    drop(tty);
+    for task in readers {
+        wakeup_task(task);
+    }
+}
@@ -12,6 +12,7 @@ mod fs;
 mod lang_items;
 mod mm;
 mod sbi;
+mod smp;
 mod sync;
 pub mod syscall;
 mod task;
@@ -20,26 +21,43 @@ use config::*;
 use core::arch::asm;
 mod timer;
 use core::arch::global_asm;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, Ordering};
 use riscv::register::{mepc, mhartid, mie, mscratch, mstatus, mtvec, pmpaddr0, pmpcfg0, satp, sie};
 
-global_asm!(include_str!("entry.asm"));
+global_asm!(include_str!("entry.asm"), MAX_HARTS = const MAX_HARTS);
 global_asm!(include_str!("link_app.S"));
 global_asm!(include_str!("time_handler.S"));
 
 extern crate alloc;
 extern crate bitflags;
 
+static BOOTED: AtomicBool = AtomicBool::new(false);
+
 #[no_mangle]
 pub fn rust_main() -> ! {
-    clear_bss();
-    println!("[kernel] Hello, world!");
-    mm::init();
-    println!("[kernel] memory init");
-    mm::remap_test();
-    trap::init_();
-    fs::list_apps();
-    task::add_initproc();
-    println!("after initproc!");
+    if smp::hart_id() == 0 {
+        clear_bss();
+        println!("[kernel] Hello, world!");
+        mm::init();
+        println!("[kernel] memory init");
+        mm::remap_test();
+        driver::init();
+        trap::init_();
+        fs::list_apps();
+        task::add_initproc();
+        println!("after initproc!");
+        BOOTED.store(true, Ordering::Release);
+    } else {
+        while !BOOTED.load(Ordering::Acquire) {
+            spin_loop();
+        }
+        mm::KERNEL_SPACE.exclusive_access().activate();
+        trap::init_();
+        println!("[kernel] hart {} online", smp::hart_id());
+    }
+    driver::init_hart();
+    smp::set_online();
     task::processor::run_tasks();
     panic!("Unreachable in rust_main!");
 }
@@ -64,22 +82,26 @@ unsafe fn init() -> ! {
     sie::set_stimer();
     pmpaddr0::write(0x3fffffffffffff);
     pmpcfg0::write(0xf);
-    time_init();
+    let hartid = mhartid::read();
+    // the kernel keeps the hart id in tp, see smp::hart_id
+    asm!("mv tp, {}", in(reg) hartid);
+    time_init(hartid);
     asm!("mret", options(noreturn),)
 }
 
-unsafe fn time_init() {
-    let hartid = mhartid::read();
+unsafe fn time_init(hartid: usize) {
     use crate::sbi::set_timer;
     set_timer(hartid, CLOCK_FREQ / TICK_PER_SEC + timer::get_time());
-    extern "C" {
-        fn __timer_scratch();
-    }
-    mscratch::write(__timer_scratch as usize);
+    let scratch = &mut *core::ptr::addr_of_mut!(timer::TIMER_SCRATCH[hartid]);
+    scratch[3] = MTIMECMP_ADDR + 8 * hartid;
+    scratch[4] = TIMER_INTERVAL;
+    scratch[6] = MSIP_ADDR + 4 * hartid;
+    mscratch::write(scratch.as_mut_ptr() as usize);
     extern "C" {
         fn __timehandler();
     }
     mtvec::write(__timehandler as usize, mtvec::TrapMode::Direct);
     mstatus::set_mie();
     mie::set_mtimer();
+    mie::set_msoft();
 }
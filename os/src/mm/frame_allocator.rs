@@ -1,12 +1,15 @@
 use core::fmt::Debug;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use super::address::{PhysAddr, PhysPageNum};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use lazy_static::lazy_static;
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    fn free_frames(&self) -> usize;
 }
 
 pub struct StackFrameAllocator {
@@ -40,6 +43,9 @@ impl FrameAllocator for StackFrameAllocator {
         }
         self.recycled.push(ppn);
     }
+    fn free_frames(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
 }
 
 impl StackFrameAllocator {
@@ -49,11 +55,11 @@ impl StackFrameAllocator {
     }
 }
 
-use crate::{config::MEMORY_END, sync::UPSafeCell};
+use crate::{config::MEMORY_END, sync::SpinLock};
 type FrameAllocatorImpl = StackFrameAllocator;
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
-        unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
+    pub static ref FRAME_ALLOCATOR: SpinLock<FrameAllocatorImpl> =
+        SpinLock::new(FrameAllocatorImpl::new());
 }
 
 pub fn init_frame_allocator() {
@@ -67,6 +73,9 @@ pub fn init_frame_allocator() {
 }
 pub struct FrameTracker {
     pub ppn: PhysPageNum,
+    /// References the kernel holds for the length of an access, apart from
+    /// the user spaces sharing the frame copy-on-write
+    pins: AtomicUsize,
 }
 
 impl FrameTracker {
@@ -75,7 +84,38 @@ impl FrameTracker {
         for i in byte_array {
             *i = 0;
         }
-        Self { ppn }
+        Self {
+            ppn,
+            pins: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Number of user spaces sharing `frame`, the kernel's pins left out
+pub fn frame_sharers(frame: &Arc<FrameTracker>) -> usize {
+    Arc::strong_count(frame) - frame.pins.load(Ordering::Acquire)
+}
+
+/// A user frame the kernel is accessing, it is neither evicted nor freed
+/// while pinned.
+pub struct FramePin(Arc<FrameTracker>);
+
+impl FramePin {
+    pub fn new(frame: &Arc<FrameTracker>) -> Self {
+        // take the reference first, a sharer too many is harmless
+        let frame = Arc::clone(frame);
+        frame.pins.fetch_add(1, Ordering::AcqRel);
+        Self(frame)
+    }
+
+    pub fn ppn(&self) -> PhysPageNum {
+        self.0.ppn
+    }
+}
+
+impl Drop for FramePin {
+    fn drop(&mut self) {
+        self.0.pins.fetch_sub(1, Ordering::AcqRel);
     }
 }
 
@@ -101,6 +141,10 @@ pub fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
 }
 
+pub fn free_frames() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().free_frames()
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
@@ -1,5 +1,6 @@
 use super::address::*;
 use super::frame_allocator::*;
+use super::swap::SwapSlot;
 use crate::asm;
 use crate::config::*;
 use crate::mm::page_table::PTEFlags;
@@ -7,17 +8,23 @@ use crate::mm::page_table::PageTable;
 use crate::mm::page_table::PageTableEntry;
 use crate::satp;
 use crate::sbi::{MMIO_BASE, VIRT_TEST};
-use crate::sync::UPSafeCell;
+use crate::smp::tlb_shootdown;
+use crate::sync::SpinLock;
+use crate::task::manager::reclaim_user_frames;
 use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_set::BTreeSet;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::bitflags;
+use easy_fs::Inode;
 use lazy_static::lazy_static;
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum MapType {
     Identical,
     Framed,
+    /// Framed, but a frame is only allocated on the first access to a page
+    Lazy,
 }
 
 bitflags! {
@@ -29,11 +36,47 @@ bitflags! {
     }
 
 }
+
+/// What resolving a page fault under the process lock came to
+pub enum Fault {
+    Resolved,
+    /// The access is not allowed or memory ran out
+    Denied,
+    /// The page is marked in transit, `slot` is to be read into the frame
+    /// with the process lock dropped
+    SwapIn(Arc<SwapSlot>, FrameTracker),
+    /// The page is marked in transit, the file is to be read into the frame
+    /// at the offset with the process lock dropped
+    FileIn(Arc<Inode>, usize, FrameTracker),
+    /// The page is on its way to or from swap or being read from its file,
+    /// try again later
+    InTransit,
+}
+
+/// File behind an mmap area, pages are read from `inode` on first access
+#[derive(Clone)]
+pub struct MmapFile {
+    pub inode: Arc<Inode>,
+    /// File offset of the first page of the area
+    pub offset: usize,
+    /// Writes reach the file (MAP_SHARED) instead of staying private
+    pub shared: bool,
+    /// The file was opened for writing
+    pub writable: bool,
+}
+
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// Frames are shared copy-on-write between forked user spaces, a frame
+    /// is private once its `Arc` is unique.
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+    /// Pages evicted to swap, shared on fork the same way as frames
+    swapped: BTreeMap<VirtPageNum, Arc<SwapSlot>>,
+    /// Pages being copied to or from swap with the process lock dropped
+    transit: BTreeSet<VirtPageNum>,
     map_type: MapType,
     map_perm: MapPermission,
+    file: Option<MmapFile>,
 }
 
 impl MapArea {
@@ -48,12 +91,18 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(start_vpn, end_vpn),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
+            transit: BTreeSet::new(),
             map_type,
             map_perm,
+            file: None,
         }
     }
 
     pub fn map(&mut self, page_table: &mut PageTable) {
+        if self.map_type == MapType::Lazy {
+            return;
+        }
         for vpn in self.vpn_range {
             self.map_one(page_table, vpn);
         }
@@ -92,32 +141,227 @@ impl MapArea {
             MapType::Identical => {
                 ppn = PhysPageNum(vpn.0);
             }
-            MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+            MapType::Framed | MapType::Lazy => {
+                self.map_frame(page_table, vpn, frame_alloc().unwrap());
+                return;
             }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         page_table.map(vpn, ppn, pte_flags);
     }
 
+    /// Back `vpn` with the zeroed `frame`
+    fn map_frame(&mut self, page_table: &mut PageTable, vpn: VirtPageNum, frame: FrameTracker) {
+        // start out accessed, the clock must not evict a page before the
+        // access that faulted it in
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap() | PTEFlags::A;
+        page_table.map(vpn, frame.ppn, pte_flags);
+        self.data_frames.insert(vpn, Arc::new(frame));
+    }
+
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         match self.map_type {
-            MapType::Framed => {
-                self.data_frames.remove(&vpn);
+            MapType::Framed | MapType::Lazy => {
+                // a lazy page that was never touched or is swapped out has
+                // nothing to unmap, one in transit is dropped when it lands
+                if self.data_frames.remove(&vpn).is_none() {
+                    self.swapped.remove(&vpn);
+                    self.transit.remove(&vpn);
+                    return;
+                }
             }
             _ => {}
         }
         page_table.unmap(vpn);
     }
 
+    /// Share every frame of `self` with `child`, write-protecting both
+    /// mappings so that the first write to a page copies it.
+    fn share_cow(
+        &mut self,
+        page_table: &mut PageTable,
+        child: &mut MapArea,
+        child_table: &mut PageTable,
+    ) {
+        let pte_flags = PTEFlags::from_bits((self.map_perm - MapPermission::W).bits).unwrap();
+        for (vpn, frame) in self.data_frames.iter() {
+            page_table.set_flags(*vpn, pte_flags);
+            child_table.map(*vpn, frame.ppn, pte_flags);
+            child.data_frames.insert(*vpn, Arc::clone(frame));
+        }
+        child.swapped = self.swapped.clone();
+    }
+
+    /// Map every frame of `self` into `child` as is, a MAP_SHARED area
+    /// keeps seeing the writes of the other side after fork.
+    fn share(&self, child: &mut MapArea, child_table: &mut PageTable) {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        for (vpn, frame) in self.data_frames.iter() {
+            child_table.map(*vpn, frame.ppn, pte_flags);
+            child.data_frames.insert(*vpn, Arc::clone(frame));
+        }
+    }
+
+    /// Private user pages may go to swap, shared file mappings stay resident
+    fn evictable(&self) -> bool {
+        self.map_perm.contains(MapPermission::U)
+            && self.map_type != MapType::Identical
+            && !self.is_shared()
+    }
+
+    fn is_shared(&self) -> bool {
+        self.file.as_ref().map_or(false, |file| file.shared)
+    }
+
+    fn file_offset(&self, vpn: VirtPageNum) -> usize {
+        let file = self.file.as_ref().unwrap();
+        file.offset + (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE
+    }
+
+    /// The dirty pages of a shared file mapping inside `[start, end)`, their
+    /// dirty bits cleared
+    fn take_dirty(
+        &self,
+        page_table: &mut PageTable,
+        start: VirtPageNum,
+        end: VirtPageNum,
+    ) -> Option<Writeback> {
+        let file = self.file.as_ref()?;
+        if !file.shared || !file.writable {
+            return None;
+        }
+        let pages: Vec<(usize, Arc<FrameTracker>)> = self
+            .data_frames
+            .range(start..end)
+            .filter(|(vpn, _)| page_table.take_dirty(**vpn))
+            .map(|(vpn, frame)| (self.file_offset(*vpn), Arc::clone(frame)))
+            .collect();
+        if pages.is_empty() {
+            return None;
+        }
+        // a stale TLB entry would let a later write skip setting the dirty bit
+        tlb_shootdown(page_table.token());
+        Some(Writeback {
+            inode: Arc::clone(&file.inode),
+            pages,
+        })
+    }
+
+    /// Change the permission of the area, pages still shared copy-on-write
+    /// stay write-protected.
+    fn set_perm(&mut self, page_table: &mut PageTable, map_perm: MapPermission) {
+        self.map_perm = map_perm;
+        for (vpn, frame) in self.data_frames.iter() {
+            let mut perm = map_perm;
+            if frame_sharers(frame) > 1 && !self.is_shared() {
+                perm.remove(MapPermission::W);
+            }
+            // keep the dirty bit, a shared file page still needs writing back
+            let dirty = page_table.translate(*vpn).unwrap().flags() & PTEFlags::D;
+            page_table.set_flags(*vpn, PTEFlags::from_bits(perm.bits).unwrap() | dirty);
+        }
+    }
+
+    /// Move the end of the area to `new_end`, unmapping the pages beyond it
+    /// when shrinking.
+    fn resize(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        let old_end = self.vpn_range.get_end();
+        if new_end < old_end {
+            for vpn in VPNRange::new(new_end, old_end) {
+                self.unmap_one(page_table, vpn);
+            }
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
+
+    /// Resolve a page fault on `vpn`, `access` is one of R, W and X. Denied
+    /// if the access violates the permission of the area.
+    fn handle_fault(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        access: MapPermission,
+    ) -> Fault {
+        if !self.map_perm.contains(access) {
+            return Fault::Denied;
+        }
+        if self.transit.contains(&vpn) {
+            return Fault::InTransit;
+        }
+        // out of memory and swap, the faulting task gets killed
+        if self.swapped.contains_key(&vpn) {
+            let Some(frame) = frame_alloc() else {
+                return Fault::Denied;
+            };
+            let slot = self.swapped.remove(&vpn).unwrap();
+            self.transit.insert(vpn);
+            return Fault::SwapIn(slot, frame);
+        }
+        let frame = match self.data_frames.get(&vpn) {
+            Some(frame) => Arc::clone(frame),
+            None if self.map_type == MapType::Lazy => {
+                let Some(frame) = frame_alloc() else {
+                    return Fault::Denied;
+                };
+                if let Some(file) = &self.file {
+                    let inode = Arc::clone(&file.inode);
+                    self.transit.insert(vpn);
+                    return Fault::FileIn(inode, self.file_offset(vpn), frame);
+                }
+                self.map_frame(page_table, vpn, frame);
+                return Fault::Resolved;
+            }
+            None => return Fault::Denied,
+        };
+        if access != MapPermission::W || page_table.translate(vpn).unwrap().writable() {
+            // another hart resolved the fault first, or our TLB entry is stale
+            return Fault::Resolved;
+        }
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        // the clone taken above is the second reference
+        if frame_sharers(&frame) == 2 || self.is_shared() {
+            page_table.set_flags(vpn, pte_flags);
+            return Fault::Resolved;
+        }
+        let Some(new_frame) = frame_alloc() else {
+            return Fault::Denied;
+        };
+        new_frame
+            .ppn
+            .get_bytes_array()
+            .copy_from_slice(frame.ppn.get_bytes_array());
+        page_table.unmap(vpn);
+        page_table.map(vpn, new_frame.ppn, pte_flags);
+        self.data_frames.insert(vpn, Arc::new(new_frame));
+        tlb_shootdown(page_table.token());
+        Fault::Resolved
+    }
+
+    /// Map the frame read back from swap or the file for `vpn`, unless the
+    /// page was unmapped while in transit
+    fn finish_page_in(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        frame: FrameTracker,
+    ) {
+        if !self.transit.remove(&vpn) {
+            return;
+        }
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap() | PTEFlags::A;
+        page_table.map(vpn, frame.ppn, pte_flags);
+        self.data_frames.insert(vpn, Arc::new(frame));
+    }
+
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
+            transit: BTreeSet::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            file: another.file.clone(),
         }
     }
 }
@@ -139,6 +383,8 @@ extern "C" {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// Where the clock of `reclaim` resumes
+    clock_hand: VirtPageNum,
 }
 
 impl MemorySet {
@@ -146,6 +392,7 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            clock_hand: VirtPageNum(0),
         }
     }
 
@@ -232,7 +479,27 @@ impl MemorySet {
         memory_set.push(
             MapArea::new(
                 MMIO_BASE.into(),
-                (MMIO_BASE + 0x2000).into(),
+                (MMIO_BASE + 0x3000).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        println!("mapping clint");
+        memory_set.push(
+            MapArea::new(
+                CLINT_BASE.into(),
+                (CLINT_BASE + CLINT_SIZE).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        println!("mapping plic");
+        memory_set.push(
+            MapArea::new(
+                PLIC_BASE.into(),
+                (PLIC_BASE + PLIC_SIZE).into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
             ),
@@ -253,6 +520,12 @@ impl MemorySet {
     }
 
     pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+        // segments are loaded eagerly, make room for them first
+        let want = elf_data.len() / PAGE_SIZE + RECLAIM_WATERMARK;
+        let free = free_frames();
+        if free < want {
+            reclaim_user_frames(want - free);
+        }
         let mut memory_set = Self::new_bare();
         memory_set.map_trampoline();
         let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
@@ -288,6 +561,12 @@ impl MemorySet {
         let max_end_va: VirtAddr = max_end_vpn.into();
         let mut user_stack_bottom: usize = max_end_va.into();
         user_stack_bottom += PAGE_SIZE;
+        // empty heap, brk grows it
+        memory_set.insert_lazy_area(
+            USER_HEAP_BASE.into(),
+            USER_HEAP_BASE.into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
         (
             memory_set,
             user_stack_bottom,
@@ -332,6 +611,173 @@ impl MemorySet {
         );
     }
 
+    pub fn insert_lazy_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_perm: MapPermission,
+    ) {
+        self.push(
+            MapArea::new(start_va, end_va, MapType::Lazy, map_perm),
+            None,
+        );
+    }
+
+    /// Map `file` at `[start_va, end_va)`, pages are read in on demand
+    pub fn insert_file_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_perm: MapPermission,
+        file: MmapFile,
+    ) {
+        let mut map_area = MapArea::new(start_va, end_va, MapType::Lazy, map_perm);
+        map_area.file = Some(file);
+        self.push(map_area, None);
+    }
+
+    /// Whether no area intersects `[start, end)`
+    pub fn is_free(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        self.areas
+            .iter()
+            .all(|area| area.vpn_range.get_end() <= start || end <= area.vpn_range.get_start())
+    }
+
+    /// Lowest gap of `pages` pages inside `[start, end)`
+    pub fn find_free_range(
+        &self,
+        start: VirtPageNum,
+        end: VirtPageNum,
+        pages: usize,
+    ) -> Option<VirtPageNum> {
+        let mut ranges: Vec<(usize, usize)> = self
+            .areas
+            .iter()
+            .map(|area| (area.vpn_range.get_start().0, area.vpn_range.get_end().0))
+            .filter(|&(l, r)| r > start.0 && l < end.0)
+            .collect();
+        ranges.sort();
+        let mut cursor = start.0;
+        for (l, r) in ranges {
+            if l >= cursor + pages {
+                break;
+            }
+            cursor = cursor.max(r);
+        }
+        if cursor + pages <= end.0 {
+            Some(VirtPageNum(cursor))
+        } else {
+            None
+        }
+    }
+
+    /// Indices of the user areas making up `[start, end)`, None if the range
+    /// is empty or cuts through an area.
+    fn whole_areas_in(&self, start: VirtPageNum, end: VirtPageNum) -> Option<Vec<usize>> {
+        let mut idxs = Vec::new();
+        for (idx, area) in self.areas.iter().enumerate() {
+            let (l, r) = (area.vpn_range.get_start(), area.vpn_range.get_end());
+            if r <= start || end <= l {
+                continue;
+            }
+            if l < start || end < r || !area.map_perm.contains(MapPermission::U) {
+                return None;
+            }
+            idxs.push(idx);
+        }
+        if idxs.is_empty() {
+            None
+        } else {
+            Some(idxs)
+        }
+    }
+
+    /// Unmap the user areas making up `[start, end)`, returns their dirty
+    /// shared file pages to write back
+    pub fn remove_areas_in(
+        &mut self,
+        start: VirtPageNum,
+        end: VirtPageNum,
+    ) -> Option<Vec<Writeback>> {
+        let idxs = self.whole_areas_in(start, end)?;
+        let mut writebacks = Vec::new();
+        for idx in idxs.into_iter().rev() {
+            let mut area = self.areas.remove(idx);
+            writebacks.extend(area.take_dirty(&mut self.page_table, start, end));
+            area.unmap(&mut self.page_table);
+        }
+        tlb_shootdown(self.token());
+        Some(writebacks)
+    }
+
+    /// Change the permission of the user areas making up `[start, end)`
+    pub fn protect_areas_in(
+        &mut self,
+        start: VirtPageNum,
+        end: VirtPageNum,
+        map_perm: MapPermission,
+    ) -> bool {
+        let Some(idxs) = self.whole_areas_in(start, end) else {
+            return false;
+        };
+        let denied = |area: &MapArea| {
+            map_perm.contains(MapPermission::W)
+                && area
+                    .file
+                    .as_ref()
+                    .map_or(false, |file| file.shared && !file.writable)
+        };
+        if idxs.iter().any(|&idx| denied(&self.areas[idx])) {
+            return false;
+        }
+        for idx in idxs {
+            self.areas[idx].set_perm(&mut self.page_table, map_perm);
+        }
+        tlb_shootdown(self.token());
+        true
+    }
+
+    /// The dirty pages of the shared file mappings intersecting
+    /// `[start, end)`, None if no area does
+    pub fn take_dirty_in(
+        &mut self,
+        start: VirtPageNum,
+        end: VirtPageNum,
+    ) -> Option<Vec<Writeback>> {
+        let mut found = false;
+        let mut writebacks = Vec::new();
+        for area in self
+            .areas
+            .iter()
+            .filter(|area| start < area.vpn_range.get_end() && area.vpn_range.get_start() < end)
+        {
+            writebacks.extend(area.take_dirty(&mut self.page_table, start, end));
+            found = true;
+        }
+        found.then_some(writebacks)
+    }
+
+    /// Move the end of the area starting at `start_vpn`, growing only into
+    /// unmapped pages.
+    pub fn resize_area(&mut self, start_vpn: VirtPageNum, new_end: VirtPageNum) -> bool {
+        let Some(idx) = self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_start() == start_vpn)
+        else {
+            return false;
+        };
+        let old_end = self.areas[idx].vpn_range.get_end();
+        if new_end < start_vpn || (new_end > old_end && !self.is_free(old_end, new_end)) {
+            return false;
+        }
+        self.areas[idx].resize(&mut self.page_table, new_end);
+        if new_end < old_end {
+            tlb_shootdown(self.token());
+        }
+        true
+    }
+
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -344,34 +790,237 @@ impl MemorySet {
         }
     }
 
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
+    /// Fork `user_space`. User pages are shared copy-on-write, only pages the
+    /// kernel writes through their physical address (the trap contexts) are
+    /// copied right away.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> MemorySet {
         let mut memory_set = MemorySet::new_bare();
         memory_set.map_trampoline();
-        for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
+        for area in user_space.areas.iter_mut() {
+            let mut new_area = MapArea::from_another(area);
+            if area.is_shared() {
+                area.share(&mut new_area, &mut memory_set.page_table);
+                memory_set.areas.push(new_area);
+                continue;
+            }
+            if area.map_perm.contains(MapPermission::U) && area.map_type != MapType::Identical {
+                area.share_cow(
+                    &mut user_space.page_table,
+                    &mut new_area,
+                    &mut memory_set.page_table,
+                );
+                memory_set.areas.push(new_area);
+                continue;
+            }
             memory_set.push(new_area, None);
             for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                let src_ppn = user_space.page_table.translate(vpn).unwrap().ppn();
                 let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
                 dst_ppn
                     .get_bytes_array()
                     .copy_from_slice(&src_ppn.get_bytes_array());
             }
         }
+        tlb_shootdown(user_space.token());
         memory_set
     }
 
+    /// Frames a fork of this user space needs for its page tables: one leaf
+    /// table per 512 resident pages plus the upper levels of each area
+    pub fn fork_frames(&self) -> usize {
+        let resident: usize = self.areas.iter().map(|area| area.data_frames.len()).sum();
+        resident / 512 + 3 * self.areas.len()
+    }
+
+    /// Whether some page is on its way to or from swap
+    pub fn in_transit(&self) -> bool {
+        self.areas.iter().any(|area| !area.transit.is_empty())
+    }
+
+    /// Resolve a page fault at `vpn` caused by an `access` (R, W or X) as far
+    /// as it goes under the process lock.
+    pub fn handle_page_fault(&mut self, vpn: VirtPageNum, access: MapPermission) -> Fault {
+        let page_table = &mut self.page_table;
+        self.areas
+            .iter_mut()
+            .find(|area| area.vpn_range.contains(vpn))
+            .map_or(Fault::Denied, |area| {
+                area.handle_fault(page_table, vpn, access)
+            })
+    }
+
+    /// Map the frame a `Fault::SwapIn` or `Fault::FileIn` read in
+    pub fn finish_page_in(&mut self, vpn: VirtPageNum, frame: FrameTracker) {
+        let page_table = &mut self.page_table;
+        if let Some(area) = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.contains(vpn))
+        {
+            area.finish_page_in(page_table, vpn, frame);
+        }
+    }
+
+    /// Pick up to `count` private user pages to evict with the clock
+    /// algorithm: a page accessed since the hand last passed it only loses
+    /// its accessed bit. The victims are unmapped and marked in transit, they
+    /// go to swap once the process lock is dropped.
+    pub fn pick_victims(&mut self, count: usize) -> Vec<(VirtPageNum, Arc<FrameTracker>)> {
+        let mut pages: Vec<(usize, VirtPageNum)> = Vec::new();
+        for (idx, area) in self.areas.iter().enumerate() {
+            if !area.evictable() {
+                continue;
+            }
+            // a frame shared copy-on-write or pinned elsewhere stays
+            for (vpn, frame) in area.data_frames.iter() {
+                if Arc::strong_count(frame) == 1 {
+                    pages.push((idx, *vpn));
+                }
+            }
+        }
+        pages.sort_by_key(|&(_, vpn)| vpn);
+        let hand = pages.partition_point(|&(_, vpn)| vpn < self.clock_hand);
+        pages.rotate_left(hand);
+        let mut victims: Vec<(VirtPageNum, Arc<FrameTracker>)> = Vec::new();
+        // the second lap picks the pages whose accessed bit the first cleared
+        for &(idx, vpn) in pages.iter().chain(pages.iter()) {
+            if victims.len() == count {
+                break;
+            }
+            self.clock_hand = VirtPageNum(vpn.0 + 1);
+            let area = &mut self.areas[idx];
+            let Some(frame) = area.data_frames.get(&vpn) else {
+                continue;
+            };
+            let flags = self.page_table.translate(vpn).unwrap().flags();
+            if flags.contains(PTEFlags::A) {
+                self.page_table.set_flags(vpn, flags - PTEFlags::A);
+                continue;
+            }
+            victims.push((vpn, Arc::clone(frame)));
+            area.data_frames.remove(&vpn);
+            area.transit.insert(vpn);
+            self.page_table.unmap(vpn);
+        }
+        if !victims.is_empty() {
+            // no hart may keep writing to a frame once it is copied out
+            tlb_shootdown(self.token());
+        }
+        victims
+    }
+
+    /// Record where the victims of `pick_victims` went, a page whose slot is
+    /// None stays resident. Returns the number of frames freed.
+    pub fn finish_eviction(
+        &mut self,
+        evicted: Vec<(VirtPageNum, Arc<FrameTracker>, Option<Arc<SwapSlot>>)>,
+    ) -> usize {
+        let mut freed = 0;
+        for (vpn, frame, slot) in evicted {
+            let Some(area) = self
+                .areas
+                .iter_mut()
+                .find(|area| area.vpn_range.contains(vpn))
+                .filter(|area| area.transit.remove(&vpn))
+            else {
+                // unmapped meanwhile
+                freed += 1;
+                continue;
+            };
+            match slot {
+                Some(slot) => {
+                    area.swapped.insert(vpn, slot);
+                    freed += 1;
+                }
+                None => {
+                    // swap is full, keep the page
+                    let pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+                    self.page_table.map(vpn, frame.ppn, pte_flags);
+                    area.data_frames.insert(vpn, frame);
+                }
+            }
+        }
+        freed
+    }
+
+    /// Pin the frame behind `vpn` for a kernel access on behalf of the user,
+    /// marking it dirty for a write as the user's own store would. None if
+    /// the page first needs a fault resolved.
+    pub fn pin_user_page(&mut self, vpn: VirtPageNum, access: MapPermission) -> Option<FramePin> {
+        let present = self.page_table.translate(vpn).map_or(false, |pte| {
+            pte.is_valid() && (access != MapPermission::W || pte.writable())
+        });
+        if !present {
+            return None;
+        }
+        let pin = self
+            .areas
+            .iter()
+            .find(|area| area.vpn_range.contains(vpn))
+            .and_then(|area| area.data_frames.get(&vpn))
+            .map(FramePin::new)?;
+        if access == MapPermission::W {
+            self.page_table.mark_dirty(vpn);
+        }
+        Some(pin)
+    }
+
+    /// The dirty pages of every shared file mapping
+    pub fn take_all_dirty(&mut self) -> Vec<Writeback> {
+        let mut writebacks = Vec::new();
+        for area in self.areas.iter() {
+            let range = area.vpn_range;
+            writebacks.extend(area.take_dirty(
+                &mut self.page_table,
+                range.get_start(),
+                range.get_end(),
+            ));
+        }
+        writebacks
+    }
+
+    /// The exiting main thread wrote the shared file mappings back already
     pub fn recycle_data_pages(&mut self) {
         self.areas.clear();
     }
 }
 
-lazy_static! {
-    pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> = Arc::new(unsafe {
-        {
-            UPSafeCell::new(MemorySet::new_kernel())
+impl Drop for MemorySet {
+    /// exec drops the old user space with the process lock released, dirty
+    /// shared pages go back first
+    fn drop(&mut self) {
+        for writeback in self.take_all_dirty() {
+            writeback.write();
+        }
+    }
+}
+
+/// Dirty pages of a shared file mapping, written back once the process lock
+/// is dropped since the file system may sleep
+pub struct Writeback {
+    inode: Arc<Inode>,
+    /// File offset and frame of each page, in file order
+    pages: Vec<(usize, Arc<FrameTracker>)>,
+}
+
+impl Writeback {
+    /// Write the pages back to the file, without growing it
+    pub fn write(self) {
+        let size = self.inode.size();
+        for (offset, frame) in self.pages {
+            if offset >= size {
+                break;
+            }
+            let len = PAGE_SIZE.min(size - offset);
+            self.inode
+                .write_at(offset, &frame.ppn.get_bytes_array()[..len]);
         }
-    });
+    }
+}
+
+lazy_static! {
+    pub static ref KERNEL_SPACE: Arc<SpinLock<MemorySet>> =
+        Arc::new(SpinLock::new(MemorySet::new_kernel()));
 }
 
 pub fn kernel_token() -> usize {
@@ -4,10 +4,12 @@ mod heap_allocator;
 mod memory_set;
 mod page_table;
 mod slab;
+mod swap;
 pub use address::*;
 pub use frame_allocator::*;
 pub use memory_set::*;
 pub use page_table::*;
+pub use swap::SwapSlot;
 
 pub use memory_set::KERNEL_SPACE;
 
@@ -16,4 +18,5 @@ pub fn init() {
     // heap_allocator::heap_test();
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.exclusive_access().activate();
+    swap::init();
 }
@@ -1,8 +1,10 @@
 use super::address::PhysPageNum;
 use crate::mm::address::*;
 use crate::mm::*;
+use crate::task::processor::current_process;
 use alloc::collections::binary_heap::Iter;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
@@ -57,6 +59,10 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
 }
 
 pub struct PageTable {
@@ -83,6 +89,29 @@ impl PageTable {
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping!", vpn);
         *pte = PageTableEntry::empty();
     }
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping!", vpn);
+        *pte = PageTableEntry::new(pte.ppn(), flags | PTEFlags::V);
+    }
+
+    /// Set the dirty bit of `vpn`, for writes the kernel does on behalf of
+    /// the user
+    pub fn mark_dirty(&mut self, vpn: VirtPageNum) {
+        if let Some(pte) = self.find_pte_create(vpn).filter(|pte| pte.is_valid()) {
+            pte.bits |= PTEFlags::D.bits as usize;
+        }
+    }
+
+    /// Clear the dirty bit of `vpn`, returning whether it was set
+    pub fn take_dirty(&mut self, vpn: VirtPageNum) -> bool {
+        let Some(pte) = self.find_pte_create(vpn).filter(|pte| pte.is_valid()) else {
+            return false;
+        };
+        let dirty = pte.dirty();
+        pte.bits &= !(PTEFlags::D.bits as usize);
+        dirty
+    }
 
     fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
@@ -147,15 +176,37 @@ impl PageTable {
     }
 }
 
-pub fn translate_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_token(token);
+/// Translate `va` of the current user space for a kernel access, faulting in
+/// lazy pages and breaking copy-on-write sharing first if `access` needs it.
+fn translated_user_pa(page_table: &PageTable, va: VirtAddr, access: MapPermission) -> PhysAddr {
+    let vpn = va.floor();
+    let present = page_table.translate(vpn).map_or(false, |pte| {
+        pte.is_valid() && (access != MapPermission::W || (pte.writable() && pte.dirty()))
+    });
+    if !present {
+        // pinning faults the page in and marks it dirty for a write
+        let resolved = current_process().pin_user_page(vpn, access).is_some();
+        assert!(resolved, "bad user address {:#x}", usize::from(va));
+    }
+    page_table.translate_va(va).unwrap()
+}
+
+/// Buffer of the current user space. Its frames stay pinned while the kernel
+/// holds on to it, so that they are neither evicted nor freed under it.
+fn translated_user_buffer(ptr: *const u8, len: usize, access: MapPermission) -> UserBuffer {
+    let process = current_process();
     let mut start = ptr as usize;
     let end = start + len;
     let mut v = Vec::new();
+    let mut pins = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let frame = process
+            .pin_user_page(vpn, access)
+            .unwrap_or_else(|| panic!("bad user address {:#x}", start));
+        let ppn = frame.ppn();
+        pins.push(frame);
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
@@ -166,7 +217,18 @@ pub fn translate_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'
         }
         start = end_va.into();
     }
-    v
+    UserBuffer { buffers: v, pins }
+}
+
+/// User buffer the kernel only reads from, `ptr` must belong to the current
+/// user space
+pub fn translate_byte_buffer(_token: usize, ptr: *const u8, len: usize) -> UserBuffer {
+    translated_user_buffer(ptr, len, MapPermission::R)
+}
+
+/// User buffer the kernel writes into
+pub fn translate_byte_buffer_mut(_token: usize, ptr: *mut u8, len: usize) -> UserBuffer {
+    translated_user_buffer(ptr, len, MapPermission::W)
 }
 
 pub fn translated_str(token: usize, ptr: *const u8) -> String {
@@ -174,10 +236,8 @@ pub fn translated_str(token: usize, ptr: *const u8) -> String {
     let mut string = String::new();
     let mut va = ptr as usize;
     loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
+        let ch: u8 =
+            *(translated_user_pa(&page_table, VirtAddr::from(va), MapPermission::R).get_mut());
         if ch == 0 {
             break;
         } else {
@@ -191,29 +251,21 @@ pub fn translated_str(token: usize, ptr: *const u8) -> String {
 pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
     let page_table = PageTable::from_token(token);
     let va = ptr as usize;
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
+    translated_user_pa(&page_table, VirtAddr::from(va), MapPermission::W).get_mut()
 }
 
 pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
     let page_table = PageTable::from_token(token);
     let va = ptr as usize;
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_ref()
+    translated_user_pa(&page_table, VirtAddr::from(va), MapPermission::R).get_ref()
 }
 
 pub struct UserBuffer {
     pub buffers: Vec<&'static mut [u8]>,
+    pins: Vec<FramePin>,
 }
 
 impl UserBuffer {
-    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
-        Self { buffers }
-    }
     pub fn len(&self) -> usize {
         self.buffers.iter().map(|b| b.len()).sum()
     }
@@ -225,6 +277,7 @@ impl IntoIterator for UserBuffer {
     fn into_iter(self) -> Self::IntoIter {
         UserBufferIterator {
             buffers: self.buffers,
+            _pins: self.pins,
             current_buffer: 0,
             current_idx: 0,
         }
@@ -233,6 +286,7 @@ impl IntoIterator for UserBuffer {
 
 pub struct UserBufferIterator {
     buffers: Vec<&'static mut [u8]>,
+    _pins: Vec<FramePin>,
     current_buffer: usize,
     current_idx: usize,
 }
@@ -1,16 +1,19 @@
 use crate::{
     config::{BLOCK_SIZE, KERNEL_HEAP_SIZE, NODE_SIZE, SLAB_PER_BLOCK, SLAB_SIZE},
     mm::heap_allocator::{Buddy, HEAP_SPACE},
+    sync::SpinLock,
 };
 use alloc::alloc::{GlobalAlloc, Layout};
 const SLAB_MEM_COUNT: usize = KERNEL_HEAP_SIZE / SLAB_SIZE;
 static mut SLAB_MEM: [u8; SLAB_MEM_COUNT] = [0; SLAB_MEM_COUNT];
 static mut PAGE_COUNT: [u8; NODE_SIZE] = [0; NODE_SIZE];
 static BUDDY: Buddy = Buddy {};
+static HEAP_LOCK: SpinLock<()> = SpinLock::new(());
 pub struct Slab;
 
 unsafe impl GlobalAlloc for Slab {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _guard = HEAP_LOCK.exclusive_access();
         // println!(
         //     "[ALLOC] requested size = {}, align = {}",
         //     layout.size(),
@@ -75,6 +78,7 @@ unsafe impl GlobalAlloc for Slab {
         ptr
     }
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _guard = HEAP_LOCK.exclusive_access();
         let ptr_usize = ptr as usize;
         let heap_base = HEAP_SPACE.as_ptr() as usize;
         // println!(
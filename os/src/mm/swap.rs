@@ -0,0 +1,76 @@
+use super::address::PhysPageNum;
+use crate::config::{PAGE_SIZE, SWAP_PAGES};
+use crate::driver::SWAP_DEVICE;
+use crate::sync::SpinLock;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+const BLOCK_SZ: usize = 512;
+const BLOCKS_PER_PAGE: usize = PAGE_SIZE / BLOCK_SZ;
+
+struct SwapAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl SwapAllocator {
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(id) = self.recycled.pop() {
+            return Some(id);
+        }
+        if self.current == SWAP_PAGES {
+            return None;
+        }
+        self.current += 1;
+        Some(self.current - 1)
+    }
+
+    fn dealloc(&mut self, id: usize) {
+        assert!(id < self.current && !self.recycled.contains(&id));
+        self.recycled.push(id);
+    }
+}
+
+lazy_static! {
+    static ref SWAP_ALLOCATOR: SpinLock<SwapAllocator> = SpinLock::new(SwapAllocator {
+        current: 0,
+        recycled: Vec::new(),
+    });
+}
+
+/// A page worth of blocks on the swap disk. Like frames, a slot is shared
+/// between forked user spaces through its `Arc` and freed with the last one.
+pub struct SwapSlot {
+    id: usize,
+}
+
+impl SwapSlot {
+    /// Copy the frame `ppn` out to a new slot, None if the swap disk is full
+    pub fn write(ppn: PhysPageNum) -> Option<Arc<SwapSlot>> {
+        let id = SWAP_ALLOCATOR.exclusive_access().alloc()?;
+        let bytes = ppn.get_bytes_array();
+        for (i, block) in bytes.chunks(BLOCK_SZ).enumerate() {
+            SWAP_DEVICE.write_block(id * BLOCKS_PER_PAGE + i, block);
+        }
+        Some(Arc::new(SwapSlot { id }))
+    }
+
+    pub fn read(&self, ppn: PhysPageNum) {
+        let bytes = ppn.get_bytes_array();
+        for (i, block) in bytes.chunks_mut(BLOCK_SZ).enumerate() {
+            SWAP_DEVICE.read_block(self.id * BLOCKS_PER_PAGE + i, block);
+        }
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP_ALLOCATOR.exclusive_access().dealloc(self.id);
+    }
+}
+
+/// Bring up the swap disk while there is still memory for its queue
+pub fn init() {
+    lazy_static::initialize(&SWAP_DEVICE);
+}
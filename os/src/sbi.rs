@@ -1,11 +1,11 @@
 // os/src/sbi.rs
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
 use lazy_static::*;
 
 unsafe impl Send for MemoryManager {}
 lazy_static! {
-    pub static ref Memory_Managr: UPSafeCell<MemoryManager> =
-        unsafe { UPSafeCell::new(MemoryManager::new()) };
+    pub static ref Memory_Managr: SpinLock<MemoryManager> =
+        SpinLock::new(unsafe { MemoryManager::new() });
 }
 pub fn console_putchar(c: usize) {
     unsafe {
@@ -13,8 +13,9 @@ pub fn console_putchar(c: usize) {
     }
 }
 
-pub fn console_getchar() -> usize {
-    unsafe { Memory_Managr.exclusive_access().read_char() as usize }
+/// A byte from the receive FIFO, if any arrived
+pub fn console_getchar() -> Option<u8> {
+    unsafe { Memory_Managr.exclusive_access().read_char() }
 }
 
 pub const VIRT_TEST: *mut u32 = 0x10_0000 as *mut u32;
@@ -32,6 +33,8 @@ pub fn shutdown(failure: bool) -> ! {
 }
 
 pub const MMIO_BASE: usize = 0x10000000;
+/// PLIC source of the UART
+pub const UART_IRQ: usize = 10;
 const RBR: usize = 0;
 const THR: usize = 0;
 // const DLL: usize = 0;
@@ -41,7 +44,7 @@ const IER: usize = 1;
 const FCR: usize = 2;
 const LCR: usize = 3;
 // const MCR: usize = 4;
-// const LSR: usize = 5;
+const LSR: usize = 5;
 // const MSR: usize = 6;
 // const SCR: usize = 7;
 
@@ -52,8 +55,9 @@ const MSB_RATE: u8 = 0x00;
 const LCR_8BITS: u8 = 3 << 0;
 const FCR_FIFO_ENABLE: u8 = 1 << 0;
 const FCR_FIFO_CLEAR: u8 = 3 << 1;
-const IER_TX_ENABLE: u8 = 1 << 1;
+// const IER_TX_ENABLE: u8 = 1 << 1;
 const IER_RX_ENABLE: u8 = 1 << 0;
+const LSR_DATA_READY: u8 = 1 << 0;
 use core::ptr;
 
 pub struct MemoryManager {
@@ -86,12 +90,16 @@ impl MemoryManager {
         self.write_byte(1, MSB_RATE);
         self.write_byte(LCR, LCR_8BITS);
         self.write_byte(FCR, FCR_FIFO_ENABLE | FCR_FIFO_CLEAR);
-        self.write_byte(IER, IER_TX_ENABLE | IER_RX_ENABLE);
+        // output is polled, only received bytes raise an interrupt
+        self.write_byte(IER, IER_RX_ENABLE);
     }
 
-    pub unsafe fn read_char(&self) -> u8 {
-        // while self.read_byte(LSR) & 0x20 == 0 {}
-        self.read_byte(RBR)
+    pub unsafe fn read_char(&self) -> Option<u8> {
+        if self.read_byte(LSR) & LSR_DATA_READY == 0 {
+            None
+        } else {
+            Some(self.read_byte(RBR))
+        }
     }
 
     pub unsafe fn put_char(&self, c: u8) {
@@ -107,3 +115,10 @@ pub fn set_timer(hartid: usize, timer: usize) {
         core::ptr::write_volatile(mtimecmp, timer as u64);
     }
 }
+
+pub fn send_ipi(hartid: usize) {
+    let msip = (MSIP_ADDR + (4 * hartid)) as *mut u32;
+    unsafe {
+        core::ptr::write_volatile(msip, 1);
+    }
+}
@@ -0,0 +1,98 @@
+//! Hart identification and inter-processor interrupts
+//!
+//! An IPI is a machine software interrupt raised through the CLINT; the
+//! M-mode handler in `time_handler.S` forwards it as a supervisor software
+//! interrupt, and `trap_handler` reads the reasons from the mailbox below.
+
+use crate::config::MAX_HARTS;
+use crate::sbi::send_ipi;
+use core::arch::asm;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const IPI_RESCHEDULE: usize = 1 << 0;
+pub const IPI_TLB_FLUSH: usize = 1 << 1;
+
+static PENDING: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+/// satp of the address space each hart runs in user mode, 0 in the kernel
+static HART_TOKEN: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+/// Times each hart has trapped into the kernel
+static ENTRIES: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// `tp` holds the hart id while running in the kernel, see `trap.S`.
+pub fn hart_id() -> usize {
+    let id: usize;
+    unsafe {
+        asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
+
+pub fn set_online() {
+    ONLINE.fetch_or(1 << hart_id(), Ordering::SeqCst);
+}
+
+pub fn set_current_token(token: usize) {
+    HART_TOKEN[hart_id()].store(token, Ordering::Release);
+}
+
+/// Called on every trap from user mode. The trampoline has switched to the
+/// kernel satp and flushed the TLB by now, which answers any shootdown.
+pub fn enter_kernel() {
+    let me = hart_id();
+    HART_TOKEN[me].store(0, Ordering::Release);
+    ENTRIES[me].fetch_add(1, Ordering::AcqRel);
+}
+
+pub fn send(hartid: usize, reason: usize) {
+    if ONLINE.load(Ordering::Acquire) & (1 << hartid) == 0 {
+        return;
+    }
+    PENDING[hartid].fetch_or(reason, Ordering::SeqCst);
+    send_ipi(hartid);
+}
+
+/// Send `reason` to every online hart except the calling one.
+pub fn broadcast(reason: usize) {
+    let me = hart_id();
+    (0..MAX_HARTS)
+        .filter(|&id| id != me)
+        .for_each(|id| send(id, reason));
+}
+
+/// Flush the TLB of every hart that may cache translations of `token`, and
+/// wait until they have.
+///
+/// Only harts in user mode under `token` are asked. They have interrupts on,
+/// so they trap right away, and the trampoline flushes the TLB when it
+/// switches satp. A hart is done once it is seen back in the kernel or
+/// trapped in since it was asked.
+pub fn tlb_shootdown(token: usize) {
+    unsafe {
+        asm!("sfence.vma");
+    }
+    let me = hart_id();
+    let mut asked = [None; MAX_HARTS];
+    for id in (0..MAX_HARTS).filter(|&id| id != me) {
+        let entries = ENTRIES[id].load(Ordering::Acquire);
+        if HART_TOKEN[id].load(Ordering::Acquire) == token {
+            send(id, IPI_TLB_FLUSH);
+            asked[id] = Some(entries);
+        }
+    }
+    for (id, entries) in asked.iter().enumerate() {
+        let Some(entries) = *entries else {
+            continue;
+        };
+        while HART_TOKEN[id].load(Ordering::Acquire) == token
+            && ENTRIES[id].load(Ordering::Acquire) == entries
+        {
+            spin_loop();
+        }
+    }
+}
+
+pub fn take_pending() -> usize {
+    PENDING[hart_id()].swap(0, Ordering::SeqCst)
+}
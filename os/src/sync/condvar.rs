@@ -1,22 +1,21 @@
-use super::{Mutex, UPSafeCell};
+use super::{Mutex, SpinLock};
 use crate::task::{
-    block_current_and_run_next, manager::wakeup_task, processor::current_task,
-    task::TaskControlBlock,
+    block_current_and_run_next, current_signal_pending, manager::wakeup_task,
+    processor::current_task, task::TaskControlBlock,
 };
+use crate::timer::{add_timer, get_time_ms, remove_timer, TimedWait};
 use alloc::{collections::vec_deque::VecDeque, sync::Arc};
 
 pub struct Condvar {
-    pub inner: UPSafeCell<CondvarInner>,
+    pub inner: SpinLock<CondvarInner>,
 }
 
 impl Condvar {
     pub fn new() -> Self {
         Self {
-            inner: unsafe {
-                UPSafeCell::new(CondvarInner {
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinLock::new(CondvarInner {
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 
@@ -27,13 +26,74 @@ impl Condvar {
         }
     }
 
-    pub fn wait(&self, mutex: Arc<dyn Mutex>) {
+    pub fn broadcast(&self) {
+        let mut inner = self.inner.exclusive_access();
+        for task in inner.wait_queue.drain(..) {
+            wakeup_task(task);
+        }
+    }
+
+    /// Release `mutex` and wait to be signalled. A signal to the process
+    /// ends the wait early like a spurious wakeup. Taking `mutex` again is up
+    /// to the caller, so that it goes through deadlock detection.
+    pub fn wait(&self, mutex: &dyn Mutex) {
+        // queue up before releasing the mutex so that a signal sent from
+        // another hart right after the unlock is not lost
+        let task = current_task().unwrap();
+        let mut inner = self.inner.exclusive_access();
+        inner.wait_queue.push_back(Arc::clone(&task));
+        drop(inner);
         mutex.unlock();
+        self.sleep(&task);
+    }
+
+    /// Like `wait`, but gives up after `timeout_ms`. Returns whether it was
+    /// woken before the timeout, which a signal racing the deadline may also
+    /// be taken for.
+    pub fn wait_timeout(self: &Arc<Self>, mutex: &dyn Mutex, timeout_ms: usize) -> bool {
+        let task = current_task().unwrap();
+        let expire_ms = get_time_ms() + timeout_ms;
         let mut inner = self.inner.exclusive_access();
-        inner.wait_queue.push_back(current_task().unwrap());
+        inner.wait_queue.push_back(Arc::clone(&task));
         drop(inner);
-        block_current_and_run_next();
-        mutex.lock();
+        add_timer(expire_ms, Arc::clone(&task), Arc::downgrade(self));
+        mutex.unlock();
+        self.sleep(&task);
+        // a timer still pending means a signal came first
+        remove_timer(&task) || get_time_ms() < expire_ms
+    }
+
+    /// Block until `task` is taken off the wait queue, or a signal to the
+    /// process takes it off
+    fn sleep(&self, task: &Arc<TaskControlBlock>) {
+        loop {
+            block_current_and_run_next();
+            let interrupted = current_signal_pending();
+            let mut inner = self.inner.exclusive_access();
+            let Some(pos) = inner.wait_queue.iter().position(|t| Arc::ptr_eq(t, task)) else {
+                return;
+            };
+            if interrupted {
+                inner.wait_queue.remove(pos);
+                return;
+            }
+        }
+    }
+
+    /// No task is waiting
+    pub fn is_idle(&self) -> bool {
+        self.inner.exclusive_access().wait_queue.is_empty()
+    }
+}
+
+impl TimedWait for Condvar {
+    fn cancel_wait(&self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let Some(pos) = inner.wait_queue.iter().position(|t| Arc::ptr_eq(t, task)) else {
+            return false;
+        };
+        inner.wait_queue.remove(pos);
+        true
     }
 }
 
@@ -0,0 +1,139 @@
+//! Deadlock avoidance for the locks and semaphores of a process, in the
+//! manner of the banker's algorithm: a task may only start waiting if every
+//! task could still get what it waits for in some order.
+//!
+//! A semaphore unit can also be given back by a task that never took one, a
+//! producer signalling a consumer. So while some task of the process is not
+//! blocked, it is counted on to up whatever semaphore is waited for.
+
+use crate::task::task::TaskControlBlock;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// What the detector needs to know of a lock or a semaphore. Tasks are told
+/// apart by the address of their control block.
+pub trait Resource {
+    /// Units nobody holds
+    fn available(&self) -> usize;
+    /// Units held, by task
+    fn holders(&self) -> Vec<(usize, usize)>;
+}
+
+/// The key a task is known by to `Resource::holders`
+pub fn task_key(task: &Arc<TaskControlBlock>) -> usize {
+    Arc::as_ptr(task) as usize
+}
+
+/// A lock or a semaphore of a process, by its slot in the process
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResourceId {
+    Mutex(usize),
+    Semaphore(usize),
+}
+
+/// A resource as it stands when a task asks to wait
+pub struct ResourceState {
+    pub id: ResourceId,
+    pub available: usize,
+    pub holders: Vec<(usize, usize)>,
+}
+
+impl ResourceState {
+    pub fn of(id: ResourceId, resource: &(impl Resource + ?Sized)) -> Self {
+        Self {
+            id,
+            available: resource.available(),
+            holders: resource.holders(),
+        }
+    }
+}
+
+/// Units of `id` that `task` holds
+fn held(resources: &[ResourceState], task: usize, id: ResourceId) -> usize {
+    resources
+        .iter()
+        .filter(|resource| resource.id == id)
+        .flat_map(|resource| resource.holders.iter())
+        .find(|(holder, _)| *holder == task)
+        .map_or(0, |&(_, units)| units)
+}
+
+/// The tasks of a process about to wait, with the resource each waits for
+/// and the units of it they held when they asked
+#[derive(Default)]
+pub struct DeadlockDetector {
+    waiting: BTreeMap<usize, (ResourceId, usize)>,
+}
+
+impl DeadlockDetector {
+    /// Let `task` wait for `wanted` if that leaves a way for all waiting
+    /// tasks to go on. `unblocked` are the tasks of the process not blocked
+    /// right now, some of them may be about to wait too.
+    pub fn request(
+        &mut self,
+        task: usize,
+        wanted: ResourceId,
+        resources: &[ResourceState],
+        unblocked: &[usize],
+    ) -> bool {
+        // a unit handed straight to a waiter moves from its need to what it
+        // holds before it gets round to `granted`
+        self.waiting
+            .retain(|&waiter, &mut (id, before)| held(resources, waiter, id) <= before);
+        self.waiting
+            .insert(task, (wanted, held(resources, task, wanted)));
+        let upper = unblocked.iter().any(|t| !self.waiting.contains_key(t));
+        if is_safe(resources, &self.waiting, upper) {
+            true
+        } else {
+            self.waiting.remove(&task);
+            false
+        }
+    }
+
+    /// `task` got what it waited for
+    pub fn granted(&mut self, task: usize) {
+        self.waiting.remove(&task);
+    }
+}
+
+/// Hand out the free units to whichever waiting task can use them, taking
+/// back all it holds once it is done, until nobody can go on. With an
+/// `upper` left running semaphores never run out.
+fn is_safe(
+    resources: &[ResourceState],
+    waiting: &BTreeMap<usize, (ResourceId, usize)>,
+    upper: bool,
+) -> bool {
+    let mut work: BTreeMap<ResourceId, usize> = BTreeMap::new();
+    let mut allocation: BTreeMap<usize, Vec<(ResourceId, usize)>> = BTreeMap::new();
+    for resource in resources {
+        work.insert(resource.id, resource.available);
+        for &(task, units) in &resource.holders {
+            allocation
+                .entry(task)
+                .or_default()
+                .push((resource.id, units));
+        }
+    }
+    let release = |work: &mut BTreeMap<ResourceId, usize>, task: &usize| {
+        for &(id, units) in allocation.get(task).into_iter().flatten() {
+            *work.entry(id).or_default() += units;
+        }
+    };
+    // tasks not waiting on anything will release what they hold
+    for task in allocation.keys().filter(|task| !waiting.contains_key(task)) {
+        release(&mut work, task);
+    }
+    let mut blocked: Vec<(usize, ResourceId)> =
+        waiting.iter().map(|(&t, &(r, _))| (t, r)).collect();
+    while let Some(pos) = blocked.iter().position(|(_, wanted)| {
+        work.get(wanted).is_some_and(|&units| units > 0)
+            || (upper && matches!(wanted, ResourceId::Semaphore(_)))
+    }) {
+        let (task, _) = blocked.swap_remove(pos);
+        release(&mut work, &task);
+    }
+    blocked.is_empty()
+}
@@ -1,9 +1,15 @@
 //! Synchronization and interior mutability primitives
 
 mod condvar;
+mod deadlock;
 mod mutex;
+mod semaphore;
+mod spin;
 mod up;
 
 pub use condvar::*;
+pub use deadlock::{task_key, DeadlockDetector, ResourceId, ResourceState};
 pub use mutex::*;
+pub use semaphore::Semaphore;
+pub use spin::{held_locks, SpinLock, SpinLockGuard};
 pub use up::UPSafeCell;
@@ -1,83 +1,125 @@
 use alloc::sync::Arc;
 
 use crate::task::{
-    manager::wakeup_task, processor::current_task, suspend_current_and_run_next,
-    task::TaskControlBlock,
+    current_signal_pending, manager::wakeup_task, processor::current_task,
+    suspend_current_and_run_next, task::TaskControlBlock,
 };
 
-use super::UPSafeCell;
+use super::deadlock::{task_key, Resource};
+use super::SpinLock;
 use crate::task::block_current_and_run_next;
 use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 
-pub trait Mutex: Sync + Send {
-    fn lock(&self);
+pub trait Mutex: Sync + Send + Resource {
+    /// Take the lock, false if a signal cut the wait short
+    fn lock(&self) -> bool;
     fn unlock(&self);
+    /// Held by some task right now
+    fn is_locked(&self) -> bool;
 }
 
 pub struct MutexSpin {
-    locked: UPSafeCell<bool>,
+    /// The holder, by `task_key`
+    locked: SpinLock<Option<usize>>,
 }
 
 impl MutexSpin {
     pub fn new() -> Self {
         MutexSpin {
-            locked: unsafe { UPSafeCell::new(false) },
+            locked: SpinLock::new(None),
         }
     }
 }
 
 impl Mutex for MutexSpin {
-    fn lock(&self) {
+    fn lock(&self) -> bool {
         loop {
             let mut locked = self.locked.exclusive_access();
-            if *locked {
+            if locked.is_some() {
                 drop(locked);
+                if current_signal_pending() {
+                    return false;
+                }
                 suspend_current_and_run_next();
                 continue;
             } else {
-                *locked = true;
-                break;
+                *locked = Some(task_key(&current_task().unwrap()));
+                return true;
             }
         }
     }
 
     fn unlock(&self) {
         let mut locked = self.locked.exclusive_access();
-        *locked = false;
+        *locked = None;
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.exclusive_access().is_some()
+    }
+}
+
+impl Resource for MutexSpin {
+    fn available(&self) -> usize {
+        self.locked.exclusive_access().is_none() as usize
+    }
+
+    fn holders(&self) -> Vec<(usize, usize)> {
+        self.locked
+            .exclusive_access()
+            .map(|task| (task, 1))
+            .into_iter()
+            .collect()
     }
 }
 
 pub struct MutexBlocking {
-    inner: UPSafeCell<MutexBlockingInner>,
+    inner: SpinLock<MutexBlockingInner>,
 }
 
 impl MutexBlocking {
     pub fn new() -> Self {
         MutexBlocking {
-            inner: unsafe {
-                UPSafeCell::new(MutexBlockingInner {
-                    locked: false,
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinLock::new(MutexBlockingInner {
+                locked: false,
+                owner: None,
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 }
 
 pub struct MutexBlockingInner {
     locked: bool,
+    /// The holder, by `task_key`
+    owner: Option<usize>,
     wait_queue: VecDeque<Arc<TaskControlBlock>>,
 }
 
 impl Mutex for MutexBlocking {
-    fn lock(&self) {
+    fn lock(&self) -> bool {
         let mut inner = self.inner.exclusive_access();
-        if inner.locked {
-            inner.wait_queue.push_back(current_task().unwrap());
-            drop(inner);
-            block_current_and_run_next();
-        } else {
+        let task = current_task().unwrap();
+        if !inner.locked {
             inner.locked = true;
+            inner.owner = Some(task_key(&task));
+            return true;
+        }
+        inner.wait_queue.push_back(Arc::clone(&task));
+        drop(inner);
+        loop {
+            block_current_and_run_next();
+            let interrupted = current_signal_pending();
+            let mut inner = self.inner.exclusive_access();
+            // `unlock` hands the lock over by taking the waiter off the queue
+            let Some(pos) = inner.wait_queue.iter().position(|t| Arc::ptr_eq(t, &task)) else {
+                return true;
+            };
+            if interrupted {
+                inner.wait_queue.remove(pos);
+                return false;
+            }
         }
     }
 
@@ -85,9 +127,31 @@ impl Mutex for MutexBlocking {
         let mut inner = self.inner.exclusive_access();
         assert!(inner.locked);
         if let Some(waking_task) = inner.wait_queue.pop_front() {
+            // handed over, the new holder owns it from here
+            inner.owner = Some(task_key(&waking_task));
             wakeup_task(waking_task);
         } else {
             inner.locked = false;
+            inner.owner = None;
         }
     }
+
+    fn is_locked(&self) -> bool {
+        self.inner.exclusive_access().locked
+    }
+}
+
+impl Resource for MutexBlocking {
+    fn available(&self) -> usize {
+        !self.inner.exclusive_access().locked as usize
+    }
+
+    fn holders(&self) -> Vec<(usize, usize)> {
+        self.inner
+            .exclusive_access()
+            .owner
+            .map(|task| (task, 1))
+            .into_iter()
+            .collect()
+    }
 }
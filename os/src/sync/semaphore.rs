@@ -0,0 +1,101 @@
+use super::deadlock::{task_key, Resource};
+use super::SpinLock;
+use crate::task::{
+    block_current_and_run_next, current_signal_pending, manager::wakeup_task,
+    processor::current_task, task::TaskControlBlock,
+};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+
+/// A counting semaphore, `down` blocks while no unit is left
+pub struct Semaphore {
+    pub inner: SpinLock<SemaphoreInner>,
+}
+
+pub struct SemaphoreInner {
+    /// Units left, or minus the number of tasks waiting for one
+    pub count: isize,
+    pub wait_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// Units taken by `down` and not yet given back, by `task_key`
+    pub holders: Vec<(usize, usize)>,
+}
+
+impl SemaphoreInner {
+    fn take(&mut self, task: usize) {
+        match self.holders.iter_mut().find(|(t, _)| *t == task) {
+            Some((_, units)) => *units += 1,
+            None => self.holders.push((task, 1)),
+        }
+    }
+
+    /// A unit given back by `task`, if it holds any
+    fn give_back(&mut self, task: usize) {
+        if let Some(pos) = self.holders.iter().position(|(t, _)| *t == task) {
+            self.holders[pos].1 -= 1;
+            if self.holders[pos].1 == 0 {
+                self.holders.swap_remove(pos);
+            }
+        }
+    }
+}
+
+impl Semaphore {
+    pub fn new(res_count: usize) -> Self {
+        Self {
+            inner: SpinLock::new(SemaphoreInner {
+                count: res_count as isize,
+                wait_queue: VecDeque::new(),
+                holders: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn up(&self) {
+        let mut inner = self.inner.exclusive_access();
+        inner.give_back(task_key(&current_task().unwrap()));
+        inner.count += 1;
+        if inner.count <= 0 {
+            // the unit goes straight to the first waiter
+            if let Some(task) = inner.wait_queue.pop_front() {
+                inner.take(task_key(&task));
+                wakeup_task(task);
+            }
+        }
+    }
+
+    /// Take a unit, false if a signal cut the wait short
+    pub fn down(&self) -> bool {
+        let task = current_task().unwrap();
+        let mut inner = self.inner.exclusive_access();
+        inner.count -= 1;
+        if inner.count >= 0 {
+            inner.take(task_key(&task));
+            return true;
+        }
+        inner.wait_queue.push_back(Arc::clone(&task));
+        drop(inner);
+        loop {
+            block_current_and_run_next();
+            let interrupted = current_signal_pending();
+            let mut inner = self.inner.exclusive_access();
+            // `up` hands a unit over by taking the waiter off the queue
+            let Some(pos) = inner.wait_queue.iter().position(|t| Arc::ptr_eq(t, &task)) else {
+                return true;
+            };
+            if interrupted {
+                inner.wait_queue.remove(pos);
+                inner.count += 1;
+                return false;
+            }
+        }
+    }
+}
+
+impl Resource for Semaphore {
+    fn available(&self) -> usize {
+        self.inner.exclusive_access().count.max(0) as usize
+    }
+
+    fn holders(&self) -> Vec<(usize, usize)> {
+        self.inner.exclusive_access().holders.clone()
+    }
+}
@@ -0,0 +1,85 @@
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::config::MAX_HARTS;
+use crate::smp::hart_id;
+
+/// `SpinLock`s each hart holds right now
+static HELD: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Number of `SpinLock`s the calling hart holds. A task holding one must not
+/// sleep: another task taking it on the same hart would spin for good.
+pub fn held_locks() -> usize {
+    HELD[hart_id()].load(Ordering::Relaxed)
+}
+
+/// A spin lock for state shared between harts.
+///
+/// The kernel runs with supervisor interrupts disabled, so a hart holding a
+/// `SpinLock` cannot be interrupted and re-enter it.
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn exclusive_access(&self) -> SpinLockGuard<'_, T> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+        HELD[hart_id()].fetch_add(1, Ordering::Relaxed);
+        SpinLockGuard { lock: self }
+    }
+
+    pub fn try_exclusive_access(&self) -> Option<SpinLockGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| {
+                HELD[hart_id()].fetch_add(1, Ordering::Relaxed);
+                SpinLockGuard { lock: self }
+            })
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        HELD[hart_id()].fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -1,5 +1,8 @@
 use core::cell::RefCell;
 use core::cell::RefMut;
+
+/// Interior mutability for state that is only ever touched by one hart, such
+/// as the per-hart `Processor`. Shared state must use `SpinLock` instead.
 pub struct UPSafeCell<T> {
     inner: RefCell<T>,
 }
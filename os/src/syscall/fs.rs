@@ -1,9 +1,13 @@
 use crate::{
     fs::*,
-    mm::{translate_byte_buffer, translated_refmut, translated_str, UserBuffer},
+    mm::{translate_byte_buffer, translate_byte_buffer_mut, translated_refmut, translated_str},
+    syscall::RESTART,
     task::processor::{current_process, current_user_token},
+    task::send_group_signal,
+    task::signal::{SignalFlags, SIGTTIN, SIG_IGN},
 };
-use easy_fs::block_cache_sync_all;
+use alloc::sync::Arc;
+use easy_fs::{block_cache_sync_all, Inode, Stat};
 
 pub fn sys_write(fd: usize, buffer: *const u8, len: usize) -> isize {
     let token = current_user_token();
@@ -15,9 +19,7 @@ pub fn sys_write(fd: usize, buffer: *const u8, len: usize) -> isize {
     if let Some(file) = &inner.fd_table[fd] {
         let file = file.clone();
         drop(inner);
-        let ret = file.write(UserBuffer::new(translate_byte_buffer(token, buffer, len))) as isize;
-        block_cache_sync_all();
-        ret
+        file.write(translate_byte_buffer(token, buffer, len)) as isize
     } else {
         -1
     }
@@ -33,21 +35,137 @@ pub fn sys_read(fd: usize, buffer: *const u8, len: usize) -> isize {
     if let Some(file) = &inner.fd_table[fd] {
         let file = file.clone();
         drop(inner);
-        file.read(UserBuffer::new(translate_byte_buffer(token, buffer, len))) as isize
+        if file.is_tty() {
+            if let Some(result) = background_read() {
+                return result;
+            }
+        }
+        file.read(translate_byte_buffer_mut(token, buffer as *mut u8, len)) as isize
     } else {
         -1
     }
 }
 
-pub fn sys_open(id: usize, path: *const u8, flags: u32) -> isize {
+/// Job control for a read from the console. A process outside the
+/// foreground group stops its group with SIGTTIN and reads again once
+/// continued. If SIGTTIN is ignored or blocked nothing would stop it, and the
+/// read fails.
+fn background_read() -> Option<isize> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let pgid = inner.pgid;
+    let ignored = inner.signal_mask.contains(SignalFlags::SIGTTIN)
+        || inner.signal_actions[SIGTTIN].handler == SIG_IGN;
+    drop(inner);
+    let foreground = tty_get_foreground();
+    if foreground == 0 || foreground == pgid {
+        return None;
+    }
+    if ignored {
+        return Some(-1);
+    }
+    send_group_signal(pgid, SIGTTIN);
+    Some(RESTART)
+}
+
+/// The file open as `fd` in the current process
+fn file_of(fd: usize) -> Option<Arc<dyn File + Send + Sync>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    match inner.fd_table.get(fd) {
+        Some(Some(file)) => Some(Arc::clone(file)),
+        _ => None,
+    }
+}
+
+/// Move the offset of `fd`, returns the new offset
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    match file_of(fd).and_then(|file| file.seek(offset, whence)) {
+        Some(offset) => offset as isize,
+        None => -1,
+    }
+}
+
+/// Read at `offset` without moving the offset of `fd`
+pub fn sys_pread64(fd: usize, buffer: *mut u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let Some(file) = file_of(fd) else {
+        return -1;
+    };
+    match file.read_at(offset, translate_byte_buffer_mut(token, buffer, len)) {
+        Some(read_size) => read_size as isize,
+        None => -1,
+    }
+}
+
+pub fn sys_pwrite64(fd: usize, buffer: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let Some(file) = file_of(fd) else {
+        return -1;
+    };
+    match file.write_at(offset, translate_byte_buffer(token, buffer, len)) {
+        Some(write_size) => write_size as isize,
+        None => -1,
+    }
+}
+
+pub fn sys_ftruncate(fd: usize, len: usize) -> isize {
+    let Some(file) = file_of(fd) else {
+        return -1;
+    };
+    if file.truncate(len) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Write every dirty cached block back to the disk
+pub fn sys_sync() -> isize {
+    block_cache_sync_all();
+    0
+}
+
+/// Like `sync`, for a file on the disk. The cache doesn't know which blocks
+/// belong to which file, and a file's data is no use without the metadata
+/// naming it, so this writes back every dirty block, not only the file's.
+pub fn sys_fsync(fd: usize) -> isize {
+    match file_of(fd) {
+        Some(file) if file.inode().is_some() => sys_sync(),
+        _ => -1,
+    }
+}
+
+/// `dirfd` value naming the working directory
+const AT_FDCWD: isize = -100;
+
+/// The directory relative paths are resolved from, the working directory or a
+/// directory open as `dirfd`
+fn dir_of(dirfd: isize) -> Option<Arc<Inode>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if dirfd == AT_FDCWD {
+        return Some(Arc::clone(&inner.cwd));
+    }
+    let inode = match inner.fd_table.get(dirfd as usize) {
+        Some(Some(file)) => file.inode(),
+        _ => None,
+    };
+    drop(inner);
+    inode.filter(|inode| inode.is_dir())
+}
+
+pub fn sys_openat(dirfd: isize, path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
     let path = translated_str(token, path);
-    if let Some(inode) = open_file(id, path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
+    let (Some(dir), Some(flags)) = (dir_of(dirfd), OpenFlags::from_bits(flags)) else {
+        return -1;
+    };
+    if let Some(inode) = open_file(&dir, path.as_str(), flags) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
         inner.fd_table[fd] = Some(inode);
-        block_cache_sync_all();
         fd as isize
     } else {
         -1
@@ -76,9 +194,10 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
     inner.fd_table[read_fd] = Some(pipe_read);
     let write_fd = inner.alloc_fd();
     inner.fd_table[write_fd] = Some(pipe_write);
+    // writing to user memory may fault in a page, which locks the process
+    drop(inner);
     *translated_refmut(token, pipe) = read_fd;
     *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
-    block_cache_sync_all();
     0
 }
 
@@ -93,41 +212,226 @@ pub fn sys_dup(fd: usize) -> isize {
     }
     let new_fd = inner.alloc_fd();
     inner.fd_table[new_fd] = Some(inner.fd_table[fd].clone().unwrap());
-    block_cache_sync_all();
     new_fd as isize
 }
 
-pub fn sys_mkdir(id: usize, path: *const u8) -> isize {
+pub fn sys_mkdir(dirfd: isize, path: *const u8) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
-    let ret = mkdir(id, path.as_str());
-    block_cache_sync_all();
-    ret
+    let Some(dir) = dir_of(dirfd) else {
+        return -1;
+    };
+    mkdir(&dir, path.as_str())
 }
 
-pub fn sys_ls(id: usize) -> isize {
-    ls(id)
+/// Fill `buf` with the next entries of the directory open as `fd`, returns
+/// the bytes filled, 0 at the end
+pub fn sys_getdents64(fd: usize, buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -1,
+    };
+    drop(inner);
+    let Some(records) = file.getdents(len) else {
+        return -1;
+    };
+    for (byte, ptr) in records
+        .iter()
+        .zip(translate_byte_buffer_mut(token, buf, records.len()))
+    {
+        unsafe {
+            *ptr = *byte;
+        }
+    }
+    records.len() as isize
 }
 
-pub fn sys_cd(id: usize, path: *const u8) -> isize {
+pub fn sys_chdir(path: *const u8) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
-    cd(id, path.as_str())
+    let process = current_process();
+    let cwd = Arc::clone(&process.inner_exclusive_access().cwd);
+    match cwd.cd(path.as_str()) {
+        Some(dir) if dir.is_dir() => {
+            process.inner_exclusive_access().cwd = dir;
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Copy the absolute path of the working directory and a NUL into `buf`,
+/// returns the bytes copied
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let cwd = Arc::clone(&process.inner_exclusive_access().cwd);
+    let Some(mut path) = cwd.path() else {
+        return -1;
+    };
+    path.push('\0');
+    if path.len() > len {
+        return -1;
+    }
+    for (byte, ptr) in path
+        .bytes()
+        .zip(translate_byte_buffer_mut(token, buf, path.len()))
+    {
+        unsafe {
+            *ptr = byte;
+        }
+    }
+    path.len() as isize
 }
 
-pub fn sys_rm(id: usize, path: *const u8) -> isize {
+pub fn sys_rm(dirfd: isize, path: *const u8) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
-    let ret = rm(id, path.as_str());
-    block_cache_sync_all();
-    ret
+    let Some(dir) = dir_of(dirfd) else {
+        return -1;
+    };
+    rm(&dir, path.as_str())
 }
 
-pub fn sys_mv(id: usize, src: *const u8, dst: *const u8) -> isize {
+pub fn sys_mv(dirfd: isize, src: *const u8, dst: *const u8) -> isize {
     let token = current_user_token();
     let src = translated_str(token, src);
     let dst = translated_str(token, dst);
-    let ret = mv(id, src.as_str(), dst.as_str());
-    block_cache_sync_all();
-    ret
+    let Some(dir) = dir_of(dirfd) else {
+        return -1;
+    };
+    mv(&dir, src.as_str(), dst.as_str())
+}
+
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let inode = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.inode(),
+        _ => None,
+    };
+    drop(inner);
+    match inode {
+        Some(inode) => {
+            *translated_refmut(token, st) = inode.stat();
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Stat `path`, relative to `dirfd`
+pub fn sys_stat(dirfd: isize, path: *const u8, st: *mut Stat, flags: u32) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let Some(dir) = dir_of(dirfd) else {
+        return -1;
+    };
+    match stat(&dir, path.as_str(), flags) {
+        Some(stat) => {
+            *translated_refmut(token, st) = stat;
+            0
+        }
+        None => -1,
+    }
+}
+
+pub fn sys_linkat(
+    old_dirfd: isize,
+    old_path: *const u8,
+    new_dirfd: isize,
+    new_path: *const u8,
+) -> isize {
+    let token = current_user_token();
+    let old_path = translated_str(token, old_path);
+    let new_path = translated_str(token, new_path);
+    let (Some(old_dir), Some(new_dir)) = (dir_of(old_dirfd), dir_of(new_dirfd)) else {
+        return -1;
+    };
+    link(&old_dir, old_path.as_str(), &new_dir, new_path.as_str())
+}
+
+pub fn sys_unlinkat(dirfd: isize, path: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let Some(dir) = dir_of(dirfd) else {
+        return -1;
+    };
+    unlink(&dir, path.as_str(), flags)
+}
+
+pub fn sys_symlinkat(target: *const u8, dirfd: isize, path: *const u8) -> isize {
+    let token = current_user_token();
+    let target = translated_str(token, target);
+    let path = translated_str(token, path);
+    let Some(dir) = dir_of(dirfd) else {
+        return -1;
+    };
+    symlink(target.as_str(), &dir, path.as_str())
+}
+
+/// Copy the target of a symbolic link without a trailing NUL, returns the
+/// bytes copied
+pub fn sys_readlinkat(dirfd: isize, path: *const u8, buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let Some(target) = dir_of(dirfd).and_then(|dir| readlink(&dir, path.as_str())) else {
+        return -1;
+    };
+    let len = len.min(target.len());
+    for (byte, ptr) in target
+        .bytes()
+        .zip(translate_byte_buffer_mut(token, buf, len))
+    {
+        unsafe {
+            *ptr = byte;
+        }
+    }
+    len as isize
+}
+
+/// Whether `fd` of the current process refers to the console
+fn is_tty(fd: usize) -> bool {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    matches!(inner.fd_table.get(fd), Some(Some(file)) if file.is_tty())
+}
+
+/// Console modes as `TtyFlags` bits
+pub fn sys_tcgetattr(fd: usize) -> isize {
+    if !is_tty(fd) {
+        return -1;
+    }
+    tty_get_flags().bits() as isize
+}
+
+pub fn sys_tcsetattr(fd: usize, flags: u32) -> isize {
+    if !is_tty(fd) {
+        return -1;
+    }
+    let Some(flags) = TtyFlags::from_bits(flags) else {
+        return -1;
+    };
+    tty_set_flags(flags);
+    0
+}
+
+pub fn sys_tcgetpgrp(fd: usize) -> isize {
+    if !is_tty(fd) {
+        return -1;
+    }
+    tty_get_foreground() as isize
+}
+
+/// Hand the console to process group `pgid`, which has to exist
+pub fn sys_tcsetpgrp(fd: usize, pgid: usize) -> isize {
+    if !is_tty(fd) || !send_group_signal(pgid, 0) {
+        return -1;
+    }
+    tty_set_foreground(pgid);
+    0
 }
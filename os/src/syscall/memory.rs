@@ -0,0 +1,222 @@
+use crate::config::{MMAP_BASE, MMAP_END, PAGE_SIZE};
+use crate::mm::{MapPermission, MmapFile, VirtAddr};
+use crate::task::processor::current_process;
+use bitflags::bitflags;
+
+bitflags! {
+    pub struct MmapProt: usize {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: usize {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+impl MmapProt {
+    /// PROT_NONE is not supported, and a writable page must be readable
+    fn to_map_perm(self) -> Option<MapPermission> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut perm = MapPermission::U;
+        if self.intersects(MmapProt::READ | MmapProt::WRITE) {
+            perm |= MapPermission::R;
+        }
+        if self.contains(MmapProt::WRITE) {
+            perm |= MapPermission::W;
+        }
+        if self.contains(MmapProt::EXEC) {
+            perm |= MapPermission::X;
+        }
+        Some(perm)
+    }
+}
+
+/// Range `[start, start + len)` of a request, None if it is empty, `start`
+/// is not page aligned or the range reaches past the mmap region, the top of
+/// what user code maps.
+fn user_range(start: usize, len: usize) -> Option<(VirtAddr, VirtAddr)> {
+    let end = start.checked_add(len)?;
+    if start % PAGE_SIZE != 0 || len == 0 || end > MMAP_END {
+        return None;
+    }
+    Some((VirtAddr::from(start), VirtAddr::from(end)))
+}
+
+/// Like `user_range`, for a range that has to lie in the mmap region
+fn mmap_range(start: usize, len: usize) -> Option<(VirtAddr, VirtAddr)> {
+    user_range(start, len).filter(|_| start >= MMAP_BASE)
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if addr == 0 {
+        return inner.program_brk as isize;
+    }
+    if inner.set_brk(addr) {
+        addr as isize
+    } else {
+        -1
+    }
+}
+
+/// Grow (or shrink) the heap by `increment` bytes and return the old break
+pub fn sys_sbrk(increment: isize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old_brk = inner.program_brk;
+    let new_brk = old_brk as isize + increment;
+    if new_brk < 0 || !inner.set_brk(new_brk as usize) {
+        return -1;
+    }
+    old_brk as isize
+}
+
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    let Some(prot) = MmapProt::from_bits(prot) else {
+        return -1;
+    };
+    let Some(flags) = MmapFlags::from_bits(flags) else {
+        return -1;
+    };
+    let Some(map_perm) = prot.to_map_perm() else {
+        return -1;
+    };
+    // exactly one of MAP_SHARED and MAP_PRIVATE
+    if flags.contains(MmapFlags::SHARED) == flags.contains(MmapFlags::PRIVATE) {
+        return -1;
+    }
+    // nothing longer fits, and the page count can't overflow
+    if len == 0 || len > MMAP_END - MMAP_BASE {
+        return -1;
+    }
+    let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = if flags.contains(MmapFlags::ANONYMOUS) {
+        if flags.contains(MmapFlags::SHARED) {
+            return -1;
+        }
+        None
+    } else {
+        if fd >= inner.fd_table.len() || offset % PAGE_SIZE != 0 {
+            return -1;
+        }
+        let Some(file) = inner.fd_table[fd].as_ref() else {
+            return -1;
+        };
+        let Some(inode) = file.inode() else {
+            return -1;
+        };
+        let shared = flags.contains(MmapFlags::SHARED);
+        if !file.readable() || (shared && prot.contains(MmapProt::WRITE) && !file.writable()) {
+            return -1;
+        }
+        Some(MmapFile {
+            inode,
+            offset,
+            shared,
+            writable: file.writable(),
+        })
+    };
+    let start = if addr != 0 {
+        let Some((start, end)) = mmap_range(addr, len) else {
+            return -1;
+        };
+        if !inner.memory_set.is_free(start.floor(), end.ceil()) {
+            return -1;
+        }
+        start
+    } else if flags.contains(MmapFlags::FIXED) {
+        return -1;
+    } else {
+        let Some(vpn) = inner.memory_set.find_free_range(
+            VirtAddr::from(MMAP_BASE).floor(),
+            VirtAddr::from(MMAP_END).floor(),
+            pages,
+        ) else {
+            return -1;
+        };
+        vpn.into()
+    };
+    let end = VirtAddr::from(usize::from(start) + pages * PAGE_SIZE);
+    match file {
+        Some(file) => inner
+            .memory_set
+            .insert_file_area(start, end, map_perm, file),
+        None => inner.memory_set.insert_lazy_area(start, end, map_perm),
+    }
+    usize::from(start) as isize
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    let Some((start, end)) = mmap_range(addr, len) else {
+        return -1;
+    };
+    let process = current_process();
+    let Some(writebacks) = process
+        .inner_exclusive_access()
+        .memory_set
+        .remove_areas_in(start.floor(), end.ceil())
+    else {
+        return -1;
+    };
+    for writeback in writebacks {
+        writeback.write();
+    }
+    0
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    let Some(map_perm) = MmapProt::from_bits(prot).and_then(MmapProt::to_map_perm) else {
+        return -1;
+    };
+    let Some((start, end)) = user_range(addr, len) else {
+        return -1;
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner
+        .memory_set
+        .protect_areas_in(start.floor(), end.ceil(), map_perm)
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Write the shared file mappings in `[addr, addr + len)` back to their files
+pub fn sys_msync(addr: usize, len: usize, _flags: usize) -> isize {
+    let Some((start, end)) = mmap_range(addr, len) else {
+        return -1;
+    };
+    let process = current_process();
+    let Some(writebacks) = process
+        .inner_exclusive_access()
+        .memory_set
+        .take_dirty_in(start.floor(), end.ceil())
+    else {
+        return -1;
+    };
+    for writeback in writebacks {
+        writeback.write();
+    }
+    0
+}
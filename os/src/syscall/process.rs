@@ -4,10 +4,14 @@ use crate::mm::translated_ref;
 use crate::mm::translated_refmut;
 use crate::mm::translated_str;
 use crate::task::exit_current_and_run_next;
+use crate::task::manager::{pid2process, TASK_MANAGER};
 use crate::task::processor::current_process;
 use crate::task::processor::current_task;
 use crate::task::processor::current_user_token;
+use crate::task::scheduler::{SchedPolicy, MAX_PRIORITY, MIN_PRIORITY};
+use crate::task::send_group_signal;
 use crate::task::suspend_current_and_run_next;
+use crate::task::INITPROC;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -43,7 +47,7 @@ pub fn sys_fork() -> isize {
     trap_cx.x[10] = 0;
     new_pid as isize
 }
-pub fn sys_exec(id: usize, path: *const u8, mut args: *const usize) -> isize {
+pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
     let mut args_vec: Vec<String> = Vec::new();
@@ -58,9 +62,10 @@ pub fn sys_exec(id: usize, path: *const u8, mut args: *const usize) -> isize {
             }
         }
     }
-    if let Some(data) = open_file(id, path.as_str(), OpenFlags::RDONLY) {
+    let process = current_process();
+    let cwd = Arc::clone(&process.inner_exclusive_access().cwd);
+    if let Some(data) = open_file(&cwd, path.as_str(), OpenFlags::RDONLY) {
         let all_data = data.read_all();
-        let process = current_process();
         let argc = args_vec.len();
         process.exec(all_data.as_slice(), args_vec);
         argc as isize
@@ -69,7 +74,13 @@ pub fn sys_exec(id: usize, path: *const u8, mut args: *const usize) -> isize {
     }
 }
 
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+/// Also report children stopped by a signal, see `sys_waitpid`
+const WUNTRACED: usize = 2;
+
+/// Reap an exited child, -2 means the child is still running. With
+/// `WUNTRACED`, a child stopped since the last report yields -3 and its stop
+/// signal in `exit_code_ptr`.
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32, options: usize) -> isize {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
     if inner
@@ -80,22 +91,105 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     {
         return -1;
     }
+    // a zombie is only reaped once its main thread has switched off its hart
     let pair = inner.children.iter().enumerate().find(|(_, p)| {
-        p.inner_exclusive_access().is_zombie && (pid == -1 || pid as usize == p.getpid())
+        let p_inner = p.inner_exclusive_access();
+        p_inner.is_zombie
+            && !p_inner.tasks[0]
+                .as_ref()
+                .is_some_and(|t| t.inner_exclusive_access().on_cpu)
+            && (pid == -1 || pid as usize == p.getpid())
     });
     if let Some((idx, _)) = pair {
         let child = inner.children.remove(idx);
         assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         let exit_code = child.inner_exclusive_access().exit_code;
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
-    } else {
-        -2
+        let token = inner.memory_set.token();
+        // writing to user memory may fault in a page, which locks the process
+        drop(inner);
+        *translated_refmut(token, exit_code_ptr) = exit_code;
+        return found_pid as isize;
     }
+    if options & WUNTRACED != 0 {
+        let stop_signal = inner
+            .children
+            .iter()
+            .filter(|p| pid == -1 || pid as usize == p.getpid())
+            .find_map(|p| p.inner_exclusive_access().stop_signal.take());
+        if let Some(signum) = stop_signal {
+            let token = inner.memory_set.token();
+            drop(inner);
+            *translated_refmut(token, exit_code_ptr) = signum as i32;
+            return -3;
+        }
+    }
+    -2
 }
 
-pub fn sys_kill() -> isize {
-    exit_current_and_run_next(-4);
+/// Move process `pid` (0 for the caller) into group `pgid` (0 for a new group
+/// named after `pid`). Only the caller and its children can be moved, and
+/// only into a new group or one that exists.
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    let process = current_process();
+    let target = if pid == 0 || pid == process.getpid() {
+        Arc::clone(&process)
+    } else {
+        let inner = process.inner_exclusive_access();
+        let Some(child) = inner.children.iter().find(|p| p.getpid() == pid) else {
+            return -1;
+        };
+        Arc::clone(child)
+    };
+    let pgid = if pgid == 0 { target.getpid() } else { pgid };
+    if pgid != target.getpid() && !send_group_signal(pgid, 0) {
+        return -1;
+    }
+    target.inner_exclusive_access().pgid = pgid;
     0
 }
+
+/// Process group of process `pid`, 0 for the caller
+pub fn sys_getpgid(pid: usize) -> isize {
+    let process = if pid == 0 {
+        current_process()
+    } else {
+        let Some(process) = pid2process(pid) else {
+            return -1;
+        };
+        process
+    };
+    let pgid = process.inner_exclusive_access().pgid;
+    pgid as isize
+}
+
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < MIN_PRIORITY as isize || prio > MAX_PRIORITY as isize {
+        return -1;
+    }
+    current_task().unwrap().inner_exclusive_access().priority = prio as usize;
+    prio
+}
+
+/// Switch every hart to scheduling policy `policy`, returns the previous one.
+/// The policy is the whole machine's, only initproc may set it.
+pub fn sys_sched_setscheduler(policy: usize) -> isize {
+    if !Arc::ptr_eq(&current_process(), &INITPROC) {
+        return -1;
+    }
+    match SchedPolicy::from_id(policy) {
+        Some(policy) => TASK_MANAGER.exclusive_access().set_policy(policy) as isize,
+        None => -1,
+    }
+}
+
+/// Lower the priority of the current thread by `increment` (a negative value
+/// raises it) and return the new priority.
+pub fn sys_nice(increment: isize) -> isize {
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    let prio = (task_inner.priority as isize - increment)
+        .clamp(MIN_PRIORITY as isize, MAX_PRIORITY as isize);
+    task_inner.priority = prio as usize;
+    prio
+}
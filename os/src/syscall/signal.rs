@@ -0,0 +1,103 @@
+use crate::mm::{translated_ref, translated_refmut};
+use crate::task::manager::pid2process;
+use crate::task::processor::{current_process, current_task, current_user_token};
+use crate::task::signal::{SignalAction, SignalFlags};
+use crate::task::{send_group_signal, send_signal};
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// Send `signum` to process `pid`, or to every process of group `-pid` when
+/// `pid` is negative. A zero `signum` only checks that the target exists.
+pub fn sys_kill(pid: isize, signum: usize) -> isize {
+    if signum != 0 && SignalFlags::from_signum(signum).is_none() {
+        return -1;
+    }
+    if pid < 0 {
+        let Some(pgid) = pid.checked_neg() else {
+            return -1;
+        };
+        return if send_group_signal(pgid as usize, signum) {
+            0
+        } else {
+            -1
+        };
+    }
+    let Some(process) = pid2process(pid as usize) else {
+        return -1;
+    };
+    if signum != 0 {
+        send_signal(&process, signum);
+    }
+    0
+}
+
+pub fn sys_sigaction(
+    signum: usize,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    let Some(flag) = SignalFlags::from_signum(signum) else {
+        return -1;
+    };
+    if SignalFlags::UNCATCHABLE.contains(flag) {
+        return -1;
+    }
+    let token = current_user_token();
+    let process = current_process();
+    // never touch user memory with the process locked
+    let old = process.inner_exclusive_access().signal_actions[signum];
+    if !old_action.is_null() {
+        *translated_refmut(token, old_action) = old;
+    }
+    if !action.is_null() {
+        let mut new = *translated_ref(token, action);
+        new.mask = SignalFlags::from_bits_truncate(new.mask.bits()) - SignalFlags::UNCATCHABLE;
+        process.inner_exclusive_access().signal_actions[signum] = new;
+    }
+    0
+}
+
+pub fn sys_sigprocmask(how: usize, set: *const u32, old_set: *mut u32) -> isize {
+    let token = current_user_token();
+    let set = if set.is_null() {
+        None
+    } else {
+        Some(
+            SignalFlags::from_bits_truncate(*translated_ref(token, set)) - SignalFlags::UNCATCHABLE,
+        )
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old = inner.signal_mask;
+    if let Some(set) = set {
+        match how {
+            SIG_BLOCK => inner.signal_mask |= set,
+            SIG_UNBLOCK => inner.signal_mask.remove(set),
+            SIG_SETMASK => inner.signal_mask = set,
+            _ => return -1,
+        }
+    }
+    drop(inner);
+    if !old_set.is_null() {
+        *translated_refmut(token, old_set) = old.bits();
+    }
+    0
+}
+
+/// Leave a signal handler, restoring the context it interrupted
+pub fn sys_sigreturn() -> isize {
+    let task = current_task().unwrap();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
+    let Some((trap_cx, mask)) = task_inner.signal_backup.take() else {
+        return -1;
+    };
+    process_inner.signal_mask = mask;
+    let a0 = trap_cx.x[10];
+    *task_inner.get_trap_cx() = trap_cx;
+    // the syscall return value goes to a0, hand back the interrupted one
+    a0 as isize
+}
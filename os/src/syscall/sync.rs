@@ -1,12 +1,88 @@
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 
 use crate::{
-    sync::{Condvar, Mutex, MutexBlocking, MutexSpin},
-    task::processor::current_process,
+    sync::{
+        task_key, Condvar, Mutex, MutexBlocking, MutexSpin, ResourceId, ResourceState, Semaphore,
+    },
+    task::processor::{current_process, current_task},
+    task::task::TaskStatus,
 };
 
 use super::process;
 
+/// Returned instead of waiting when the wait could never end
+const DEADLOCK: isize = -0xdead;
+
+/// 1 turns deadlock detection on for the process, 0 off
+pub fn sys_enable_deadlock_detect(enabled: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    match enabled {
+        0 => process_inner.deadlock = None,
+        1 => {
+            process_inner.deadlock.get_or_insert_with(Default::default);
+        }
+        _ => return -1,
+    }
+    0
+}
+
+/// With detection on, let the current task wait for `wanted` only if that
+/// cannot deadlock the process
+fn request(wanted: ResourceId) -> bool {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let process_inner = &mut *process_inner;
+    let Some(detector) = process_inner.deadlock.as_mut() else {
+        return true;
+    };
+    let mutexes = process_inner
+        .mutex_list
+        .iter()
+        .enumerate()
+        .filter_map(|(id, mutex)| {
+            Some(ResourceState::of(ResourceId::Mutex(id), &**mutex.as_ref()?))
+        });
+    let semaphores = process_inner
+        .semaphore_list
+        .iter()
+        .enumerate()
+        .filter_map(|(id, sem)| {
+            Some(ResourceState::of(
+                ResourceId::Semaphore(id),
+                &**sem.as_ref()?,
+            ))
+        });
+    let resources: Vec<ResourceState> = mutexes.chain(semaphores).collect();
+    let unblocked: Vec<usize> = process_inner
+        .tasks
+        .iter()
+        .flatten()
+        .filter(|task| {
+            matches!(
+                task.inner_exclusive_access().task_status,
+                TaskStatus::Ready | TaskStatus::Running
+            )
+        })
+        .map(task_key)
+        .collect();
+    detector.request(
+        task_key(&current_task().unwrap()),
+        wanted,
+        &resources,
+        &unblocked,
+    )
+}
+
+/// The wait `request` let through is over
+fn granted() {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    if let Some(detector) = process_inner.deadlock.as_mut() {
+        detector.granted(task_key(&current_task().unwrap()));
+    }
+}
+
 pub fn sys_mutex_create(blocking: bool) -> isize {
     let process = current_process();
     let mutex: Option<Arc<dyn Mutex>> = if !blocking {
@@ -30,26 +106,104 @@ pub fn sys_mutex_create(blocking: bool) -> isize {
     }
 }
 
-pub fn sys_mutex_lock(mutex_id: usize) -> isize {
+/// The mutex `mutex_id` of the current process, if there is one
+fn mutex(mutex_id: usize) -> Option<Arc<dyn Mutex>> {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
-    drop(process_inner);
-    drop(process);
-    mutex.lock();
-    0
+    process_inner.mutex_list.get(mutex_id)?.clone()
+}
+
+/// Take the mutex `mutex_id`, or `DEADLOCK` if detection refused the wait
+fn lock(mutex_id: usize, mutex: &dyn Mutex) -> isize {
+    if !request(ResourceId::Mutex(mutex_id)) {
+        return DEADLOCK;
+    }
+    let locked = mutex.lock();
+    granted();
+    if locked {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_mutex_lock(mutex_id: usize) -> isize {
+    let Some(mutex) = mutex(mutex_id) else {
+        return -1;
+    };
+    lock(mutex_id, &*mutex)
 }
 
 pub fn sys_mutex_unlock(mutex_id: usize) -> isize {
+    let Some(mutex) = mutex(mutex_id) else {
+        return -1;
+    };
+    mutex.unlock();
+    0
+}
+
+/// Free the slot of a mutex nobody holds, its id may be handed out again
+pub fn sys_mutex_destroy(mutex_id: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    match process_inner.mutex_list.get_mut(mutex_id) {
+        Some(slot) if slot.as_ref().is_some_and(|mutex| !mutex.is_locked()) => {
+            *slot = None;
+            0
+        }
+        _ => -1,
+    }
+}
+
+pub fn sys_semaphore_create(res_count: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let semaphore = Some(Arc::new(Semaphore::new(res_count)));
+    let id = if let Some(id) = process_inner
+        .semaphore_list
+        .iter()
+        .position(|item| item.is_none())
+    {
+        process_inner.semaphore_list[id] = semaphore;
+        id
+    } else {
+        process_inner.semaphore_list.push(semaphore);
+        process_inner.semaphore_list.len() - 1
+    };
+    id as isize
+}
+
+/// The semaphore `sem_id` of the current process, if there is one
+fn semaphore(sem_id: usize) -> Option<Arc<Semaphore>> {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
-    drop(process_inner);
-    drop(process);
-    mutex.unlock();
+    process_inner.semaphore_list.get(sem_id)?.clone()
+}
+
+pub fn sys_semaphore_up(sem_id: usize) -> isize {
+    let Some(semaphore) = semaphore(sem_id) else {
+        return -1;
+    };
+    semaphore.up();
     0
 }
 
+pub fn sys_semaphore_down(sem_id: usize) -> isize {
+    let Some(semaphore) = semaphore(sem_id) else {
+        return -1;
+    };
+    if !request(ResourceId::Semaphore(sem_id)) {
+        return DEADLOCK;
+    }
+    let taken = semaphore.down();
+    granted();
+    if taken {
+        0
+    } else {
+        -1
+    }
+}
+
 pub fn sys_condvar_create() -> isize {
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
@@ -71,22 +225,58 @@ pub fn sys_condvar_create() -> isize {
     id as isize
 }
 
-pub fn sys_condvar_signal(condvar_id: usize) -> isize {
+/// The condvar `condvar_id` of the current process, if there is one
+fn condvar(condvar_id: usize) -> Option<Arc<Condvar>> {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
-    drop(process_inner);
-    drop(process);
+    process_inner.condvar_list.get(condvar_id)?.clone()
+}
+
+pub fn sys_condvar_signal(condvar_id: usize) -> isize {
+    let Some(condvar) = condvar(condvar_id) else {
+        return -1;
+    };
     condvar.signal();
     0
 }
 
+pub fn sys_condvar_broadcast(condvar_id: usize) -> isize {
+    let Some(condvar) = condvar(condvar_id) else {
+        return -1;
+    };
+    condvar.broadcast();
+    0
+}
+
 pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    let (Some(condvar), Some(mutex)) = (condvar(condvar_id), mutex(mutex_id)) else {
+        return -1;
+    };
+    condvar.wait(&*mutex);
+    lock(mutex_id, &*mutex)
+}
+
+/// 0 once signalled, 1 after `timeout_ms` went by without a signal
+pub fn sys_condvar_wait_timeout(condvar_id: usize, mutex_id: usize, timeout_ms: usize) -> isize {
+    let (Some(condvar), Some(mutex)) = (condvar(condvar_id), mutex(mutex_id)) else {
+        return -1;
+    };
+    let signalled = condvar.wait_timeout(&*mutex, timeout_ms);
+    match lock(mutex_id, &*mutex) {
+        0 => !signalled as isize,
+        err => err,
+    }
+}
+
+/// Free the slot of a condvar nobody waits on
+pub fn sys_condvar_destroy(condvar_id: usize) -> isize {
     let process = current_process();
-    let process_inner = process.inner_exclusive_access();
-    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
-    drop(process_inner);
-    condvar.wait(mutex);
-    0
+    let mut process_inner = process.inner_exclusive_access();
+    match process_inner.condvar_list.get_mut(condvar_id) {
+        Some(slot) if slot.as_ref().is_some_and(|condvar| condvar.is_idle()) => {
+            *slot = None;
+            0
+        }
+        _ => -1,
+    }
 }
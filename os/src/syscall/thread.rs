@@ -17,8 +17,9 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
             .ustack_base,
         true,
     ));
-    add_task(Arc::clone(&new_task));
-    let new_task_inner = new_task.inner_exclusive_access();
+    let priority = task.inner_exclusive_access().priority;
+    let mut new_task_inner = new_task.inner_exclusive_access();
+    new_task_inner.priority = priority;
     let new_task_res = new_task_inner.res.as_ref().unwrap();
     let new_task_tid = new_task_res.tid;
     let mut process_inner = process.inner_exclusive_access();
@@ -36,6 +37,9 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
         trap_handler as usize,
     );
     (*new_task_trap_cx).x[10] = arg;
+    drop(new_task_inner);
+    drop(process_inner);
+    add_task(new_task);
     new_task_tid as isize
 }
 
@@ -52,11 +56,11 @@ pub fn sys_gettid() -> isize {
 pub fn sys_waittid(tid: usize) -> i32 {
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
-    let task_inner = task.inner_exclusive_access();
-    let mut process_inner = process.inner_exclusive_access();
-    if task_inner.res.as_ref().unwrap().tid == tid {
+    let task_tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+    if task_tid == tid {
         return -1;
     }
+    let mut process_inner = process.inner_exclusive_access();
     let mut exit_code: Option<i32> = None;
     let waited_task = process_inner.tasks[tid].as_ref();
     if let Some(waited_task) = waited_task {
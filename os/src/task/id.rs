@@ -3,8 +3,8 @@ use crate::config::USER_STACK_SIZE;
 use crate::mm::MapPermission;
 use crate::mm::PhysPageNum;
 use crate::mm::VirtAddr;
+use crate::sync::SpinLock;
 use crate::task::lazy_static;
-use crate::task::UPSafeCell;
 use crate::{
     config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE},
     mm::KERNEL_SPACE,
@@ -46,7 +46,7 @@ impl TaskUserRes {
         let mut process_inner = process.inner_exclusive_access();
         let ustack_bottom = ustack_bottom_from_tid(self.ustack_base, self.tid);
         let ustack_top = ustack_bottom + USER_STACK_SIZE;
-        process_inner.memory_set.insert_framed_area(
+        process_inner.memory_set.insert_lazy_area(
             ustack_bottom.into(),
             ustack_top.into(),
             MapPermission::R | MapPermission::W | MapPermission::U,
@@ -150,10 +150,9 @@ impl RecycleAllocator {
 }
 
 lazy_static! {
-    static ref PID_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-        unsafe { UPSafeCell::new(RecycleAllocator::new()) };
-    static ref KSTACK_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-        unsafe { UPSafeCell::new(RecycleAllocator::new()) };
+    static ref PID_ALLOCATOR: SpinLock<RecycleAllocator> = SpinLock::new(RecycleAllocator::new());
+    static ref KSTACK_ALLOCATOR: SpinLock<RecycleAllocator> =
+        SpinLock::new(RecycleAllocator::new());
 }
 
 pub struct PidHandle(pub usize);
@@ -1,64 +1,151 @@
-use crate::task::UPSafeCell;
-use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+use crate::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
 use lazy_static::lazy_static;
 
+use super::scheduler::{SchedPolicy, Scheduler, DEFAULT_POLICY};
 use super::task::TaskControlBlock;
 use crate::task::ProcessControlBlock;
 use crate::task::TaskStatus;
 use alloc::collections::BTreeMap;
 
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    scheduler: Box<dyn Scheduler + Send>,
+    policy: SchedPolicy,
+    /// Bumped by every policy switch. Tasks running or blocked at the time
+    /// keep the state the old policy left them until they are next queued or
+    /// ticked, then it is reset.
+    epoch: usize,
 }
 
 impl TaskManager {
     pub fn new() -> Self {
         Self {
-            ready_queue: VecDeque::new(),
+            scheduler: DEFAULT_POLICY.scheduler(),
+            policy: DEFAULT_POLICY,
+            epoch: 0,
+        }
+    }
+    /// Switch to `policy`, moving the ready tasks over, and return the old one
+    pub fn set_policy(&mut self, policy: SchedPolicy) -> SchedPolicy {
+        if policy != self.policy {
+            self.epoch += 1;
+            let mut scheduler = policy.scheduler();
+            for task in self.scheduler.drain() {
+                self.adopt(&task);
+                scheduler.add(task);
+            }
+            self.scheduler = scheduler;
+        }
+        core::mem::replace(&mut self.policy, policy)
+    }
+    /// Reset the scheduling state `task` has from an earlier policy
+    fn adopt(&self, task: &Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        if task_inner.sched_epoch != self.epoch {
+            task_inner.sched_epoch = self.epoch;
+            task_inner.pass = 0;
+            task_inner.mlfq_level = 0;
+            task_inner.ticks_used = 0;
         }
     }
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
+        self.adopt(&task);
+        self.scheduler.add(task);
     }
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+        self.scheduler.fetch()
     }
     pub fn remove(&mut self, task: Arc<TaskControlBlock>) {
-        if let Some((id, _)) = self
-            .ready_queue
-            .iter()
-            .enumerate()
-            .find(|(_, t)| Arc::as_ptr(t) == Arc::as_ptr(&task))
-        {
-            self.ready_queue.remove(id);
-        }
+        self.scheduler.remove(&task);
+    }
+    pub fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.adopt(task);
+        self.scheduler.tick(task)
     }
 }
 
 lazy_static! {
-    pub static ref TASK_MANAGER: Arc<UPSafeCell<TaskManager>> =
-        Arc::new(unsafe { UPSafeCell::new(TaskManager::new()) });
-    pub static ref PID2PCB: UPSafeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
-        unsafe { UPSafeCell::new(BTreeMap::new()) };
+    pub static ref TASK_MANAGER: Arc<SpinLock<TaskManager>> =
+        Arc::new(SpinLock::new(TaskManager::new()));
+    pub static ref PID2PCB: SpinLock<BTreeMap<usize, Arc<ProcessControlBlock>>> =
+        SpinLock::new(BTreeMap::new());
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
     TASK_MANAGER.exclusive_access().add(task);
 }
 
+/// Fetch the next task to run and mark it as running on the calling hart.
+///
+/// `on_cpu` is set while the task manager is still locked, so a task is
+/// always either in the ready queue or on some hart as far as
+/// `retire_task` can tell.
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_MANAGER.exclusive_access().fetch()
+    let mut manager = TASK_MANAGER.exclusive_access();
+    let task = manager.fetch()?;
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.on_cpu = true;
+    task_inner.task_status = TaskStatus::Running;
+    drop(task_inner);
+    Some(task)
 }
 
 pub fn remove_task(task: Arc<TaskControlBlock>) {
     TASK_MANAGER.exclusive_access().remove(task);
 }
 
+pub fn tick_task(task: &Arc<TaskControlBlock>) -> bool {
+    TASK_MANAGER.exclusive_access().tick(task)
+}
+
 pub fn wakeup_task(task: Arc<TaskControlBlock>) {
+    let mut manager = TASK_MANAGER.exclusive_access();
     let mut task_inner = task.inner_exclusive_access();
+    let requeue = match task_inner.task_status {
+        // still switching away on its hart, `park_task` will queue it
+        TaskStatus::Blocked => !task_inner.on_cpu,
+        // woken before it got to block, `block_current_and_run_next` sees it
+        TaskStatus::Running => false,
+        TaskStatus::Ready | TaskStatus::Exited => return,
+    };
     task_inner.task_status = TaskStatus::Ready;
     drop(task_inner);
-    add_task(task);
+    if requeue {
+        manager.add(task);
+    }
+}
+
+/// Called by a hart once `task` has switched away from it: the task context
+/// is saved now, so it is safe to let another hart pick the task up.
+pub fn park_task(task: Arc<TaskControlBlock>) {
+    let mut manager = TASK_MANAGER.exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.on_cpu = false;
+    let requeue = task_inner.task_status == TaskStatus::Ready;
+    drop(task_inner);
+    if requeue {
+        manager.add(task);
+    }
+}
+
+/// Take `task` out of scheduling for good, waiting for the hart that may be
+/// running it to switch away first.
+pub fn retire_task(task: &Arc<TaskControlBlock>) {
+    loop {
+        let mut manager = TASK_MANAGER.exclusive_access();
+        manager.remove(Arc::clone(task));
+        let mut task_inner = task.inner_exclusive_access();
+        if !task_inner.on_cpu {
+            task_inner.task_status = TaskStatus::Exited;
+            return;
+        }
+        drop(task_inner);
+        drop(manager);
+        spin_loop();
+    }
 }
 
 pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
@@ -75,3 +162,16 @@ pub fn remove_from_pid2process(pid: usize) {
         panic!("remove_from_pid2process: pid {} not found", pid);
     }
 }
+
+/// Evict up to `count` pages from the user spaces of all processes. A
+/// process whose lock is taken, the caller's own included, is skipped.
+pub fn reclaim_user_frames(mut count: usize) {
+    let processes: Vec<Arc<ProcessControlBlock>> =
+        PID2PCB.exclusive_access().values().cloned().collect();
+    for process in processes {
+        if count == 0 {
+            break;
+        }
+        count -= process.reclaim(count);
+    }
+}
@@ -1,15 +1,23 @@
 use crate::fs::open_file;
 use crate::fs::OpenFlags;
+use crate::fs::ROOT_INODE;
 use crate::sbi::shutdown;
-use crate::sync::UPSafeCell;
+use crate::smp::{broadcast, IPI_RESCHEDULE};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use id::TaskUserRes;
 use id::IDLE_PID;
-use manager::add_task;
 use manager::remove_from_pid2process;
+use manager::retire_task;
+use manager::tick_task;
+use manager::wakeup_task;
+use manager::PID2PCB;
 use manager::TASK_MANAGER;
-use processor::{schedule, take_curent_task};
+use processor::{current_process, current_task, schedule};
+use signal::{
+    default_action, DefaultAction, SignalAction, SignalFlags, MAX_SIG, SIGCONT, SIGKILL, SIG_DFL,
+    SIG_IGN,
+};
 use task::TaskControlBlock;
 
 mod context;
@@ -18,6 +26,8 @@ pub mod manager;
 mod process;
 use crate::task::process::ProcessControlBlock;
 pub mod processor;
+pub mod scheduler;
+pub mod signal;
 mod switch;
 pub mod task;
 use crate::task::context::TaskContext;
@@ -27,7 +37,7 @@ use switch::__switch;
 
 lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
-        let inode = open_file(0, "initproc", OpenFlags::RDONLY).unwrap();
+        let inode = open_file(&ROOT_INODE, "initproc", OpenFlags::RDONLY).unwrap();
         let v = inode.read_all();
         ProcessControlBlock::new(v.as_slice())
     };
@@ -38,31 +48,56 @@ pub fn add_initproc() {
 }
 pub fn suspend_current_and_run_next() {
     // println!("Suspend current and run next");
-    let task = take_curent_task().unwrap();
+    // the task stays current until `run_tasks` parks it on the ready queue
+    let task = current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
     task_inner.task_status = TaskStatus::Ready;
     drop(task_inner);
-    add_task(task);
+    drop(task);
     schedule(task_cx_ptr);
 }
+pub fn tick_current_and_run_next() {
+    let task = current_task().unwrap();
+    let preempt = tick_task(&task);
+    drop(task);
+    if preempt {
+        suspend_current_and_run_next();
+    }
+}
+
 pub fn block_current_and_run_next() {
-    let task = take_curent_task().unwrap();
+    let task = current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
-    task_inner.task_status = TaskStatus::Blocked;
+    // a wakeup from another hart may already have made it Ready
+    if task_inner.task_status == TaskStatus::Running {
+        task_inner.task_status = TaskStatus::Blocked;
+    }
     drop(task_inner);
+    drop(task);
     schedule(task_cx_ptr);
 }
 
 pub fn exit_current_and_run_next(xstate: i32) {
-    let task = take_curent_task().unwrap();
-    let mut task_inner = task.inner_exclusive_access();
+    let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
-    let tid = task_inner.res.as_ref().unwrap().tid;
+    let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+    if tid == 0 {
+        // written back while the task may still sleep on the file system,
+        // what other threads write until they are retired is lost
+        let writebacks = process.inner_exclusive_access().memory_set.take_all_dirty();
+        for writeback in writebacks {
+            writeback.write();
+        }
+    }
+    let mut task_inner = task.inner_exclusive_access();
     task_inner.exit_code = Some(xstate);
-    task_inner.res = None;
+    task_inner.task_status = TaskStatus::Exited;
+    // dropping TaskUserRes locks the process, so release the task first
+    let res = task_inner.res.take();
     drop(task_inner);
+    drop(res);
     drop(task);
     if tid == 0 {
         let pid = process.getpid();
@@ -76,21 +111,28 @@ pub fn exit_current_and_run_next(xstate: i32) {
         }
         remove_from_pid2process(pid);
         let mut process_inner = process.inner_exclusive_access();
-        process_inner.is_zombie = true;
-        process_inner.exit_code = xstate;
+        process_inner.is_exiting = true;
+        let siblings: Vec<Arc<TaskControlBlock>> = process_inner
+            .tasks
+            .iter()
+            .skip(1)
+            .flatten()
+            .cloned()
+            .collect();
+        drop(process_inner);
 
-        {
-            let mut initproc_inner = INITPROC.inner_exclusive_access();
-            for child in process_inner.children.iter() {
-                child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
-                initproc_inner.children.push(child.clone());
-            }
+        // other threads may be running on other harts, kick them out
+        if !siblings.is_empty() {
+            broadcast(IPI_RESCHEDULE);
+        }
+        for sibling in siblings.iter() {
+            retire_task(sibling);
         }
 
         let mut recycle_res = Vec::<TaskUserRes>::new();
+        let process_inner = process.inner_exclusive_access();
         for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
             let task = task.as_ref().unwrap();
-            remove_task(Arc::clone(task));
             let mut task_inner = task.inner_exclusive_access();
             if let Some(res) = task_inner.res.take() {
                 recycle_res.push(res);
@@ -99,19 +141,198 @@ pub fn exit_current_and_run_next(xstate: i32) {
         drop(process_inner);
         recycle_res.clear();
 
+        // never hold our own lock while locking INITPROC, waitpid locks them
+        // the other way round
+        let children = core::mem::take(&mut process.inner_exclusive_access().children);
+        for child in children {
+            child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
+            INITPROC.inner_exclusive_access().children.push(child);
+        }
+
         let mut process_inner = process.inner_exclusive_access();
-        process_inner.children.clear();
         process_inner.memory_set.recycle_data_pages();
         process_inner.fd_table.clear();
         while process_inner.tasks.len() > 1 {
             process_inner.tasks.pop();
         }
+        process_inner.exit_code = xstate;
+        // from here on the parent may reap us
+        process_inner.is_zombie = true;
     }
     drop(process);
     let mut _unused = TaskContext::zero_init();
     schedule(&mut _unused as *mut _);
 }
 
+/// Deliver the pending signals of the current process on the way back to
+/// user mode, either running the default action or entering the handler.
+pub fn handle_signals() {
+    loop {
+        let task = current_task().unwrap();
+        let process = task.process.upgrade().unwrap();
+        let mut process_inner = process.inner_exclusive_access();
+        let pending =
+            process_inner.signals & (!process_inner.signal_mask | SignalFlags::UNCATCHABLE);
+        let signum = if pending.contains(SignalFlags::SIGKILL) {
+            Some(SIGKILL)
+        } else if process_inner.stopped {
+            None
+        } else {
+            pending.first()
+        };
+        let Some(signum) = signum else {
+            if !process_inner.stopped {
+                return;
+            }
+            drop(process_inner);
+            drop(process);
+            drop(task);
+            // stay off user mode until SIGCONT or SIGKILL arrives
+            suspend_current_and_run_next();
+            continue;
+        };
+        let flag = SignalFlags::from_signum(signum).unwrap();
+        process_inner.signals.remove(flag);
+        let action = process_inner.signal_actions[signum];
+        let handler = if SignalFlags::UNCATCHABLE.contains(flag) {
+            SIG_DFL
+        } else {
+            action.handler
+        };
+        match handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(signum) {
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+                DefaultAction::Stop => {
+                    process_inner.stopped = true;
+                    process_inner.stop_signal = Some(signum);
+                }
+                DefaultAction::Terminate => {
+                    let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+                    drop(process_inner);
+                    if tid != 0 {
+                        // only the main thread takes the process down, wake
+                        // it up in case it waits for something
+                        process.inner_exclusive_access().signals |= flag;
+                        interrupt_threads(&process);
+                    }
+                    drop(process);
+                    drop(task);
+                    exit_current_and_run_next(-(signum as i32));
+                }
+            },
+            _ => {
+                let mut task_inner = task.inner_exclusive_access();
+                if task_inner.signal_backup.is_some() {
+                    // handlers do not nest, try again after sigreturn
+                    process_inner.signals |= flag;
+                    return;
+                }
+                let trap_cx = task_inner.get_trap_cx();
+                task_inner.signal_backup = Some((trap_cx.clone(), process_inner.signal_mask));
+                process_inner.signal_mask |= action.mask | flag;
+                trap_cx.sepc = handler;
+                trap_cx.x[1] = action.restorer;
+                trap_cx.x[10] = signum;
+                return;
+            }
+        }
+    }
+}
+
+/// Post `signum` to `process`. SIGCONT resumes the process right away,
+/// whatever it does about it, and cancels the stops still pending, a stop
+/// cancels a pending SIGCONT.
+pub fn send_signal(process: &Arc<ProcessControlBlock>, signum: usize) {
+    let flag = SignalFlags::from_signum(signum).unwrap();
+    let mut inner = process.inner_exclusive_access();
+    if signum == SIGCONT {
+        inner.stopped = false;
+        inner.stop_signal = None;
+        inner.signals.remove(SignalFlags::STOP);
+    } else if SignalFlags::STOP.contains(flag) {
+        inner.signals.remove(SignalFlags::SIGCONT);
+    }
+    inner.signals |= flag;
+    drop(inner);
+    interrupt_threads(process);
+}
+
+/// Wake every thread of `process`, so that a blocking wait gives up for a
+/// pending signal. Waits recheck what they wait for when woken, the ones
+/// with nothing to give up for go back to sleep, as does device I/O until it
+/// completes.
+fn interrupt_threads(process: &Arc<ProcessControlBlock>) {
+    let tasks: Vec<Arc<TaskControlBlock>> = process
+        .inner_exclusive_access()
+        .tasks
+        .iter()
+        .flatten()
+        .cloned()
+        .collect();
+    for task in tasks {
+        wakeup_task(task);
+    }
+}
+
+/// Post `signum` to every process of group `pgid`, a zero `signum` only
+/// checks that the group is not empty
+pub fn send_group_signal(pgid: usize, signum: usize) -> bool {
+    // fork locks PID2PCB with the parent locked, never the other way round
+    let processes: Vec<Arc<ProcessControlBlock>> =
+        PID2PCB.exclusive_access().values().cloned().collect();
+    let group: Vec<Arc<ProcessControlBlock>> = processes
+        .into_iter()
+        .filter(|process| process.inner_exclusive_access().pgid == pgid)
+        .collect();
+    if signum != 0 {
+        for process in group.iter() {
+            send_signal(process, signum);
+        }
+    }
+    !group.is_empty()
+}
+
+/// Whether a signal that does not get ignored waits for the current process,
+/// blocking syscalls give up early so that it is delivered
+pub fn current_signal_pending() -> bool {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let pending = inner.signals & (!inner.signal_mask | SignalFlags::UNCATCHABLE);
+    (0..=MAX_SIG).any(|signum| {
+        let Some(flag) = SignalFlags::from_signum(signum) else {
+            return false;
+        };
+        if !pending.contains(flag) {
+            return false;
+        }
+        match inner.signal_actions[signum].handler {
+            SIG_IGN => SignalFlags::UNCATCHABLE.contains(flag),
+            SIG_DFL => default_action(signum) != DefaultAction::Ignore,
+            _ => true,
+        }
+    })
+}
+
+/// Raise `signum` for a fault of the current instruction. Returning to it
+/// faults again, so a blocked or ignored signal falls back to its default
+/// action, as does a fault inside a handler.
+pub fn force_current_signal(signum: usize) {
+    let task = current_task().unwrap();
+    let in_handler = task.inner_exclusive_access().signal_backup.is_some();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let flag = SignalFlags::from_signum(signum).unwrap();
+    if in_handler
+        || process_inner.signal_mask.contains(flag)
+        || process_inner.signal_actions[signum].handler == SIG_IGN
+    {
+        process_inner.signal_mask.remove(flag);
+        process_inner.signal_actions[signum] = SignalAction::default();
+    }
+    process_inner.signals |= flag;
+}
+
 pub fn remove_task(task: Arc<TaskControlBlock>) {
     TASK_MANAGER.exclusive_access().remove(task);
 }
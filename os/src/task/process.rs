@@ -2,16 +2,24 @@ use super::id::pid_alloc;
 use super::id::RecycleAllocator;
 use super::manager::add_task;
 use super::manager::insert_into_pid2process;
+use super::manager::reclaim_user_frames;
+use super::signal::{SignalAction, SignalActions, SignalFlags, MAX_SIG, SIG_IGN};
+use crate::config::{MMAP_BASE, RECLAIM_BATCH, RECLAIM_WATERMARK, USER_HEAP_BASE};
 use crate::fs::File;
 use crate::fs::*;
 use crate::mm::translated_refmut;
 use crate::mm::MemorySet;
+use crate::mm::VirtAddr;
 use crate::mm::KERNEL_SPACE;
+use crate::mm::{free_frames, Fault, FramePin, MapPermission, SwapSlot, VirtPageNum};
 use crate::sync::Condvar;
+use crate::sync::DeadlockDetector;
 use crate::sync::Mutex;
+use crate::sync::Semaphore;
+use crate::sync::{SpinLock, SpinLockGuard};
 use crate::task::id::PidHandle;
+use crate::task::suspend_current_and_run_next;
 use crate::task::TaskControlBlock;
-use crate::task::UPSafeCell;
 use crate::trap::trap_handler;
 use crate::trap::TrapContext;
 use alloc::string::String;
@@ -19,44 +27,151 @@ use alloc::sync::Arc;
 use alloc::sync::Weak;
 use alloc::vec;
 use alloc::vec::Vec;
-use core::cell::RefMut;
+use easy_fs::Inode;
 pub struct ProcessControlBlock {
     pub pid: PidHandle,
-    inner: UPSafeCell<ProcessControlBlockInner>,
+    inner: SpinLock<ProcessControlBlockInner>,
 }
 
 impl ProcessControlBlock {
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, ProcessControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, ProcessControlBlockInner> {
         self.inner.exclusive_access()
     }
 
+    pub fn try_inner_exclusive_access(
+        &self,
+    ) -> Option<SpinLockGuard<'_, ProcessControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
 
+    /// Resolve a page fault at `vpn` caused by an `access` (R, W or X),
+    /// returns false if the address is not mapped with that permission. The
+    /// process lock is dropped around swap and file I/O.
+    pub fn handle_page_fault(&self, vpn: VirtPageNum, access: MapPermission) -> bool {
+        self.ensure_free_frames(RECLAIM_WATERMARK);
+        loop {
+            let mut inner = self.inner_exclusive_access();
+            match inner.memory_set.handle_page_fault(vpn, access) {
+                Fault::Resolved => return true,
+                Fault::Denied => return false,
+                Fault::SwapIn(slot, frame) => {
+                    drop(inner);
+                    slot.read(frame.ppn);
+                    drop(slot);
+                    self.inner_exclusive_access()
+                        .memory_set
+                        .finish_page_in(vpn, frame);
+                    return true;
+                }
+                Fault::FileIn(inode, offset, frame) => {
+                    drop(inner);
+                    // the part of the page past the end of file stays zeroed
+                    inode.read_at(offset, frame.ppn.get_bytes_array());
+                    self.inner_exclusive_access()
+                        .memory_set
+                        .finish_page_in(vpn, frame);
+                    return true;
+                }
+                Fault::InTransit => {
+                    drop(inner);
+                    suspend_current_and_run_next();
+                }
+            }
+        }
+    }
+
+    /// The frame behind `vpn` after resolving a fault for `access` on it,
+    /// None if the access is not allowed.
+    pub fn pin_user_page(&self, vpn: VirtPageNum, access: MapPermission) -> Option<FramePin> {
+        loop {
+            let pin = self
+                .inner_exclusive_access()
+                .memory_set
+                .pin_user_page(vpn, access);
+            if pin.is_some() {
+                return pin;
+            }
+            if !self.handle_page_fault(vpn, access) {
+                return None;
+            }
+        }
+    }
+
+    /// Make sure `want` frames are free before a user page fault or fork
+    /// needs them, evicting first from this user space, then from the others.
+    pub fn ensure_free_frames(&self, want: usize) {
+        let free = free_frames();
+        if free >= want {
+            return;
+        }
+        let count = (want - free).max(RECLAIM_BATCH);
+        let evicted = self.reclaim(count);
+        if evicted < count {
+            reclaim_user_frames(count - evicted);
+        }
+    }
+
+    /// Evict up to `count` pages of this process to swap, none if its lock
+    /// is taken. The victims are written out with the lock dropped. Returns
+    /// the number of frames freed.
+    pub fn reclaim(&self, count: usize) -> usize {
+        let Some(mut inner) = self.try_inner_exclusive_access() else {
+            return 0;
+        };
+        let victims = inner.memory_set.pick_victims(count);
+        drop(inner);
+        if victims.is_empty() {
+            return 0;
+        }
+        let evicted = victims
+            .into_iter()
+            .map(|(vpn, frame)| {
+                let slot = SwapSlot::write(frame.ppn);
+                (vpn, frame, slot)
+            })
+            .collect();
+        self.inner_exclusive_access()
+            .memory_set
+            .finish_eviction(evicted)
+    }
+
     pub fn new(elf_data: &[u8]) -> Arc<Self> {
         let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
         let pid_handle = pid_alloc();
+        let pgid = pid_handle.0;
         let process = Arc::new(Self {
             pid: pid_handle,
-            inner: unsafe {
-                UPSafeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: None,
-                    children: Vec::new(),
-                    fd_table: vec![
-                        Some(Arc::new(Stdin)),
-                        Some(Arc::new(Stdout)),
-                        Some(Arc::new(Stdout)),
-                    ],
-                    exit_code: 0,
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                })
-            },
+            inner: SpinLock::new(ProcessControlBlockInner {
+                is_zombie: false,
+                is_exiting: false,
+                memory_set,
+                parent: None,
+                children: Vec::new(),
+                fd_table: vec![
+                    Some(Arc::new(Stdin)),
+                    Some(Arc::new(Stdout)),
+                    Some(Arc::new(Stdout)),
+                ],
+                cwd: Arc::clone(&ROOT_INODE),
+                exit_code: 0,
+                program_brk: USER_HEAP_BASE,
+                pgid,
+                signals: SignalFlags::empty(),
+                signal_mask: SignalFlags::empty(),
+                signal_actions: [SignalAction::default(); MAX_SIG + 1],
+                stopped: false,
+                stop_signal: None,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                mutex_list: Vec::new(),
+                semaphore_list: Vec::new(),
+                condvar_list: Vec::new(),
+                deadlock: None,
+            }),
         });
         let task = Arc::new(TaskControlBlock::new(
             Arc::clone(&process),
@@ -87,12 +202,24 @@ impl ProcessControlBlock {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
         let new_token = memory_set.token();
-        self.inner_exclusive_access().memory_set = memory_set;
+        let mut process_inner = self.inner_exclusive_access();
+        let old_memory_set = core::mem::replace(&mut process_inner.memory_set, memory_set);
+        process_inner.program_brk = USER_HEAP_BASE;
+        // the handlers are gone with the old image, ignored signals stay so
+        for action in process_inner.signal_actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
+        }
+        drop(process_inner);
+        // writes back the dirty shared file pages
+        drop(old_memory_set);
         let task = self.inner_exclusive_access().get_task(0);
         let mut task_inner = task.inner_exclusive_access();
         task_inner.res.as_mut().unwrap().ustack_base = ustack_base;
         task_inner.res.as_mut().unwrap().alloc_user_res();
         task_inner.trap_cx_ppn = task_inner.res.as_mut().unwrap().trap_cx_ppn();
+        task_inner.signal_backup = None;
         let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
         user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
         let argv_base = user_sp;
@@ -129,9 +256,19 @@ impl ProcessControlBlock {
     }
 
     pub fn fork(self: &Arc<Self>) -> Arc<Self> {
-        let mut parent = self.inner_exclusive_access();
+        let want = self.inner_exclusive_access().memory_set.fork_frames();
+        self.ensure_free_frames(RECLAIM_WATERMARK + want);
+        // a page on its way to or from swap would be missing in the child
+        let mut parent = loop {
+            let parent = self.inner_exclusive_access();
+            if !parent.memory_set.in_transit() {
+                break parent;
+            }
+            drop(parent);
+            suspend_current_and_run_next();
+        };
         assert_eq!(parent.thread_count(), 1);
-        let memory_set = MemorySet::from_existed_user(&parent.memory_set);
+        let memory_set = MemorySet::from_existed_user(&mut parent.memory_set);
         let pid = pid_alloc();
         let mut new_fd_table = Vec::<Option<Arc<dyn File + Send + Sync>>>::new();
         for fd in parent.fd_table.iter() {
@@ -143,20 +280,29 @@ impl ProcessControlBlock {
         }
         let child = Arc::new(Self {
             pid,
-            inner: unsafe {
-                UPSafeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    fd_table: new_fd_table,
-                    exit_code: 0,
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                })
-            },
+            inner: SpinLock::new(ProcessControlBlockInner {
+                is_zombie: false,
+                is_exiting: false,
+                memory_set,
+                parent: Some(Arc::downgrade(self)),
+                children: Vec::new(),
+                fd_table: new_fd_table,
+                cwd: Arc::clone(&parent.cwd),
+                exit_code: 0,
+                program_brk: parent.program_brk,
+                pgid: parent.pgid,
+                signals: SignalFlags::empty(),
+                signal_mask: parent.signal_mask,
+                signal_actions: parent.signal_actions,
+                stopped: false,
+                stop_signal: None,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                mutex_list: Vec::new(),
+                semaphore_list: Vec::new(),
+                condvar_list: Vec::new(),
+                deadlock: None,
+            }),
         });
         parent.children.push(Arc::clone(&child));
         let task = Arc::new(TaskControlBlock::new(
@@ -174,7 +320,15 @@ impl ProcessControlBlock {
         let mut child_inner = child.inner_exclusive_access();
         child_inner.tasks.push(Some(Arc::clone(&task)));
         drop(child_inner);
-        let task_inner = task.inner_exclusive_access();
+        let parent_task = parent.get_task(0);
+        let parent_task_inner = parent_task.inner_exclusive_access();
+        let priority = parent_task_inner.priority;
+        // forked from inside a signal handler, the child returns from it too
+        let signal_backup = parent_task_inner.signal_backup.clone();
+        drop(parent_task_inner);
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.priority = priority;
+        task_inner.signal_backup = signal_backup;
         let trap_cx = task_inner.get_trap_cx();
         trap_cx.kernel_sp = task.kernel_stack.get_top();
         drop(task_inner);
@@ -186,15 +340,33 @@ impl ProcessControlBlock {
 
 pub struct ProcessControlBlockInner {
     pub is_zombie: bool,
+    /// The main thread is exiting, other threads must not return to user mode
+    pub is_exiting: bool,
     pub memory_set: MemorySet,
     pub parent: Option<Weak<ProcessControlBlock>>,
     pub children: Vec<Arc<ProcessControlBlock>>,
     pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// Where relative paths start from
+    pub cwd: Arc<Inode>,
     pub exit_code: i32,
+    pub program_brk: usize,
+    /// Process group, signals from the terminal go to a whole group
+    pub pgid: usize,
+    /// Signals sent to the process and not delivered yet
+    pub signals: SignalFlags,
+    pub signal_mask: SignalFlags,
+    pub signal_actions: SignalActions,
+    /// Stopped by a signal, no thread returns to user mode until SIGCONT
+    pub stopped: bool,
+    /// The signal that stopped the process, until `waitpid` reports it
+    pub stop_signal: Option<usize>,
     pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
     pub task_res_allocator: RecycleAllocator,
     pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
+    pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
     pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// Set while deadlock detection is on
+    pub deadlock: Option<DeadlockDetector>,
 }
 
 impl ProcessControlBlockInner {
@@ -211,6 +383,20 @@ impl ProcessControlBlockInner {
         }
     }
 
+    /// Move the program break, the heap may not grow into the mmap region
+    pub fn set_brk(&mut self, new_brk: usize) -> bool {
+        if !(USER_HEAP_BASE..=MMAP_BASE).contains(&new_brk) {
+            return false;
+        }
+        let heap_start = VirtAddr::from(USER_HEAP_BASE).floor();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        if !self.memory_set.resize_area(heap_start, new_end) {
+            return false;
+        }
+        self.program_brk = new_brk;
+        true
+    }
+
     pub fn alloc_tid(&mut self) -> usize {
         self.task_res_allocator.alloc()
     }
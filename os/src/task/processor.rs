@@ -1,10 +1,14 @@
-use super::manager::fetch_task;
+use super::manager::{fetch_task, park_task};
 use super::process::ProcessControlBlock;
-use super::task::{TaskControlBlock, TaskStatus};
+use super::task::TaskControlBlock;
+use crate::config::MAX_HARTS;
+use crate::smp::hart_id;
 use crate::task::__switch;
+use crate::trap::wait_for_interrupt;
 use crate::trap::TrapContext;
 use crate::{sync::UPSafeCell, task::TaskContext};
 use alloc::sync::Arc;
+use core::cell::RefMut;
 use lazy_static::lazy_static;
 
 pub struct Processor {
@@ -34,11 +38,16 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: UPSafeCell<Processor> = unsafe { UPSafeCell::new(Processor::new()) };
+    pub static ref PROCESSORS: [UPSafeCell<Processor>; MAX_HARTS] =
+        core::array::from_fn(|_| unsafe { UPSafeCell::new(Processor::new()) });
+}
+
+fn current_processor() -> RefMut<'static, Processor> {
+    PROCESSORS[hart_id()].exclusive_access()
 }
 
 pub fn take_curent_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_curent()
+    current_processor().take_curent()
 }
 
 pub fn current_process() -> Arc<ProcessControlBlock> {
@@ -46,7 +55,7 @@ pub fn current_process() -> Arc<ProcessControlBlock> {
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().current()
+    current_processor().current()
 }
 
 pub fn current_user_token() -> usize {
@@ -77,24 +86,30 @@ pub fn current_kstack_top() -> usize {
 
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
+        let mut processor = current_processor();
         if let Some(task) = fetch_task() {
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
-            let mut task_inner = task.inner_exclusive_access();
+            let task_inner = task.inner_exclusive_access();
             let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
-            task_inner.task_status = TaskStatus::Running;
             drop(task_inner);
             processor.current = Some(task);
             drop(processor);
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            // the task stays current until its context is saved, which also
+            // keeps the kernel stack of an exited task alive up to here
+            let task = take_curent_task().unwrap();
+            park_task(task);
+        } else {
+            drop(processor);
+            wait_for_interrupt();
         }
     }
 }
 
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-    let mut processor = PROCESSOR.exclusive_access();
+    let mut processor = current_processor();
     let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
     drop(processor);
     unsafe {
@@ -0,0 +1,233 @@
+use super::task::TaskControlBlock;
+use alloc::boxed::Box;
+use alloc::collections::{BinaryHeap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+pub const DEFAULT_PRIORITY: usize = 16;
+pub const MIN_PRIORITY: usize = 2;
+pub const MAX_PRIORITY: usize = 1024;
+const BIG_STRIDE: usize = 1 << 20;
+
+pub const MLFQ_LEVELS: usize = 4;
+const MLFQ_BOOST_TICKS: usize = 200;
+
+/// Scheduling policy used by `TaskManager`.
+///
+/// `tick` is called on every timer interrupt for the task running on the
+/// current processor and decides whether that task should be preempted.
+pub trait Scheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    fn remove(&mut self, task: &Arc<TaskControlBlock>);
+    fn tick(&mut self, _task: &Arc<TaskControlBlock>) -> bool {
+        true
+    }
+    /// Take out every queued task, to hand them to another policy
+    fn drain(&mut self) -> Vec<Arc<TaskControlBlock>>;
+}
+
+/// The policies `sys_sched_setscheduler` picks from, by number
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    Stride = 0,
+    Mlfq = 1,
+    Fifo = 2,
+}
+
+/// The policy the kernel boots with
+pub const DEFAULT_POLICY: SchedPolicy = SchedPolicy::Stride;
+
+impl SchedPolicy {
+    pub fn from_id(id: usize) -> Option<Self> {
+        match id {
+            0 => Some(Self::Stride),
+            1 => Some(Self::Mlfq),
+            2 => Some(Self::Fifo),
+            _ => None,
+        }
+    }
+
+    pub fn scheduler(self) -> Box<dyn Scheduler + Send> {
+        match self {
+            Self::Stride => Box::new(StrideScheduler::new()),
+            Self::Mlfq => Box::new(MlfqScheduler::new()),
+            Self::Fifo => Box::new(FifoScheduler::new()),
+        }
+    }
+}
+
+/// Plain round-robin over a FIFO ready queue.
+pub struct FifoScheduler {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl FifoScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for FifoScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.pop_front()
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        self.ready_queue.retain(|t| !Arc::ptr_eq(t, task));
+    }
+
+    fn drain(&mut self) -> Vec<Arc<TaskControlBlock>> {
+        self.ready_queue.drain(..).collect()
+    }
+}
+
+struct StrideEntry {
+    pass: usize,
+    task: Arc<TaskControlBlock>,
+}
+
+impl PartialEq for StrideEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.pass == other.pass
+    }
+}
+
+impl Eq for StrideEntry {}
+
+impl PartialOrd for StrideEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StrideEntry {
+    // BinaryHeap is a max-heap, reverse it so that the smallest pass comes first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.pass.cmp(&self.pass)
+    }
+}
+
+/// Stride scheduling: every dispatch advances the pass of a task by
+/// `BIG_STRIDE / priority`, and the task with the smallest pass runs next.
+pub struct StrideScheduler {
+    heap: BinaryHeap<StrideEntry>,
+    min_pass: usize,
+}
+
+impl StrideScheduler {
+    pub fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            min_pass: 0,
+        }
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        // a task waking up after a long sleep must not monopolize the cpu
+        task_inner.pass = task_inner.pass.max(self.min_pass);
+        let pass = task_inner.pass;
+        drop(task_inner);
+        self.heap.push(StrideEntry { pass, task });
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let StrideEntry { pass, task } = self.heap.pop()?;
+        self.min_pass = pass;
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.pass += BIG_STRIDE / task_inner.priority;
+        drop(task_inner);
+        Some(task)
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        self.heap.retain(|entry| !Arc::ptr_eq(&entry.task, task));
+    }
+
+    fn drain(&mut self) -> Vec<Arc<TaskControlBlock>> {
+        self.heap.drain().map(|entry| entry.task).collect()
+    }
+}
+
+/// Multilevel feedback queue: a task that uses up the time slice of its level
+/// is demoted, level `i` owns a slice of `2^i` ticks, and all queued tasks are
+/// boosted back to the top level every `MLFQ_BOOST_TICKS` ticks.
+pub struct MlfqScheduler {
+    queues: [VecDeque<Arc<TaskControlBlock>>; MLFQ_LEVELS],
+    ticks: usize,
+}
+
+impl MlfqScheduler {
+    pub fn new() -> Self {
+        Self {
+            queues: core::array::from_fn(|_| VecDeque::new()),
+            ticks: 0,
+        }
+    }
+
+    fn time_slice(level: usize) -> usize {
+        1 << level
+    }
+
+    fn boost(&mut self) {
+        for level in 1..MLFQ_LEVELS {
+            while let Some(task) = self.queues[level].pop_front() {
+                let mut task_inner = task.inner_exclusive_access();
+                task_inner.mlfq_level = 0;
+                task_inner.ticks_used = 0;
+                drop(task_inner);
+                self.queues[0].push_back(task);
+            }
+        }
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let level = task.inner_exclusive_access().mlfq_level;
+        self.queues[level].push_back(task);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        for queue in self.queues.iter_mut() {
+            queue.retain(|t| !Arc::ptr_eq(t, task));
+        }
+    }
+
+    fn drain(&mut self) -> Vec<Arc<TaskControlBlock>> {
+        self.queues
+            .iter_mut()
+            .flat_map(|queue| queue.drain(..))
+            .collect()
+    }
+
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.ticks += 1;
+        if self.ticks >= MLFQ_BOOST_TICKS {
+            self.ticks = 0;
+            self.boost();
+        }
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.ticks_used += 1;
+        if task_inner.ticks_used < Self::time_slice(task_inner.mlfq_level) {
+            return false;
+        }
+        task_inner.ticks_used = 0;
+        task_inner.mlfq_level = (task_inner.mlfq_level + 1).min(MLFQ_LEVELS - 1);
+        true
+    }
+}
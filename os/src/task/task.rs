@@ -1,22 +1,34 @@
-use core::cell::RefMut;
-
 use crate::mm::*;
+use crate::sync::{SpinLock, SpinLockGuard};
 use crate::task::context::TaskContext;
 use crate::task::id::kernel_stack_alloc;
 use crate::task::id::KernelStack;
-use crate::task::UPSafeCell;
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
 use alloc::sync::Weak;
 
 use super::id::TaskUserRes;
 use super::process::ProcessControlBlock;
+use super::scheduler::DEFAULT_PRIORITY;
+use super::signal::SignalFlags;
 pub struct TaskControlBlockInner {
     pub res: Option<TaskUserRes>,
     pub trap_cx_ppn: PhysPageNum,
     pub task_cx: TaskContext,
     pub task_status: TaskStatus,
     pub exit_code: Option<i32>,
+    pub priority: usize,
+    pub pass: usize,
+    pub mlfq_level: usize,
+    pub ticks_used: usize,
+    /// The `TaskManager` epoch the scheduling state above belongs to
+    pub sched_epoch: usize,
+    /// Set while some hart is executing on (or switching away from) this
+    /// task's kernel stack.
+    pub on_cpu: bool,
+    /// User context and signal mask to restore on `sigreturn`, set while a
+    /// signal handler runs on this task
+    pub signal_backup: Option<(TrapContext, SignalFlags)>,
 }
 
 impl TaskControlBlockInner {
@@ -34,18 +46,23 @@ pub enum TaskStatus {
     Ready,
     Running,
     Blocked,
+    Exited,
 }
 
 pub struct TaskControlBlock {
     pub process: Weak<ProcessControlBlock>,
     pub kernel_stack: KernelStack,
-    inner: UPSafeCell<TaskControlBlockInner>,
+    inner: SpinLock<TaskControlBlockInner>,
 }
 impl TaskControlBlock {
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, TaskControlBlockInner> {
         self.inner.exclusive_access()
     }
 
+    pub fn try_inner_exclusive_access(&self) -> Option<SpinLockGuard<'_, TaskControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
     pub fn get_user_token(&self) -> usize {
         let process = self.process.upgrade().unwrap();
         let inner = process.inner_exclusive_access();
@@ -64,15 +81,20 @@ impl TaskControlBlock {
         Self {
             process: Arc::downgrade(&process),
             kernel_stack: new_kernel_stack,
-            inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    res: Some(res),
-                    trap_cx_ppn,
-                    task_cx: TaskContext::goto_trap_return(kstack_top),
-                    task_status: TaskStatus::Ready,
-                    exit_code: None,
-                })
-            },
+            inner: SpinLock::new(TaskControlBlockInner {
+                res: Some(res),
+                trap_cx_ppn,
+                task_cx: TaskContext::goto_trap_return(kstack_top),
+                task_status: TaskStatus::Ready,
+                exit_code: None,
+                priority: DEFAULT_PRIORITY,
+                pass: 0,
+                mlfq_level: 0,
+                ticks_used: 0,
+                sched_epoch: 0,
+                on_cpu: false,
+                signal_backup: None,
+            }),
         }
     }
 }
@@ -6,6 +6,19 @@ pub fn get_time() -> usize {
 }
 
 use crate::config::*;
+use crate::smp::hart_id;
+use crate::sync::SpinLock;
+use crate::task::{manager::wakeup_task, task::TaskControlBlock};
+use alloc::collections::BinaryHeap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+
+/// Per-hart scratch area of `__timehandler`, see `time_handler.S`.
+#[link_section = ".data"]
+pub static mut TIMER_SCRATCH: [[usize; 7]; MAX_HARTS] = [[0; 7]; MAX_HARTS];
+const TICK_PENDING: usize = 5;
 
 #[no_mangle]
 pub fn get_time_ms() -> usize {
@@ -13,3 +26,95 @@ pub fn get_time_ms() -> usize {
     // println!("get_time_us: {}", t);
     t
 }
+
+/// Wall clock time in seconds since the epoch, from the RTC
+pub fn get_wall_time_sec() -> u32 {
+    // reading the low half latches the high half
+    let low = unsafe { (RTC_BASE as *const u32).read_volatile() } as u64;
+    let high = unsafe { ((RTC_BASE + 4) as *const u32).read_volatile() } as u64;
+    ((high << 32 | low) / 1_000_000_000) as u32
+}
+
+/// Consume the tick recorded by the M-mode timer handler on this hart.
+pub fn take_tick() -> bool {
+    let flag = unsafe {
+        &*(core::ptr::addr_of_mut!(TIMER_SCRATCH[hart_id()][TICK_PENDING]) as *const AtomicUsize)
+    };
+    flag.swap(0, Ordering::SeqCst) != 0
+}
+
+/// A queue a task can sleep on with a deadline
+pub trait TimedWait: Send + Sync {
+    /// Take `task` off the queue, false if something else woke it first
+    fn cancel_wait(&self, task: &Arc<TaskControlBlock>) -> bool;
+}
+
+/// A task to take off `queue` and wake at `expire_ms`
+struct Timer {
+    expire_ms: usize,
+    task: Arc<TaskControlBlock>,
+    queue: Weak<dyn TimedWait>,
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.expire_ms == other.expire_ms
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    /// The earliest deadline is the largest, so it tops the heap
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        other.expire_ms.cmp(&self.expire_ms)
+    }
+}
+
+lazy_static! {
+    static ref TIMERS: SpinLock<BinaryHeap<Timer>> = SpinLock::new(BinaryHeap::new());
+}
+
+/// Wake `task` at `expire_ms` unless it leaves `queue` before then
+pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>, queue: Weak<dyn TimedWait>) {
+    TIMERS.exclusive_access().push(Timer {
+        expire_ms,
+        task,
+        queue,
+    });
+}
+
+/// Drop the timer of `task`, false if it is gone already
+pub fn remove_timer(task: &Arc<TaskControlBlock>) -> bool {
+    let mut timers = TIMERS.exclusive_access();
+    let len = timers.len();
+    timers.retain(|timer| !Arc::ptr_eq(&timer.task, task));
+    timers.len() < len
+}
+
+/// Wake the tasks whose deadline has passed. Whoever takes a task off its
+/// queue wakes it, so a signal racing the deadline cannot wake it twice.
+pub fn check_timer() {
+    let now = get_time_ms();
+    let mut expired = Vec::new();
+    let mut timers = TIMERS.exclusive_access();
+    while timers.peek().is_some_and(|timer| timer.expire_ms <= now) {
+        expired.push(timers.pop().unwrap());
+    }
+    drop(timers);
+    for timer in expired {
+        if timer
+            .queue
+            .upgrade()
+            .is_some_and(|queue| queue.cancel_wait(&timer.task))
+        {
+            wakeup_task(timer.task);
+        }
+    }
+}
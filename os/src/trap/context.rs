@@ -2,6 +2,7 @@ use crate::trap::context::sstatus::SPP;
 use riscv::register::sstatus;
 
 #[repr(C)]
+#[derive(Clone)]
 pub struct TrapContext {
     pub x: [usize; 32],
     pub sstatus: usize,
@@ -9,6 +10,8 @@ pub struct TrapContext {
     pub kernel_satp: usize,
     pub kernel_sp: usize,
     pub trap_handler: usize,
+    /// tp of the kernel (the hart id), stored by `__restore`
+    pub kernel_tp: usize,
 }
 
 impl TrapContext {
@@ -31,6 +34,7 @@ impl TrapContext {
             kernel_satp,
             kernel_sp,
             trap_handler,
+            kernel_tp: 0,
         };
         cx.set_sp(sp);
         cx
@@ -1,16 +1,22 @@
 mod context;
 
-use crate::task::processor::{current_trap_cx_user_va, current_user_token};
+use crate::driver::handle_irqs;
+use crate::fs::flush_if_due;
+use crate::mm::{MapPermission, VirtAddr};
+use crate::smp::{self, IPI_RESCHEDULE};
+use crate::task::processor::{current_process, current_trap_cx_user_va, current_user_token};
+use crate::timer::{check_timer, take_tick};
 use crate::{
     config::{TRAMPOLINE, TRAP_CONTEXT},
-    syscall::syscall,
-    task::{processor::current_trap_cx, suspend_current_and_run_next},
+    syscall::{syscall, RESTART},
+    task::{processor::current_trap_cx, suspend_current_and_run_next, tick_current_and_run_next},
 };
 pub use context::TrapContext;
 use core::arch::{asm, global_asm};
 
 global_asm!(include_str!("trap.S"));
-use crate::task::exit_current_and_run_next;
+use crate::task::signal::{SIGILL, SIGSEGV};
+use crate::task::{exit_current_and_run_next, force_current_signal, handle_signals};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
@@ -29,6 +35,7 @@ pub fn init_() {
 
 #[unsafe(no_mangle)]
 pub fn trap_handler() -> ! {
+    smp::enter_kernel();
     set_kernel_entry();
     let scause = scause::read();
     let stval = stval::read();
@@ -36,22 +43,45 @@ pub fn trap_handler() -> ! {
         Trap::Exception(Exception::UserEnvCall) => {
             let mut cx = current_trap_cx();
             cx.sepc += 4;
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]) as usize;
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
             cx = current_trap_cx();
-            cx.x[10] = result as usize;
+            if result == RESTART {
+                // back onto the ecall, its arguments are still in place
+                cx.sepc -= 4;
+            } else {
+                cx.x[10] = result as usize;
+            }
         }
-        Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::InstructionFault)
+        Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionPageFault)
-        | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::LoadPageFault) => {
-            println!("[kernel] PageFault in application, kernel killed it.");
-            exit_current_and_run_next(-2);
+            let access = match scause.cause() {
+                Trap::Exception(Exception::StorePageFault) => MapPermission::W,
+                Trap::Exception(Exception::InstructionPageFault) => MapPermission::X,
+                _ => MapPermission::R,
+            };
+            let resolved =
+                current_process().handle_page_fault(VirtAddr::from(stval).floor(), access);
+            if !resolved {
+                println!("[kernel] PageFault in application, raising SIGSEGV.");
+                force_current_signal(SIGSEGV);
+            }
+        }
+        Trap::Exception(Exception::StoreFault)
+        | Trap::Exception(Exception::InstructionFault)
+        | Trap::Exception(Exception::LoadFault) => {
+            println!("[kernel] PageFault in application, raising SIGSEGV.");
+            force_current_signal(SIGSEGV);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
-            println!("[kernel] IllegalInstruction in application, kernel killed it.");
-            exit_current_and_run_next(-3);
+            println!("[kernel] IllegalInstruction in application, raising SIGILL.");
+            force_current_signal(SIGILL);
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            handle_irqs();
         }
         Trap::Interrupt(Interrupt::SupervisorSoft) => {
             // println!("[Timer] interrupt in application, kernel suspend it.");
@@ -60,7 +90,17 @@ pub fn trap_handler() -> ! {
             unsafe {
                 asm!("csrw sip, {}", in(reg) sip);
             }
-            suspend_current_and_run_next();
+            // a TLB flush request needs no work here: the trampoline already
+            // flushed the TLB when switching to the kernel address space
+            let ipi = smp::take_pending();
+            if ipi & IPI_RESCHEDULE != 0 {
+                suspend_current_and_run_next();
+            } else if take_tick() {
+                // trapped from user mode, so no file system lock is held
+                flush_if_due();
+                check_timer();
+                tick_current_and_run_next();
+            }
             // println!("[Timer] interrupt handled");
         }
         _ => {
@@ -80,6 +120,32 @@ pub fn enable_timer_interrupt() {
     }
 }
 
+const SIP_SSIP: usize = 1 << 1;
+const SIP_SEIP: usize = 1 << 9;
+
+/// Sleep until an interrupt is pending and serve it in place, for a hart with
+/// nothing to run. The kernel runs with interrupts off, so they never trap.
+pub fn wait_for_interrupt() {
+    unsafe {
+        asm!("wfi");
+    }
+    let pending = sip::read().bits();
+    if pending & SIP_SEIP != 0 {
+        handle_irqs();
+    }
+    if pending & SIP_SSIP != 0 {
+        unsafe {
+            asm!("csrc sip, {}", in(reg) SIP_SSIP);
+        }
+        // no task to preempt, and the TLB holds no user translations that
+        // matter before the next switch to user space flushes it anyway
+        smp::take_pending();
+        if take_tick() {
+            check_timer();
+        }
+    }
+}
+
 fn set_kernel_entry() {
     unsafe {
         stvec::write(trap_from_kernel as usize, stvec::TrapMode::Direct);
@@ -99,9 +165,15 @@ fn set_user_trap_entry() {
 
 #[no_mangle]
 pub fn trap_return() -> ! {
+    // the main thread is tearing the process down on another hart
+    if current_process().inner_exclusive_access().is_exiting {
+        exit_current_and_run_next(-1);
+    }
+    handle_signals();
     set_user_trap_entry();
     let trap_cx_user_va = current_trap_cx_user_va();
     let user_satp = current_user_token();
+    smp::set_current_token(user_satp);
     extern "C" {
         fn __alltraps();
         fn __restore();
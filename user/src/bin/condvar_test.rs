@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{
+    condvar_broadcast, condvar_create, condvar_destroy, condvar_signal, condvar_wait,
+    condvar_wait_timeout, exit, get_time, mutex_blocking_create, mutex_destroy, mutex_lock,
+    mutex_unlock, sleep, thread_create, waittid,
+};
+
+const THREAD_NUM: usize = 4;
+const MUTEX_ID: usize = 0;
+const CONDVAR_ID: usize = 0;
+
+static mut READY: bool = false;
+static mut WOKEN: usize = 0;
+
+fn waiter() -> ! {
+    mutex_lock(MUTEX_ID);
+    while !unsafe { READY } {
+        condvar_wait(CONDVAR_ID, MUTEX_ID);
+    }
+    unsafe {
+        WOKEN += 1;
+    }
+    mutex_unlock(MUTEX_ID);
+    exit(0)
+}
+
+fn signaller() -> ! {
+    sleep(20);
+    mutex_lock(MUTEX_ID);
+    condvar_signal(CONDVAR_ID);
+    mutex_unlock(MUTEX_ID);
+    exit(0)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    assert_eq!(mutex_blocking_create() as usize, MUTEX_ID);
+    assert_eq!(condvar_create() as usize, CONDVAR_ID);
+
+    // nobody signals, so the wait runs out
+    let start = get_time();
+    mutex_lock(MUTEX_ID);
+    assert!(!condvar_wait_timeout(CONDVAR_ID, MUTEX_ID, 50));
+    mutex_unlock(MUTEX_ID);
+    assert!(get_time() - start >= 50);
+
+    // a signal well before the deadline ends the wait early
+    let start = get_time();
+    let tid = thread_create(signaller as usize, 0);
+    mutex_lock(MUTEX_ID);
+    assert!(condvar_wait_timeout(CONDVAR_ID, MUTEX_ID, 5000));
+    mutex_unlock(MUTEX_ID);
+    assert!(get_time() - start < 5000);
+    waittid(tid as usize);
+
+    // one broadcast wakes every waiter
+    let threads: Vec<_> = (0..THREAD_NUM)
+        .map(|_| thread_create(waiter as usize, 0))
+        .collect();
+    sleep(20);
+    mutex_lock(MUTEX_ID);
+    unsafe {
+        READY = true;
+    }
+    condvar_broadcast(CONDVAR_ID);
+    mutex_unlock(MUTEX_ID);
+    for tid in threads {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    assert_eq!(unsafe { WOKEN }, THREAD_NUM);
+
+    // a held mutex stays, a free one goes and its slot is reused
+    mutex_lock(MUTEX_ID);
+    assert_eq!(mutex_destroy(MUTEX_ID), -1);
+    mutex_unlock(MUTEX_ID);
+    assert_eq!(condvar_destroy(CONDVAR_ID), 0);
+    assert_eq!(condvar_destroy(CONDVAR_ID), -1);
+    assert_eq!(mutex_destroy(MUTEX_ID), 0);
+    assert_eq!(mutex_destroy(MUTEX_ID), -1);
+    for _ in 0..1000 {
+        assert_eq!(mutex_blocking_create() as usize, MUTEX_ID);
+        assert_eq!(condvar_create() as usize, CONDVAR_ID);
+        assert_eq!(condvar_destroy(CONDVAR_ID), 0);
+        assert_eq!(mutex_destroy(MUTEX_ID), 0);
+    }
+    println!("condvar_test passed!");
+    0
+}
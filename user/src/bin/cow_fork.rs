@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, waitpid};
+
+const PAGES: usize = 16;
+const LEN: usize = PAGES * 4096;
+
+static mut DATA: [u8; LEN] = [0; LEN];
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let data = unsafe { &mut *core::ptr::addr_of_mut!(DATA) };
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (i % 251) as u8;
+    }
+    let pid = fork();
+    if pid == 0 {
+        // the child sees the parent's data and its writes stay private
+        for (i, byte) in data.iter().enumerate() {
+            assert_eq!(*byte, (i % 251) as u8);
+        }
+        for page in 0..PAGES {
+            data[page * 4096] = 0xff;
+        }
+        for page in 0..PAGES {
+            assert_eq!(data[page * 4096], 0xff);
+        }
+        exit(0);
+    }
+    // parent writes only to the odd pages, the even ones stay shared
+    for page in (1..PAGES).step_by(2) {
+        data[page * 4096 + 1] = 0xee;
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    for (i, byte) in data.iter().enumerate() {
+        let expected = if (i / 4096) % 2 == 1 && i % 4096 == 1 {
+            0xee
+        } else {
+            (i % 251) as u8
+        };
+        assert_eq!(*byte, expected);
+    }
+    println!("cow_fork passed!");
+    0
+}
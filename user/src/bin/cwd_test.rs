@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    OpenFlags, chdir, close, exit, fork, getcwd, mkdir, open, openat, read, rm, stat, waitpid,
+    write,
+};
+
+fn cwd_is(expected: &str) -> bool {
+    let mut buf = [0u8; 64];
+    let len = getcwd(&mut buf);
+    len > 0 && &buf[..len as usize - 1] == expected.as_bytes()
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    assert!(cwd_is("/"));
+    assert_eq!(mkdir("cwd_dir\0"), 0);
+    assert_eq!(mkdir("cwd_dir/sub\0"), 0);
+    assert_eq!(chdir("cwd_dir/sub\0"), 0);
+    assert!(cwd_is("/cwd_dir/sub"));
+    assert_eq!(getcwd(&mut [0u8; 4]), -1);
+
+    // relative paths start from the working directory
+    assert_eq!(chdir("..\0"), 0);
+    assert!(cwd_is("/cwd_dir"));
+    let fd = open("file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, b"cwd");
+    close(fd as usize);
+    assert_eq!(chdir("file\0"), -1);
+    assert_eq!(chdir("/\0"), 0);
+    assert!(stat("cwd_dir/file\0").is_some());
+    assert!(stat("/cwd_dir/sub/../file\0").is_some());
+
+    // or from a directory opened as dirfd
+    let dirfd = open("cwd_dir\0", OpenFlags::RDONLY);
+    assert!(dirfd > 0);
+    assert_eq!(open("cwd_dir\0", OpenFlags::WRONLY), -1);
+    let fd = openat(dirfd, "file\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buf = [0u8; 8];
+    assert_eq!(read(fd as usize, &mut buf), 3);
+    assert_eq!(&buf[..3], b"cwd");
+    close(fd as usize);
+    assert_eq!(openat(fd, "file\0", OpenFlags::RDONLY), -1);
+    assert_eq!(openat(1, "file\0", OpenFlags::RDONLY), -1);
+    close(dirfd as usize);
+
+    // children start where the parent is, and move on their own
+    assert_eq!(chdir("cwd_dir\0"), 0);
+    let pid = fork();
+    if pid == 0 {
+        assert!(cwd_is("/cwd_dir"));
+        assert_eq!(chdir("sub\0"), 0);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert!(cwd_is("/cwd_dir"));
+
+    assert_eq!(rm("file\0"), 0);
+    assert_eq!(rm("sub\0"), 0);
+    assert_eq!(chdir("..\0"), 0);
+    assert_eq!(rm("cwd_dir\0"), 0);
+    println!("cwd_test passed!");
+    0
+}
@@ -0,0 +1,150 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use user_lib::{
+    DEADLOCK, condvar_create, condvar_signal, condvar_wait, enable_deadlock_detect, exit,
+    mutex_blocking_create, mutex_lock, mutex_unlock, semaphore_create, semaphore_down,
+    semaphore_up, sleep, thread_create, waittid,
+};
+
+const FIRST: usize = 0;
+const SECOND: usize = 1;
+const GUARD: usize = 2;
+/// Semaphores counting the free and the filled slots of `BUFFER`
+const EMPTY: usize = 0;
+const FULL: usize = 1;
+/// Semaphores of one unit, taken in opposite order
+const SEM_A: usize = 2;
+const SEM_B: usize = 3;
+const SLOTS: usize = 4;
+const ITEMS: usize = 32;
+static BUFFER: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Take `FIRST` then `SECOND`, or the other way round. Exits with 1 if it
+/// was refused the second lock.
+fn take_both(reversed: usize) -> ! {
+    let (a, b) = if reversed == 0 {
+        (FIRST, SECOND)
+    } else {
+        (SECOND, FIRST)
+    };
+    assert_eq!(mutex_lock(a), 0);
+    // both threads hold their first lock before either asks for the second
+    sleep(50);
+    let refused = mutex_lock(b) == DEADLOCK;
+    if !refused {
+        mutex_unlock(b);
+    }
+    mutex_unlock(a);
+    exit(refused as i32)
+}
+
+fn producer() -> ! {
+    for item in 0..ITEMS {
+        assert_eq!(semaphore_down(EMPTY), 0);
+        assert_eq!(mutex_lock(GUARD), 0);
+        BUFFER[item % SLOTS].store(item, Ordering::Relaxed);
+        mutex_unlock(GUARD);
+        semaphore_up(FULL);
+    }
+    exit(0)
+}
+
+fn consumer() -> ! {
+    for item in 0..ITEMS {
+        assert_eq!(semaphore_down(FULL), 0);
+        assert_eq!(mutex_lock(GUARD), 0);
+        assert_eq!(BUFFER[item % SLOTS].load(Ordering::Relaxed), item);
+        mutex_unlock(GUARD);
+        semaphore_up(EMPTY);
+    }
+    exit(0)
+}
+
+/// Take `SEM_B` then wait for `SEM_A`, which the main thread holds
+fn take_b_then_a() -> ! {
+    assert_eq!(semaphore_down(SEM_B), 0);
+    assert_eq!(semaphore_down(SEM_A), 0);
+    semaphore_up(SEM_A);
+    semaphore_up(SEM_B);
+    exit(0)
+}
+
+fn signaller(condvar: usize) -> ! {
+    assert_eq!(mutex_lock(FIRST), 0);
+    READY.store(true, Ordering::Relaxed);
+    condvar_signal(condvar);
+    mutex_unlock(FIRST);
+    exit(0)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    assert_eq!(enable_deadlock_detect(true), 0);
+    assert_eq!(mutex_blocking_create() as usize, FIRST);
+    assert_eq!(mutex_blocking_create() as usize, SECOND);
+
+    // waiting on a lock it holds itself would never end
+    assert_eq!(mutex_lock(FIRST), 0);
+    assert_eq!(mutex_lock(FIRST), DEADLOCK);
+    mutex_unlock(FIRST);
+
+    // two threads taking the locks in opposite order: the one closing the
+    // cycle is refused, backs off, and the other gets both
+    let threads = [
+        thread_create(take_both as usize, 0),
+        thread_create(take_both as usize, 1),
+    ];
+    let refused: i32 = threads.iter().map(|tid| waittid(*tid as usize)).sum();
+    assert_eq!(refused, 1);
+
+    // a unit nobody holds is not waited for in vain while another thread
+    // runs: the consumer starts out waiting for one the producer gives it
+    assert_eq!(mutex_blocking_create() as usize, GUARD);
+    assert_eq!(semaphore_create(SLOTS) as usize, EMPTY);
+    assert_eq!(semaphore_create(0) as usize, FULL);
+    assert_eq!(semaphore_create(1) as usize, SEM_A);
+    assert_eq!(semaphore_create(1) as usize, SEM_B);
+    let threads = [
+        thread_create(consumer as usize, 0),
+        thread_create(producer as usize, 0),
+    ];
+    for tid in threads {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+
+    // waiting on the last unit of a semaphore it holds itself would never
+    // end either
+    assert_eq!(semaphore_down(SEM_A), 0);
+    assert_eq!(semaphore_down(SEM_A), DEADLOCK);
+
+    // two semaphores taken in opposite order: with the other thread waiting
+    // for `SEM_A`, waiting for `SEM_B` would never end
+    let tid = thread_create(take_b_then_a as usize, 0);
+    sleep(50);
+    assert_eq!(semaphore_down(SEM_B), DEADLOCK);
+    semaphore_up(SEM_A);
+    assert_eq!(waittid(tid as usize), 0);
+
+    // a condvar waiter takes the lock back through the detector too
+    let condvar = condvar_create() as usize;
+    assert_eq!(mutex_lock(FIRST), 0);
+    let tid = thread_create(signaller as usize, condvar);
+    while !READY.load(Ordering::Relaxed) {
+        assert_eq!(condvar_wait(condvar, FIRST), 0);
+    }
+    mutex_unlock(FIRST);
+    assert_eq!(waittid(tid as usize), 0);
+
+    // with detection off the locks behave as before
+    assert_eq!(enable_deadlock_detect(false), 0);
+    assert_eq!(mutex_lock(FIRST), 0);
+    mutex_unlock(FIRST);
+    println!("deadlock_test passed!");
+    0
+}
@@ -10,13 +10,13 @@ use user_lib::{OpenFlags, close, open, read, write};
 pub fn main() -> i32 {
     let test_str = "Hello, world!";
     let filea = "filea\0";
-    let fd = open(0, filea, OpenFlags::CREATE | OpenFlags::WRONLY);
+    let fd = open(filea, OpenFlags::CREATE | OpenFlags::WRONLY);
     assert!(fd > 0);
     let fd = fd as usize;
     write(fd, test_str.as_bytes());
     close(fd);
 
-    let fd = open(0, filea, OpenFlags::RDONLY);
+    let fd = open(filea, OpenFlags::RDONLY);
     assert!(fd > 0);
     let fd = fd as usize;
     let mut buffer = [0u8; 100];
@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    MmapFlags, MmapProt, OpenFlags, close, exit, fork, mmap, mmap_file, munmap, open, read, sync,
+    unlink, waitpid, write, yield_,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// Far more blocks than the block cache holds, the read has to go to disk
+const LEN: usize = 16 * PAGE_SIZE;
+
+fn expected(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+/// A page shared with the child, it holds how far the child counted and
+/// whether to stop
+fn map_shared_page(name: &str) -> usize {
+    let fd = open(name, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, &[0u8; 16]), 16);
+    let addr = mmap_file(
+        0,
+        PAGE_SIZE,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::SHARED,
+        fd,
+        0,
+    );
+    assert!(addr > 0);
+    close(fd);
+    addr as usize
+}
+
+/// While a task sleeps on a file read, another one gets to run. With a
+/// single hart a polling reader would not let the child count at all.
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let name = "fs_sleep_data\0";
+    let fd = open(
+        name,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut chunk = [0u8; 512];
+    for start in (0..LEN).step_by(chunk.len()) {
+        for (i, byte) in chunk.iter_mut().enumerate() {
+            *byte = expected(start + i);
+        }
+        assert_eq!(write(fd, &chunk), chunk.len() as isize);
+    }
+    close(fd);
+    // out of the cache and onto the disk
+    sync();
+
+    let count_name = "fs_sleep_count\0";
+    let shared = map_shared_page(count_name);
+    let [count, stop] = unsafe { &*(shared as *const [AtomicUsize; 2]) };
+    // fault the page in before fork, so the child gets the same frame
+    stop.store(0, Ordering::Relaxed);
+    let pid = fork();
+    if pid == 0 {
+        while stop.load(Ordering::Relaxed) == 0 {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        exit(0);
+    }
+    while count.load(Ordering::Relaxed) == 0 {
+        yield_();
+    }
+
+    let addr = mmap(
+        0,
+        LEN,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    );
+    assert!(addr > 0);
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as usize as *mut u8, LEN) };
+    // touch the buffer first, so only the file read happens in the call
+    buf.fill(0);
+    let fd = open(name, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let before = count.load(Ordering::Relaxed);
+    assert_eq!(read(fd, buf), LEN as isize);
+    let after = count.load(Ordering::Relaxed);
+    close(fd);
+    for (i, byte) in buf.iter().enumerate() {
+        assert_eq!(*byte, expected(i));
+    }
+    assert!(after > before, "the child never ran during the read");
+
+    stop.store(1, Ordering::Relaxed);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(munmap(addr as usize, LEN), 0);
+    assert_eq!(munmap(shared, PAGE_SIZE), 0);
+    assert_eq!(unlink(name), 0);
+    assert_eq!(unlink(count_name), 0);
+    println!("fs_sleep_test passed!");
+    0
+}
@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    DT_DIR, DT_LNK, DT_REG, OpenFlags, close, dirents, getdents, lstat, mkdir, open, rm, symlink,
+};
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("dents\0"), 0);
+    let fd = open("dents/file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(mkdir("dents/dir\0"), 0);
+    assert_eq!(symlink("file\0", "dents/link\0"), 0);
+
+    let fd = open("dents\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    // too small for any record
+    assert_eq!(getdents(fd, &mut [0u8; 16]), -1);
+    // room for one record at a time
+    let mut buf = [0u8; 32];
+    let mut seen = [false; 3];
+    let mut calls = 0;
+    loop {
+        let len = getdents(fd, &mut buf);
+        assert!(len >= 0);
+        if len == 0 {
+            break;
+        }
+        calls += 1;
+        for dirent in dirents(&buf[..len as usize]) {
+            let (i, d_type) = match dirent.name {
+                "file" => (0, DT_REG),
+                "dir" => (1, DT_DIR),
+                "link" => (2, DT_LNK),
+                name => panic!("unexpected entry {}", name),
+            };
+            assert_eq!(dirent.d_type, d_type);
+            assert!(!seen[i]);
+            seen[i] = true;
+            let path = ["dents/file\0", "dents/dir\0", "dents/link\0"][i];
+            assert_eq!(dirent.ino, lstat(path).unwrap().ino);
+        }
+    }
+    assert_eq!(seen, [true; 3]);
+    assert_eq!(calls, 3);
+    assert_eq!(getdents(fd, &mut buf), 0);
+    close(fd);
+
+    let fd = open("dents/file\0", OpenFlags::RDONLY);
+    assert_eq!(getdents(fd as usize, &mut buf), -1);
+    close(fd as usize);
+
+    assert_eq!(rm("dents/link\0"), 0);
+    assert_eq!(rm("dents/dir\0"), 0);
+    assert_eq!(rm("dents/file\0"), 0);
+    assert_eq!(rm("dents\0"), 0);
+    println!("getdents_test passed!");
+    0
+}
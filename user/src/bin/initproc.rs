@@ -8,7 +8,7 @@ use user_lib::{exec, fork, wait, yield_};
 #[unsafe(no_mangle)]
 fn main() -> i32 {
     if fork() == 0 {
-        exec(0, "user_shell\0", &[core::ptr::null::<u8>()]);
+        exec("user_shell\0", &[core::ptr::null::<u8>()]);
     } else {
         loop {
             let mut exit_code: i32 = 0;
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    AT_FDCWD, AT_REMOVEDIR, OpenFlags, close, link, lstat, mkdir, open, read, readlink, stat,
+    symlink, unlink, unlinkat, write,
+};
+
+fn read_file(path: &str) -> Option<usize> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut buf = [0u8; 64];
+    let len = read(fd as usize, &mut buf) as usize;
+    close(fd as usize);
+    assert_eq!(&buf[..len], b"shared contents");
+    Some(len)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let fd = open("link_orig\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, b"shared contents");
+    close(fd as usize);
+
+    // a hard link keeps the data alive after the original name goes
+    assert_eq!(link("link_orig\0", "link_hard\0"), 0);
+    assert_eq!(stat("link_hard\0").unwrap().nlink, 2);
+    assert_eq!(
+        stat("link_hard\0").unwrap().ino,
+        stat("link_orig\0").unwrap().ino
+    );
+    assert_eq!(unlink("link_orig\0"), 0);
+    assert_eq!(stat("link_hard\0").unwrap().nlink, 1);
+    assert_eq!(read_file("link_hard\0"), Some(15));
+
+    // a symbolic link is followed by open and stat, not by lstat
+    assert_eq!(symlink("link_hard\0", "link_soft\0"), 0);
+    assert_eq!(read_file("link_soft\0"), Some(15));
+    assert!(lstat("link_soft\0").unwrap().is_symlink());
+    assert!(!stat("link_soft\0").unwrap().is_symlink());
+    let mut buf = [0u8; 64];
+    let len = readlink("link_soft\0", &mut buf);
+    assert_eq!(&buf[..len as usize], b"link_hard");
+
+    // through a directory, and around in circles
+    assert!(mkdir("link_dir\0") == 0);
+    assert_eq!(symlink("../link_hard\0", "link_dir/up\0"), 0);
+    assert_eq!(read_file("link_dir/up\0"), Some(15));
+    assert_eq!(symlink("link_loop\0", "link_loop\0"), 0);
+    assert!(stat("link_loop\0").is_none());
+    assert!(read_file("link_loop\0").is_none());
+
+    assert_eq!(unlink("link_dir\0"), -1);
+    assert_eq!(unlink("link_dir/up\0"), 0);
+    assert_eq!(unlinkat(AT_FDCWD, "link_dir\0", AT_REMOVEDIR), 0);
+    assert_eq!(unlink("link_loop\0"), 0);
+    assert_eq!(unlink("link_soft\0"), 0);
+    assert_eq!(read_file("link_hard\0"), Some(15));
+    assert_eq!(unlink("link_hard\0"), 0);
+    assert!(stat("link_hard\0").is_none());
+    println!("link_test passed!");
+    0
+}
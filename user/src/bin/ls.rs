@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user_lib;
+
+use alloc::format;
+use alloc::string::String;
+use user_lib::{OpenFlags, Stat, close, dirents, getdents, lstat, open, readlink};
+
+/// `drwxr-xr-x` style type and permissions
+fn mode_string(st: &Stat) -> String {
+    let mut s = String::from(if st.is_dir() {
+        "d"
+    } else if st.is_symlink() {
+        "l"
+    } else {
+        "-"
+    });
+    for shift in [6, 3, 0] {
+        let bits = st.mode >> shift;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+/// `YYYY-MM-DD hh:mm` in UTC
+fn time_string(secs: u64) -> String {
+    // days to a civil date, after Howard Hinnant's days_from_civil inverse
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60
+    )
+}
+
+fn show(path: &str, name: &str, long: bool) {
+    if !long {
+        println!("{}", name);
+        return;
+    }
+    let path = format!("{}\0", path);
+    let Some(st) = lstat(path.as_str()) else {
+        println!("ls: cannot access {}", name);
+        return;
+    };
+    let mut target = [0u8; 256];
+    let len = if st.is_symlink() {
+        readlink(path.as_str(), &mut target).max(0) as usize
+    } else {
+        0
+    };
+    println!(
+        "{} {:>2} {} {} {:>8} {} {}{}{}",
+        mode_string(&st),
+        st.nlink,
+        st.uid,
+        st.gid,
+        st.size,
+        time_string(st.mtime),
+        name,
+        if len > 0 { " -> " } else { "" },
+        core::str::from_utf8(&target[..len]).unwrap_or("?")
+    );
+}
+
+/// List a directory by its entries, anything else by its name
+fn list(path: &str, long: bool, header: bool) -> bool {
+    let Some(st) = lstat(format!("{}\0", path).as_str()) else {
+        println!("ls: cannot access {}", path);
+        return false;
+    };
+    if !st.is_dir() {
+        show(path, path, long);
+        return true;
+    }
+    let fd = open(format!("{}\0", path).as_str(), OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("ls: cannot open {}", path);
+        return false;
+    }
+    if header {
+        println!("{}:", path);
+    }
+    let mut buf = [0u8; 512];
+    loop {
+        let len = getdents(fd as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        for dirent in dirents(&buf[..len as usize]) {
+            show(
+                format!("{}/{}", path, dirent.name).as_str(),
+                dirent.name,
+                long,
+            );
+        }
+    }
+    close(fd as usize);
+    true
+}
+
+#[unsafe(no_mangle)]
+pub fn main(_argc: usize, argv: &[&str]) -> i32 {
+    let long = argv.get(1) == Some(&"-l");
+    let paths = &argv[if long { 2 } else { 1 }..];
+    if paths.is_empty() {
+        return if list(".", long, false) { 0 } else { 1 };
+    }
+    let mut status = 0;
+    for path in paths {
+        if !list(path, long, paths.len() > 1) {
+            status = 1;
+        }
+    }
+    status
+}
@@ -0,0 +1,138 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    MmapFlags, MmapProt, OpenFlags, close, fork, mmap_file, msync, munmap, open, read, waitpid,
+    write,
+};
+
+const PAGE_SIZE: usize = 4096;
+const LEN: usize = 2 * PAGE_SIZE + 100;
+
+fn expected(i: usize) -> u8 {
+    (i % 239) as u8
+}
+
+fn read_file(name: &str, buf: &mut [u8]) -> usize {
+    let fd = open(name, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut total = 0;
+    loop {
+        let len = read(fd, &mut buf[total..]);
+        if len <= 0 {
+            break;
+        }
+        total += len as usize;
+    }
+    close(fd);
+    total
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let name = "mmap_file_data\0";
+    let fd = open(
+        name,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut data = [0u8; LEN];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = expected(i);
+    }
+    assert_eq!(write(fd, &data), LEN as isize);
+    close(fd);
+
+    // a private mapping sees the file but its writes never reach it
+    let fd = open(name, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let map_len = 3 * PAGE_SIZE;
+    assert_eq!(
+        mmap_file(
+            0,
+            map_len,
+            MmapProt::READ | MmapProt::WRITE,
+            MmapFlags::SHARED,
+            fd,
+            0
+        ),
+        -1
+    );
+    let addr = mmap_file(
+        0,
+        map_len,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE,
+        fd,
+        0,
+    );
+    assert!(addr > 0);
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as usize as *mut u8, map_len) };
+    for i in 0..LEN {
+        assert_eq!(buf[i], expected(i));
+    }
+    // past the end of file the last page is zero filled
+    assert!(buf[LEN..].iter().all(|byte| *byte == 0));
+    buf[0] = 0xff;
+    assert_eq!(munmap(addr as usize, map_len), 0);
+    close(fd);
+    let mut check = [0u8; LEN];
+    assert_eq!(read_file(name, &mut check), LEN);
+    assert_eq!(check[0], expected(0));
+
+    // a shared mapping is written back by msync and munmap, and is
+    // shared with a forked child
+    let fd = open(name, OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let addr = mmap_file(
+        0,
+        map_len,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::SHARED,
+        fd,
+        0,
+    );
+    assert!(addr > 0);
+    let addr = addr as usize;
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, map_len) };
+    buf[1] = 0xee;
+    assert_eq!(msync(addr, map_len), 0);
+    assert_eq!(read_file(name, &mut check), LEN);
+    assert_eq!(check[1], 0xee);
+
+    // fault the page in first so that parent and child map the same frame
+    assert_eq!(buf[PAGE_SIZE], expected(PAGE_SIZE));
+    let pid = fork();
+    if pid == 0 {
+        buf[PAGE_SIZE] = 0xdd;
+        return 0;
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(buf[PAGE_SIZE], 0xdd);
+    buf[LEN - 1] = 0xcc;
+    // writes past the end of file are dropped, the file does not grow
+    buf[LEN] = 0xbb;
+    assert_eq!(munmap(addr, map_len), 0);
+    close(fd);
+    assert_eq!(read_file(name, &mut check), LEN);
+    for i in 0..LEN {
+        let want = match i {
+            1 => 0xee,
+            PAGE_SIZE => 0xdd,
+            _ if i == LEN - 1 => 0xcc,
+            _ => expected(i),
+        };
+        assert_eq!(check[i], want);
+    }
+    println!("mmap_file passed!");
+    0
+}
@@ -0,0 +1,98 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{MmapFlags, MmapProt, brk, mmap, mprotect, munmap, sbrk};
+
+const PAGE_SIZE: usize = 4096;
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    // heap: grow with sbrk, write to every page, then shrink back
+    let base = brk(0);
+    assert!(base > 0);
+    assert_eq!(sbrk(4 * PAGE_SIZE as isize), base);
+    assert_eq!(brk(0), base + 4 * PAGE_SIZE as isize);
+    let heap = base as usize as *mut u8;
+    for page in 0..4 {
+        unsafe {
+            heap.add(page * PAGE_SIZE).write_volatile(page as u8);
+        }
+    }
+    for page in 0..4 {
+        assert_eq!(
+            unsafe { heap.add(page * PAGE_SIZE).read_volatile() },
+            page as u8
+        );
+    }
+    assert_eq!(sbrk(-4 * PAGE_SIZE as isize), base + 4 * PAGE_SIZE as isize);
+    assert_eq!(brk(0), base);
+    assert_eq!(sbrk(-1), -1);
+
+    // anonymous mapping, zero filled and private
+    let len = 8 * PAGE_SIZE;
+    let addr = mmap(
+        0,
+        len,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    );
+    assert!(addr > 0);
+    let addr = addr as usize;
+    assert_eq!(addr % PAGE_SIZE, 0);
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) };
+    assert!(buf.iter().all(|byte| *byte == 0));
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = (i % 253) as u8;
+    }
+    for (i, byte) in buf.iter().enumerate() {
+        assert_eq!(*byte, (i % 253) as u8);
+    }
+
+    // the range is taken, a fixed mapping on top of it must fail
+    assert_eq!(
+        mmap(
+            addr,
+            PAGE_SIZE,
+            MmapProt::READ,
+            MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::FIXED,
+        ),
+        -1
+    );
+    // bad arguments
+    assert_eq!(
+        mmap(
+            0,
+            0,
+            MmapProt::READ,
+            MmapFlags::PRIVATE | MmapFlags::ANONYMOUS
+        ),
+        -1
+    );
+    assert_eq!(
+        mmap(
+            0,
+            usize::MAX,
+            MmapProt::READ,
+            MmapFlags::PRIVATE | MmapFlags::ANONYMOUS
+        ),
+        -1
+    );
+    assert_eq!(munmap(addr + 1, PAGE_SIZE), -1);
+    assert_eq!(munmap(addr, usize::MAX), -1);
+    assert_eq!(mprotect(addr, usize::MAX, MmapProt::READ), -1);
+
+    // read only mappings can still be read
+    assert_eq!(mprotect(addr, len, MmapProt::READ), 0);
+    assert_eq!(buf[PAGE_SIZE + 1], ((PAGE_SIZE + 1) % 253) as u8);
+    assert_eq!(mprotect(addr, len, MmapProt::READ | MmapProt::WRITE), 0);
+    buf[0] = 0xff;
+    assert_eq!(buf[0], 0xff);
+
+    assert_eq!(munmap(addr, len), 0);
+    assert_eq!(munmap(addr, len), -1);
+    println!("mmap_test passed!");
+    0
+}
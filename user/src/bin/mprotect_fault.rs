@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{MmapFlags, MmapProt, mmap, mprotect};
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("Into Test mprotect_fault, we will write to a read-only mapping...");
+    println!("Kernel should kill this application!");
+    let addr = mmap(
+        0,
+        4096,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    );
+    assert!(addr > 0);
+    let ptr = addr as usize as *mut u8;
+    unsafe {
+        ptr.write_volatile(1);
+    }
+    assert_eq!(mprotect(addr as usize, 4096, MmapProt::READ), 0);
+    unsafe {
+        ptr.write_volatile(2);
+    }
+    0
+}
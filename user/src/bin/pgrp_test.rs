@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    SIG_IGN, SIGCONT, SIGKILL, SIGTSTP, SIGTTIN, SignalAction, TtyFlags, WNOHANG, WUNTRACED, exit,
+    fork, getpgid, getpid, kill, read, setpgid, sigaction, sleep, tcgetattr, tcgetpgrp, tcsetattr,
+    tcsetpgrp, waitpid_options,
+};
+
+/// Start a group of its own and read from the console, exits with 1 if the
+/// read failed
+fn background_reader(ignore_sigttin: bool) -> ! {
+    assert_eq!(setpgid(0, 0), 0);
+    if ignore_sigttin {
+        let ignore = SignalAction {
+            handler: SIG_IGN,
+            ..Default::default()
+        };
+        assert_eq!(sigaction(SIGTTIN, Some(&ignore), None), 0);
+    }
+    let mut buf = [0u8; 1];
+    exit((read(0, &mut buf) == -1) as i32)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let pid = getpid() as usize;
+    let foreground = tcgetpgrp(0);
+    assert!(foreground > 0);
+
+    // a process can start its own group
+    assert_eq!(setpgid(0, 0), 0);
+    assert_eq!(getpgid(0), pid as isize);
+
+    // a child joins the group of its parent, or one of its own
+    let child = fork();
+    if child == 0 {
+        loop {
+            sleep(10);
+        }
+    }
+    let child = child as usize;
+    assert_eq!(getpgid(child), pid as isize);
+    assert_eq!(setpgid(child, 0), 0);
+    assert_eq!(getpgid(child), child as isize);
+    assert_eq!(setpgid(child, 12345), -1);
+
+    // stops are reported once with WUNTRACED
+    let mut exit_code = 0;
+    assert_eq!(kill(-(child as isize), SIGTSTP), 0);
+    assert_eq!(waitpid_options(child, &mut exit_code, WUNTRACED), -3);
+    assert_eq!(exit_code, SIGTSTP);
+    assert_eq!(
+        waitpid_options(child, &mut exit_code, WNOHANG | WUNTRACED),
+        -2
+    );
+    assert_eq!(kill(-(child as isize), SIGCONT), 0);
+    assert_eq!(kill(-(child as isize), SIGKILL), 0);
+    assert_eq!(waitpid_options(child, &mut exit_code, 0), child as isize);
+    assert_eq!(exit_code, -SIGKILL);
+    assert_eq!(kill(-(child as isize), SIGCONT), -1);
+
+    // the console can be handed to a group and its modes switched
+    assert_eq!(tcsetpgrp(0, pid), 0);
+    assert_eq!(tcgetpgrp(0), pid as isize);
+    let flags = tcgetattr(0).unwrap();
+    assert!(flags.contains(TtyFlags::ICANON | TtyFlags::ECHO | TtyFlags::ISIG));
+    assert_eq!(tcsetattr(0, flags - TtyFlags::ICANON - TtyFlags::ECHO), 0);
+    assert_eq!(
+        tcgetattr(0),
+        Some(flags - TtyFlags::ICANON - TtyFlags::ECHO)
+    );
+    assert_eq!(tcsetattr(0, flags), 0);
+
+    // a background reader is stopped with SIGTTIN rather than read from,
+    // and fails to read if it ignores SIGTTIN
+    let child = fork();
+    if child == 0 {
+        background_reader(false);
+    }
+    let child = child as usize;
+    assert_eq!(waitpid_options(child, &mut exit_code, WUNTRACED), -3);
+    assert_eq!(exit_code, SIGTTIN);
+    // continued, it is stopped again as it reads once more
+    assert_eq!(kill(child as isize, SIGCONT), 0);
+    assert_eq!(waitpid_options(child, &mut exit_code, WUNTRACED), -3);
+    assert_eq!(exit_code, SIGTTIN);
+    assert_eq!(kill(child as isize, SIGKILL), 0);
+    assert_eq!(waitpid_options(child, &mut exit_code, 0), child as isize);
+    let child = fork();
+    if child == 0 {
+        background_reader(true);
+    }
+    let child = child as usize;
+    assert_eq!(waitpid_options(child, &mut exit_code, 0), child as isize);
+    assert_eq!(exit_code, 1);
+    assert_eq!(tcsetpgrp(0, foreground as usize), 0);
+
+    println!("pgrp_test passed!");
+    0
+}
@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    SCHED_FIFO, close, exit, fork, get_time, getpid, nice, pipe, read, sched_setscheduler,
+    set_priority, waitpid, write,
+};
+
+const PRIORITIES: [isize; 4] = [4, 8, 16, 32];
+/// Spinners per priority, so there are more of them than harts
+const COPIES: usize = 4;
+const RUN_MS: isize = 1000;
+
+fn spin(prio: isize, report: usize) -> ! {
+    assert_eq!(set_priority(prio), prio);
+    let start = get_time();
+    let mut count: usize = 0;
+    while get_time() - start < RUN_MS {
+        count += 1;
+    }
+    println!(
+        "pid {}: priority {}, count {}, count / priority = {}",
+        getpid(),
+        prio,
+        count,
+        count / prio as usize
+    );
+    write(report, &count.to_le_bytes());
+    exit(0)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    assert_eq!(set_priority(1), -1);
+    assert_eq!(set_priority(16), 16);
+    assert_eq!(nice(4), 12);
+    assert_eq!(nice(-8), 20);
+
+    // the policy is the whole machine's, only initproc may switch it
+    assert_eq!(sched_setscheduler(SCHED_FIFO), -1);
+
+    // under stride scheduling, the one the kernel boots with, the cpu share
+    // grows with the priority
+    let mut pids = [[0isize; COPIES]; PRIORITIES.len()];
+    let mut pipes = [[0usize; 2]; PRIORITIES.len()];
+    for (i, prio) in PRIORITIES.iter().enumerate() {
+        assert_eq!(pipe(&mut pipes[i]), 0);
+        for pid in pids[i].iter_mut() {
+            *pid = fork();
+            if *pid == 0 {
+                spin(*prio, pipes[i][1]);
+            }
+        }
+        close(pipes[i][1]);
+    }
+    let mut exit_code: i32 = 0;
+    for pid in pids.iter().flatten() {
+        assert_eq!(waitpid(*pid as usize, &mut exit_code), *pid);
+        assert_eq!(exit_code, 0);
+    }
+    let mut shares = [0usize; PRIORITIES.len()];
+    for (i, share) in shares.iter_mut().enumerate() {
+        let mut buf = [0u8; 8];
+        for _ in 0..COPIES {
+            assert_eq!(read(pipes[i][0], &mut buf), 8);
+            *share += usize::from_le_bytes(buf);
+        }
+        close(pipes[i][0]);
+    }
+    println!("counts by priority {:?}: {:?}", PRIORITIES, shares);
+    // each doubling of the priority should about double the share. Only
+    // the order is checked, the harts and timer ticks blur the ratios.
+    for pair in shares.windows(2) {
+        assert!(pair[1] > pair[0], "share did not grow with priority");
+    }
+    assert!(
+        shares[PRIORITIES.len() - 1] > shares[0] * 2,
+        "the highest priority got no more than twice the lowest's share"
+    );
+    println!("sched_priority passed!");
+    0
+}
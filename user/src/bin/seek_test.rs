@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    OpenFlags, SEEK_CUR, SEEK_END, SEEK_SET, close, exit, fork, fstat, ftruncate, lseek, open,
+    pread, pwrite, read, unlink, waitpid, write,
+};
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let fd = open(
+        "seek_file\0",
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::RDWR,
+    ) as usize;
+    assert_eq!(write(fd, b"hello, world"), 12);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 12);
+    assert_eq!(lseek(fd, 7, SEEK_SET), 7);
+    let mut buf = [0u8; 16];
+    assert_eq!(read(fd, &mut buf), 5);
+    assert_eq!(&buf[..5], b"world");
+    assert_eq!(lseek(fd, -5, SEEK_END), 7);
+    assert_eq!(write(fd, b"there"), 5);
+    assert_eq!(lseek(fd, -13, SEEK_END), -1);
+    assert_eq!(lseek(fd, 0, 3), -1);
+
+    // pread and pwrite leave the offset alone
+    assert_eq!(pwrite(fd, b"J", 0), 1);
+    assert_eq!(pread(fd, &mut buf, 0), 12);
+    assert_eq!(&buf[..12], b"Jello, there");
+    assert_eq!(pread(fd, &mut buf, 100), 0);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 12);
+
+    // seeking past the end leaves a hole of zeros
+    assert_eq!(lseek(fd, 20, SEEK_SET), 20);
+    assert_eq!(write(fd, b"!"), 1);
+    assert_eq!(fstat(fd).unwrap().size, 21);
+    assert_eq!(pread(fd, &mut buf, 12), 9);
+    assert_eq!(&buf[..9], b"\0\0\0\0\0\0\0\0!");
+
+    assert_eq!(ftruncate(fd, 5), 0);
+    assert_eq!(fstat(fd).unwrap().size, 5);
+    assert_eq!(ftruncate(fd, 8), 0);
+    assert_eq!(pread(fd, &mut buf, 0), 8);
+    assert_eq!(&buf[..8], b"Jello\0\0\0");
+    close(fd);
+
+    // appends land at the end whatever the offset, shared across a fork
+    let fd = open("seek_file\0", OpenFlags::WRONLY | OpenFlags::APPEND) as usize;
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(write(fd, b"child"), 5);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(write(fd, b"parent"), 6);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 19);
+    assert_eq!(pread(fd, &mut buf, 0), -1);
+    close(fd);
+    let fd = open("seek_file\0", OpenFlags::RDONLY) as usize;
+    let mut buf = [0u8; 32];
+    assert_eq!(read(fd, &mut buf), 19);
+    assert_eq!(&buf[..19], b"Jello\0\0\0childparent");
+    assert_eq!(ftruncate(fd, 0), -1);
+    close(fd);
+
+    // creating a file that is already there keeps what it holds, as `>>`
+    // in the shell does
+    let fd = open(
+        "seek_file\0",
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::APPEND,
+    ) as usize;
+    assert_eq!(write(fd, b"!"), 1);
+    close(fd);
+    let fd = open("seek_file\0", OpenFlags::RDONLY) as usize;
+    assert_eq!(read(fd, &mut buf), 20);
+    assert_eq!(&buf[..20], b"Jello\0\0\0childparent!");
+    close(fd);
+
+    // a write far past the end only backs the block it lands in
+    let fd = open("sparse_file\0", OpenFlags::CREATE | OpenFlags::RDWR) as usize;
+    assert_eq!(lseek(fd, 4 << 20, SEEK_SET), 4 << 20);
+    assert_eq!(write(fd, b"x"), 1);
+    let st = fstat(fd).unwrap();
+    assert_eq!(st.size, (4 << 20) + 1);
+    assert!(st.blocks < 8);
+    assert_eq!(pread(fd, &mut buf, 1 << 20), 32);
+    assert!(buf.iter().all(|byte| *byte == 0));
+    close(fd);
+    assert_eq!(unlink("sparse_file\0"), 0);
+
+    // pipes and the console have no offset
+    assert_eq!(lseek(0, 0, SEEK_SET), -1);
+    assert_eq!(unlink("seek_file\0"), 0);
+    println!("seek_test passed!");
+    0
+}
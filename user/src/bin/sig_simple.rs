@@ -0,0 +1,120 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    SIG_BLOCK, SIG_IGN, SIG_SETMASK, SIGCONT, SIGKILL, SIGSTOP, SIGTERM, SIGUSR1, SignalAction,
+    SignalFlags, exit, fork, getpid, kill, mutex_blocking_create, mutex_lock, sigaction,
+    sigprocmask, sleep, thread_create, waitpid,
+};
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_usr1(signum: i32) {
+    assert_eq!(signum, SIGUSR1);
+    HANDLED.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Take mutex 1 then mutex 0, the main thread takes them the other way round
+fn lock_reversed() -> ! {
+    mutex_lock(1);
+    sleep(50);
+    mutex_lock(0);
+    exit(0)
+}
+
+fn wait_child(pid: usize) -> i32 {
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+    exit_code
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let pid = getpid();
+
+    // a user handler runs and returns through sigreturn
+    let action = SignalAction {
+        handler: on_usr1 as usize,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SIGUSR1, Some(&action), None), 0);
+    assert_eq!(kill(pid, SIGUSR1), 0);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 1);
+
+    // numbers no signal has, and a group id that can't be negated
+    assert_eq!(kill(pid, 16), -1);
+    assert_eq!(kill(pid, 32), -1);
+    assert_eq!(sigaction(16, Some(&action), None), -1);
+    assert_eq!(kill(isize::MIN, SIGUSR1), -1);
+
+    // a blocked signal stays pending until it is unblocked
+    let mut old = SignalFlags::empty();
+    assert_eq!(
+        sigprocmask(SIG_BLOCK, Some(SignalFlags::SIGUSR1), Some(&mut old)),
+        0
+    );
+    assert_eq!(kill(pid, SIGUSR1), 0);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 1);
+    assert_eq!(sigprocmask(SIG_SETMASK, Some(old), None), 0);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 2);
+
+    // SIGKILL can be neither caught nor ignored
+    let ignore = SignalAction {
+        handler: SIG_IGN,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SIGKILL, Some(&ignore), None), -1);
+
+    // the default action of SIGTERM terminates the child
+    let child = fork();
+    if child == 0 {
+        loop {
+            sleep(10);
+        }
+    }
+    assert_eq!(kill(child, SIGTERM), 0);
+    assert_eq!(wait_child(child as usize), -SIGTERM);
+
+    // an ignored signal is dropped
+    let child = fork();
+    if child == 0 {
+        assert_eq!(sigaction(SIGTERM, Some(&ignore), None), 0);
+        kill(getpid(), SIGTERM);
+        exit(0);
+    }
+    assert_eq!(wait_child(child as usize), 0);
+
+    // a stopped child resumes on SIGCONT
+    let child = fork();
+    if child == 0 {
+        sleep(50);
+        exit(7);
+    }
+    assert_eq!(kill(child, SIGSTOP), 0);
+    sleep(100);
+    assert_eq!(kill(child, SIGCONT), 0);
+    assert_eq!(wait_child(child as usize), 7);
+
+    // SIGKILL ends a child whose threads deadlocked, the main thread too
+    // waits for a mutex
+    let child = fork();
+    if child == 0 {
+        assert_eq!(mutex_blocking_create(), 0);
+        assert_eq!(mutex_blocking_create(), 1);
+        assert_eq!(mutex_lock(0), 0);
+        thread_create(lock_reversed as usize, 0);
+        sleep(20);
+        mutex_lock(1);
+        exit(0);
+    }
+    sleep(100);
+    assert_eq!(kill(child, SIGKILL), 0);
+    assert_eq!(wait_child(child as usize), -SIGKILL);
+
+    println!("sig_simple passed!");
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{OpenFlags, S_IFDIR, S_IFREG, close, fstat, mkdir, open, rm, stat, write};
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let name = "stat_file\0";
+    let fd = open(
+        name,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let data = [b'x'; 1000];
+    assert_eq!(write(fd, &data), 1000);
+    let st = fstat(fd).unwrap();
+    close(fd);
+    assert_eq!(st.size, 1000);
+    assert_eq!(st.mode, S_IFREG | 0o644);
+    assert_eq!(st.nlink, 1);
+    assert!(st.mtime > 0 && st.ctime >= st.mtime);
+
+    let by_path = stat(name).unwrap();
+    assert_eq!(by_path.ino, st.ino);
+    assert_eq!(by_path.size, st.size);
+    assert!(by_path.mtime >= st.mtime);
+    assert!(stat("no_such_file\0").is_none());
+
+    let root = stat(".\0").unwrap();
+    assert!(root.is_dir());
+    assert!(mkdir("stat_dir\0") == 0);
+    let dir = stat("stat_dir\0").unwrap();
+    assert_eq!(dir.mode, S_IFDIR | 0o755);
+    assert_eq!(dir.nlink, 2);
+    assert_eq!(stat(".\0").unwrap().nlink, root.nlink + 1);
+    assert_eq!(rm("stat_dir\0"), 0);
+    assert_eq!(stat(".\0").unwrap().nlink, root.nlink);
+    assert_eq!(rm(name), 0);
+    println!("stat_test passed!");
+    0
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{MmapFlags, MmapProt, exit, fork, mmap, munmap, waitpid};
+
+const PAGE_SIZE: usize = 4096;
+/// More than the 128 MiB of RAM QEMU is given, part of it has to be swapped
+const PAGES: usize = 144 * 256;
+
+fn pattern(page: usize) -> usize {
+    page.wrapping_mul(0x9e37_79b9) ^ 0x5a5a
+}
+
+fn check(base: *mut usize, step: usize) {
+    for page in (0..PAGES).step_by(step) {
+        let word = unsafe { base.add(page * PAGE_SIZE / 8).read_volatile() };
+        assert_eq!(word, pattern(page));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let addr = mmap(
+        0,
+        PAGES * PAGE_SIZE,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    );
+    assert!(addr > 0);
+    let base = addr as usize as *mut usize;
+    for page in 0..PAGES {
+        unsafe {
+            base.add(page * PAGE_SIZE / 8).write_volatile(pattern(page));
+        }
+    }
+    println!("swap_test: {} pages written", PAGES);
+    check(base, 1);
+    // swapped out pages are shared with the child like resident ones
+    let pid = fork();
+    if pid == 0 {
+        check(base, 7);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    check(base, 1);
+    assert_eq!(munmap(addr as usize, PAGES * PAGE_SIZE), 0);
+    println!("swap_test passed!");
+    0
+}
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{OpenFlags, close, fsync, open, pipe, read, sync, unlink, write};
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let fd = open("sync_file\0", OpenFlags::CREATE | OpenFlags::RDWR) as usize;
+    assert_eq!(write(fd, b"written back"), 12);
+    assert_eq!(fsync(fd), 0);
+    close(fd);
+    assert_eq!(sync(), 0);
+    let fd = open("sync_file\0", OpenFlags::RDONLY) as usize;
+    let mut buf = [0u8; 16];
+    assert_eq!(read(fd, &mut buf), 12);
+    assert_eq!(&buf[..12], b"written back");
+    close(fd);
+    assert_eq!(fsync(fd), -1);
+
+    // only files on the disk can be synced
+    let mut fds = [0usize; 2];
+    assert_eq!(pipe(&mut fds), 0);
+    assert_eq!(fsync(fds[0]), -1);
+    close(fds[0]);
+    close(fds[1]);
+    assert_eq!(unlink("sync_file\0"), 0);
+    println!("sync_test passed!");
+    0
+}
@@ -8,19 +8,133 @@ use crate::alloc::string::ToString;
 #[macro_use]
 extern crate user_lib;
 
-const LF: u8 = 0x0au8;
-const CR: u8 = 0x0du8;
-const DL: u8 = 0x7fu8;
-const BS: u8 = 0x08u8;
 const LINE_START: &str = " >> ";
 
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
-use user_lib::console::getchar;
 use user_lib::{
-    OpenFlags, cd, close, dup, exec, fork, ls, mkdir, mv, open, pipe, read, rm, waitpid,
+    OpenFlags, SIG_DFL, SIG_IGN, SIGCONT, SIGINT, SIGTSTP, SIGTTIN, SIGTTOU, SignalAction, WNOHANG,
+    WUNTRACED, chdir, close, dup, exec, fork, getcwd, getpgid, getpid, kill, link, mkdir, mv, open,
+    pipe, read, rm, setpgid, sigaction, symlink, tcsetpgrp, waitpid_options,
 };
 
+#[derive(PartialEq)]
+enum JobState {
+    Running,
+    Stopped,
+}
+
+/// A pipeline started by the shell, all its processes share one group
+struct Job {
+    id: usize,
+    pgid: usize,
+    /// Processes not reaped yet
+    pids: Vec<usize>,
+    command: String,
+    state: JobState,
+}
+
+impl Job {
+    fn print(&self) {
+        let state = match self.state {
+            JobState::Running => "Running",
+            JobState::Stopped => "Stopped",
+        };
+        println!("[{}] {}\t{}", self.id, state, self.command);
+    }
+
+    /// Wait for the job to exit or to stop, true if it stopped
+    fn wait(&mut self) -> bool {
+        let mut stopped = false;
+        let mut i = 0;
+        while i < self.pids.len() {
+            let mut exit_code: i32 = 0;
+            if waitpid_options(self.pids[i], &mut exit_code, WUNTRACED) == -3 {
+                stopped = true;
+                i += 1;
+            } else {
+                self.pids.remove(i);
+            }
+        }
+        stopped
+    }
+
+    /// Reap what exited without waiting, true once nothing is left
+    fn poll(&mut self) -> bool {
+        let mut i = 0;
+        while i < self.pids.len() {
+            let mut exit_code: i32 = 0;
+            match waitpid_options(self.pids[i], &mut exit_code, WNOHANG | WUNTRACED) {
+                -2 => i += 1,
+                -3 => {
+                    self.state = JobState::Stopped;
+                    i += 1;
+                }
+                _ => {
+                    self.pids.remove(i);
+                }
+            }
+        }
+        self.pids.is_empty()
+    }
+}
+
+/// Give the console to `job` and wait for it, a stopped job goes back to
+/// the job list
+fn run_foreground(mut job: Job, jobs: &mut Vec<Job>, shell_pgid: usize) {
+    tcsetpgrp(0, job.pgid);
+    if job.state == JobState::Stopped {
+        job.state = JobState::Running;
+        kill(-(job.pgid as isize), SIGCONT);
+    }
+    let stopped = job.wait();
+    tcsetpgrp(0, shell_pgid);
+    if stopped {
+        job.state = JobState::Stopped;
+        job.print();
+        jobs.push(job);
+    }
+}
+
+/// The job named by `%n` or `n`, the latest one without an argument
+fn find_job(jobs: &[Job], args: &[String]) -> Option<usize> {
+    match args.get(1) {
+        None => jobs.len().checked_sub(1),
+        Some(arg) => {
+            let id: usize = arg
+                .trim_end_matches('\0')
+                .trim_start_matches('%')
+                .parse()
+                .ok()?;
+            jobs.iter().position(|job| job.id == id)
+        }
+    }
+}
+
+extern "C" fn on_interrupt(_signum: i32) {}
+
+/// The shell leaves Ctrl-Z and background reads to its jobs, and Ctrl-C only
+/// abandons the line being typed
+fn set_shell_signals(shell: bool) {
+    let interrupt = SignalAction {
+        handler: if shell {
+            on_interrupt as usize
+        } else {
+            SIG_DFL
+        },
+        ..Default::default()
+    };
+    sigaction(SIGINT, Some(&interrupt), None);
+    let job_control = SignalAction {
+        handler: if shell { SIG_IGN } else { SIG_DFL },
+        ..Default::default()
+    };
+    for signum in [SIGTSTP, SIGTTIN, SIGTTOU] {
+        sigaction(signum, Some(&job_control), None);
+    }
+}
+
 #[derive(Debug)]
 struct ProcessArguments {
     input: String,
@@ -77,266 +191,304 @@ impl ProcessArguments {
     }
 }
 
-fn edit_path(current_path: String, command: &str) -> String {
-    let mut components = Vec::new();
-
-    if command.starts_with('/') {
-        components = Vec::new();
-    } else {
-        components = current_path
-            .split('/')
-            .filter(|&s| !s.is_empty())
-            .map(|s| s.to_string())
-            .collect();
-    }
-
-    for part in command.split('/') {
-        match part {
-            "" | "." => {}
-            ".." => {
-                components.pop();
-            }
-            x => {
-                components.push(x.to_string());
-            }
+/// The working directory, for the prompt
+fn current_path() -> String {
+    let mut buf = [0u8; 256];
+    match getcwd(&mut buf) {
+        len if len > 0 => {
+            String::from(core::str::from_utf8(&buf[..len as usize - 1]).unwrap_or("?"))
         }
+        _ => String::from("?"),
     }
-
-    let mut result = String::from("/");
-    result.push_str(&components.join("/"));
-    while result.contains("//") {
-        result = result.replace("//", "/");
-    }
-    result
 }
+
 #[unsafe(no_mangle)]
 pub fn main() -> i32 {
     println!("Rust user shell");
+    setpgid(0, 0);
+    let shell_pgid = getpid() as usize;
+    tcsetpgrp(0, shell_pgid);
+    set_shell_signals(true);
+    let mut jobs: Vec<Job> = Vec::new();
     let mut line: String = String::new();
-    let mut current_path: String = String::from("/");
-    let mut current_inode_id: usize = 0;
-    print!("{}", current_path.clone() + LINE_START);
     loop {
-        let c = getchar();
-        match c {
-            LF | CR => {
-                println!("");
-                if !line.is_empty() {
-                    let splited: Vec<_> = line.as_str().split('|').collect();
-                    let process_arguments_list: Vec<_> = splited
-                        .iter()
-                        .map(|&cmd| ProcessArguments::new(cmd))
-                        .collect();
-                    let mut valid = true;
-                    for (i, process_args) in process_arguments_list.iter().enumerate() {
-                        if i == 0 {
-                            if !process_args.output.is_empty() {
-                                valid = false;
-                            }
-                        } else if i == process_arguments_list.len() - 1 {
-                            if !process_args.input.is_empty() {
-                                valid = false;
-                            }
-                        } else if !process_args.output.is_empty() || !process_args.input.is_empty()
-                        {
-                            valid = false;
-                        }
+        jobs.retain_mut(|job| {
+            if job.poll() {
+                println!("[{}] Done\t{}", job.id, job.command);
+                false
+            } else {
+                true
+            }
+        });
+        print!("{}", current_path() + LINE_START);
+        // the console echoes and edits the line, Ctrl-C cuts it short
+        line.clear();
+        let mut buf = [0u8; 256];
+        loop {
+            let size = read(0, &mut buf);
+            if size <= 0 {
+                line.clear();
+                break;
+            }
+            for &c in buf[..size as usize].iter() {
+                line.push(c as char);
+            }
+            if line.ends_with('\n') {
+                break;
+            }
+        }
+        let input = line.trim();
+        let (command, background) = match input.strip_suffix('&') {
+            Some(command) => (command.trim_end(), true),
+            None => (input, false),
+        };
+        if command.is_empty() {
+            continue;
+        }
+        let builtin = ProcessArguments::new(command).args_copy;
+        match builtin[0].as_str() {
+            "jobs\0" => {
+                for job in jobs.iter() {
+                    job.print();
+                }
+                continue;
+            }
+            "fg\0" => {
+                let Some(idx) = find_job(&jobs, &builtin) else {
+                    println!("fg: no such job");
+                    continue;
+                };
+                let job = jobs.remove(idx);
+                println!("{}", job.command);
+                run_foreground(job, &mut jobs, shell_pgid);
+                continue;
+            }
+            "bg\0" => {
+                let Some(idx) = find_job(&jobs, &builtin) else {
+                    println!("bg: no such job");
+                    continue;
+                };
+                let job = &mut jobs[idx];
+                job.state = JobState::Running;
+                kill(-(job.pgid as isize), SIGCONT);
+                println!("[{}] {} &", job.id, job.command);
+                continue;
+            }
+            _ => {}
+        }
+        let splited: Vec<_> = command.split('|').collect();
+        let process_arguments_list: Vec<_> = splited
+            .iter()
+            .map(|&cmd| ProcessArguments::new(cmd))
+            .collect();
+        let mut valid = true;
+        for (i, process_args) in process_arguments_list.iter().enumerate() {
+            if i == 0 {
+                if !process_args.output.is_empty() {
+                    valid = false;
+                }
+            } else if i == process_arguments_list.len() - 1 {
+                if !process_args.input.is_empty() {
+                    valid = false;
+                }
+            } else if !process_args.output.is_empty() || !process_args.input.is_empty() {
+                valid = false;
+            }
+        }
+        if process_arguments_list.len() == 1 {
+            valid = true;
+        }
+        if !valid {
+            println!("Invalid command: Inputs/Outputs cannot be correctly binded!");
+        } else {
+            // create pipes
+            let mut pipes_fd: Vec<[usize; 2]> = Vec::new();
+            if !process_arguments_list.is_empty() {
+                for _ in 0..process_arguments_list.len() - 1 {
+                    let mut pipe_fd = [0usize; 2];
+                    pipe(&mut pipe_fd);
+                    pipes_fd.push(pipe_fd);
+                }
+            }
+            let mut children: Vec<usize> = Vec::new();
+            let mut pgid = 0;
+            for (i, process_argument) in process_arguments_list.iter().enumerate() {
+                let args_copy = &process_argument.args_copy;
+                if args_copy[0] == "mkdir\0" {
+                    if args_copy.len() != 2 {
+                        println!("Invalid command: mkdir requires one argument");
+                        continue;
                     }
-                    if process_arguments_list.len() == 1 {
-                        valid = true;
+                    if mkdir(args_copy[1].as_str()) == -1 {
+                        println!("Error when creating directory {}", args_copy[1]);
+                    };
+                    continue;
+                }
+                if args_copy[0] == "rm\0" {
+                    if args_copy.len() != 2 {
+                        println!("Invalid command: rm requires one argument");
+                        continue;
+                    }
+                    if rm(args_copy[1].as_str()) == -1 {
+                        println!("Error when removing file {}", args_copy[2]);
+                    };
+                    continue;
+                }
+                if args_copy[0] == "ln\0" {
+                    let symbolic = args_copy.get(1).map(String::as_str) == Some("-s\0");
+                    let paths = &args_copy[if symbolic { 2 } else { 1 }..];
+                    if paths.len() != 2 {
+                        println!("Invalid command: ln requires a target and a link name");
+                        continue;
                     }
-                    if !valid {
-                        println!("Invalid command: Inputs/Outputs cannot be correctly binded!");
+                    let ret = if symbolic {
+                        symlink(paths[0].as_str(), paths[1].as_str())
                     } else {
-                        // create pipes
-                        let mut pipes_fd: Vec<[usize; 2]> = Vec::new();
-                        if !process_arguments_list.is_empty() {
-                            for _ in 0..process_arguments_list.len() - 1 {
-                                let mut pipe_fd = [0usize; 2];
-                                pipe(&mut pipe_fd);
-                                pipes_fd.push(pipe_fd);
-                            }
-                        }
-                        let mut children: Vec<_> = Vec::new();
-                        for (i, process_argument) in process_arguments_list.iter().enumerate() {
-                            let args_copy = &process_argument.args_copy;
-                            if args_copy[0] == "mkdir\0" {
-                                if args_copy.len() != 2 {
-                                    println!("Invalid command: mkdir requires one argument");
-                                    continue;
-                                }
-                                if mkdir(current_inode_id, args_copy[1].as_str()) == -1 {
-                                    println!("Error when creating directory {}", args_copy[1]);
-                                };
-                                continue;
-                            }
-                            if args_copy[0] == "rm\0" {
-                                if args_copy.len() != 2 {
-                                    println!("Invalid command: rm requires one argument");
-                                    continue;
-                                }
-                                if rm(current_inode_id, args_copy[1].as_str()) == -1 {
-                                    println!("Error when removing file {}", args_copy[2]);
-                                };
-                                continue;
-                            }
-                            if args_copy[0] == "mv\0" {
-                                if args_copy.len() != 3 {
-                                    println!("Invalid command: mv requires two arguments");
-                                    continue;
-                                }
-                                if mv(
-                                    current_inode_id,
-                                    args_copy[1].as_str(),
-                                    args_copy[2].as_str(),
-                                ) == -1
-                                {
-                                    println!(
-                                        "Error when moving file {} to {}",
-                                        args_copy[1], args_copy[2]
-                                    );
-                                }
-                                continue;
-                            }
-                            if args_copy[0] == "cd\0" {
-                                if args_copy.len() != 2 {
-                                    println!("Invalid command: cd requires one argument");
-                                    continue;
-                                }
-                                let inode_id = cd(current_inode_id, args_copy[1].as_str());
-                                if inode_id != -1 {
-                                    current_inode_id = inode_id as usize;
-                                    current_path =
-                                        edit_path(current_path.clone(), args_copy[1].as_str());
-                                } else {
-                                    println!("Error when changing directory to {}", args_copy[1]);
-                                }
-                                continue;
-                            }
-                            if args_copy[0] == "ls\0" {
-                                if ls(current_inode_id) == -1 {
-                                    println!("Error when listing directory");
-                                };
-                                continue;
-                            }
-                            if args_copy[0] == "cat\0" {
-                                if args_copy.len() != 2 {
-                                    println!("Invalid command: cat requires one argument");
-                                    continue;
-                                }
-                                let fd = open(
-                                    current_inode_id,
-                                    args_copy[1].as_str(),
-                                    OpenFlags::RDONLY,
-                                );
-                                if fd == -1 {
-                                    panic!("Error occured when opening file");
-                                }
-                                let fd = fd as usize;
-                                let mut buf = [0u8; 256];
-                                loop {
-                                    let size = read(fd, &mut buf) as usize;
-                                    if size == 0 {
-                                        break;
-                                    }
-                                    print!("{}", core::str::from_utf8(&buf[..size]).unwrap());
-                                }
-                                close(fd);
-                                continue;
-                            }
-                            let pid = fork();
-                            if pid == 0 {
-                                let input = &process_argument.input;
-                                let output = &process_argument.output;
-                                let args_copy = &process_argument.args_copy;
-                                let args_addr = &process_argument.args_addr;
-                                // redirect input
-                                if !input.is_empty() {
-                                    let input_fd =
-                                        open(current_inode_id, input.as_str(), OpenFlags::RDONLY);
-                                    if input_fd == -1 {
-                                        println!("Error when opening file {}", input);
-                                        return -4;
-                                    }
-                                    let input_fd = input_fd as usize;
-                                    close(0);
-                                    assert_eq!(dup(input_fd), 0);
-                                    close(input_fd);
-                                }
-                                // redirect output
-                                if !output.is_empty() {
-                                    let output_fd = open(
-                                        current_inode_id,
-                                        output.as_str(),
-                                        OpenFlags::CREATE | OpenFlags::WRONLY,
-                                    );
-                                    if output_fd == -1 {
-                                        println!("Error when opening file {}", output);
-                                        return -4;
-                                    }
-                                    let output_fd = output_fd as usize;
-                                    close(1);
-                                    assert_eq!(dup(output_fd), 1);
-                                    close(output_fd);
-                                }
-                                // receive input from the previous process
-                                if i > 0 {
-                                    close(0);
-                                    let read_end = pipes_fd.get(i - 1).unwrap()[0];
-                                    assert_eq!(dup(read_end), 0);
-                                }
-                                // send output to the next process
-                                if i < process_arguments_list.len() - 1 {
-                                    close(1);
-                                    let write_end = pipes_fd.get(i).unwrap()[1];
-                                    assert_eq!(dup(write_end), 1);
-                                }
-                                // close all pipe ends inherited from the parent process
-                                for pipe_fd in pipes_fd.iter() {
-                                    close(pipe_fd[0]);
-                                    close(pipe_fd[1]);
-                                }
-                                // execute new application
-                                if exec(
-                                    current_inode_id,
-                                    args_copy[0].as_str(),
-                                    args_addr.as_slice(),
-                                ) == -1
-                                {
-                                    println!("Error when executing!");
-                                    return -4;
-                                }
-                                unreachable!();
-                            } else {
-                                children.push(pid);
-                            }
+                        link(paths[0].as_str(), paths[1].as_str())
+                    };
+                    if ret == -1 {
+                        println!("Error when linking {} to {}", paths[1], paths[0]);
+                    }
+                    continue;
+                }
+                if args_copy[0] == "mv\0" {
+                    if args_copy.len() != 3 {
+                        println!("Invalid command: mv requires two arguments");
+                        continue;
+                    }
+                    if mv(args_copy[1].as_str(), args_copy[2].as_str()) == -1 {
+                        println!(
+                            "Error when moving file {} to {}",
+                            args_copy[1], args_copy[2]
+                        );
+                    }
+                    continue;
+                }
+                if args_copy[0] == "cd\0" {
+                    if args_copy.len() != 2 {
+                        println!("Invalid command: cd requires one argument");
+                        continue;
+                    }
+                    if chdir(args_copy[1].as_str()) == -1 {
+                        println!("Error when changing directory to {}", args_copy[1]);
+                    }
+                    continue;
+                }
+                if args_copy[0] == "cat\0" {
+                    if args_copy.len() != 2 {
+                        println!("Invalid command: cat requires one argument");
+                        continue;
+                    }
+                    let fd = open(args_copy[1].as_str(), OpenFlags::RDONLY);
+                    if fd == -1 {
+                        panic!("Error occured when opening file");
+                    }
+                    let fd = fd as usize;
+                    let mut buf = [0u8; 256];
+                    loop {
+                        let size = read(fd, &mut buf) as usize;
+                        if size == 0 {
+                            break;
                         }
-                        for pipe_fd in pipes_fd.iter() {
-                            close(pipe_fd[0]);
-                            close(pipe_fd[1]);
+                        print!("{}", core::str::from_utf8(&buf[..size]).unwrap());
+                    }
+                    close(fd);
+                    continue;
+                }
+                let pid = fork();
+                if pid == 0 {
+                    // join the job, the first process names its group
+                    setpgid(0, pgid);
+                    if !background {
+                        tcsetpgrp(0, getpgid(0) as usize);
+                    }
+                    set_shell_signals(false);
+                    let input = &process_argument.input;
+                    let output = &process_argument.output;
+                    let args_copy = &process_argument.args_copy;
+                    let args_addr = &process_argument.args_addr;
+                    // redirect input
+                    if !input.is_empty() {
+                        let input_fd = open(input.as_str(), OpenFlags::RDONLY);
+                        if input_fd == -1 {
+                            println!("Error when opening file {}", input);
+                            return -4;
                         }
-                        let mut exit_code: i32 = 0;
-                        for pid in children.into_iter() {
-                            let exit_pid = waitpid(pid as usize, &mut exit_code);
-                            assert_eq!(pid, exit_pid);
-                            //println!("Shell: Process {} exited with code {}", pid, exit_code);
+                        let input_fd = input_fd as usize;
+                        close(0);
+                        assert_eq!(dup(input_fd), 0);
+                        close(input_fd);
+                    }
+                    // redirect output
+                    if !output.is_empty() {
+                        let output_fd = open(
+                            output.as_str(),
+                            OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+                        );
+                        if output_fd == -1 {
+                            println!("Error when opening file {}", output);
+                            return -4;
                         }
+                        let output_fd = output_fd as usize;
+                        close(1);
+                        assert_eq!(dup(output_fd), 1);
+                        close(output_fd);
+                    }
+                    // receive input from the previous process
+                    if i > 0 {
+                        close(0);
+                        let read_end = pipes_fd.get(i - 1).unwrap()[0];
+                        assert_eq!(dup(read_end), 0);
+                    }
+                    // send output to the next process
+                    if i < process_arguments_list.len() - 1 {
+                        close(1);
+                        let write_end = pipes_fd.get(i).unwrap()[1];
+                        assert_eq!(dup(write_end), 1);
+                    }
+                    // close all pipe ends inherited from the parent process
+                    for pipe_fd in pipes_fd.iter() {
+                        close(pipe_fd[0]);
+                        close(pipe_fd[1]);
                     }
-                    line.clear();
+                    // execute new application, bare names are also looked for
+                    // in the root
+                    let program = args_copy[0].as_str();
+                    if exec(program, args_addr.as_slice()) == -1
+                        && (program.contains('/')
+                            || exec(format!("/{}", program).as_str(), args_addr.as_slice()) == -1)
+                    {
+                        println!("Error when executing!");
+                        return -4;
+                    }
+                    unreachable!();
+                } else {
+                    if pgid == 0 {
+                        pgid = pid as usize;
+                    }
+                    // also done here, whichever side gets there first
+                    setpgid(pid as usize, pgid);
+                    children.push(pid as usize);
                 }
-                print!("{}", current_path.clone() + LINE_START);
             }
-            BS | DL => {
-                if !line.is_empty() {
-                    print!("{}", BS as char);
-                    print!(" ");
-                    print!("{}", BS as char);
-                    line.pop();
-                }
+            for pipe_fd in pipes_fd.iter() {
+                close(pipe_fd[0]);
+                close(pipe_fd[1]);
             }
-            _ => {
-                print!("{}", c as char);
-                line.push(c as char);
+            if !children.is_empty() {
+                let job = Job {
+                    id: jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1,
+                    pgid,
+                    pids: children,
+                    command: command.to_string(),
+                    state: JobState::Running,
+                };
+                if background {
+                    println!("[{}] {}", job.id, job.pgid);
+                    jobs.push(job);
+                } else {
+                    run_foreground(job, &mut jobs, shell_pgid);
+                }
             }
         }
     }
@@ -5,11 +5,18 @@
 extern crate user_lib;
 
 // not in SUCC_TESTS & FAIL_TESTS
-// count_lines, infloop, user_shell, usertests
+// count_lines, infloop, ls, user_shell, usertests
 
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("filetest_simple\0", "\0", "\0", "\0", 0),
+    ("stat_test\0", "\0", "\0", "\0", 0),
+    ("link_test\0", "\0", "\0", "\0", 0),
+    ("cwd_test\0", "\0", "\0", "\0", 0),
+    ("getdents_test\0", "\0", "\0", "\0", 0),
+    ("seek_test\0", "\0", "\0", "\0", 0),
+    ("sync_test\0", "\0", "\0", "\0", 0),
+    ("fs_sleep_test\0", "\0", "\0", "\0", 0),
     ("cat\0", "filea\0", "\0", "\0", 0),
     ("cmdline_args\0", "1\0", "2\0", "3\0", 0),
     ("exit\0", "\0", "\0", "\0", 0),
@@ -18,11 +25,19 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("forktest\0", "\0", "\0", "\0", 0),
     ("forktest2\0", "\0", "\0", "\0", 0),
     ("forktree\0", "\0", "\0", "\0", 0),
+    ("cow_fork\0", "\0", "\0", "\0", 0),
     ("hello_world\0", "\0", "\0", "\0", 0),
     ("huge_write\0", "\0", "\0", "\0", 0),
     ("matrix\0", "\0", "\0", "\0", 0),
-    // ("mpsc_sem\0", "\0", "\0", "\0", 0),
+    ("mmap_test\0", "\0", "\0", "\0", 0),
+    ("mmap_file\0", "\0", "\0", "\0", 0),
+    ("swap_test\0", "\0", "\0", "\0", 0),
+    ("sig_simple\0", "\0", "\0", "\0", 0),
+    ("pgrp_test\0", "\0", "\0", "\0", 0),
+    ("mpsc_sem\0", "\0", "\0", "\0", 0),
     ("phil_din_mutex\0", "\0", "\0", "\0", 0),
+    ("phil_din_sem\0", "\0", "\0", "\0", 0),
+    ("deadlock_test\0", "\0", "\0", "\0", 0),
     ("pipe_large_test\0", "\0", "\0", "\0", 0),
     ("pipetest\0", "\0", "\0", "\0", 0),
     ("adder_peterson_spin\0", "\0", "\0", "\0", 0),
@@ -30,12 +45,14 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("adder_mutex_blocking\0", "\0", "\0", "\0", 0),
     ("adder_mutex_spin\0", "\0", "\0", "\0", 0),
     ("run_pipe_test\0", "\0", "\0", "\0", 0),
+    ("sched_priority\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sleep\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     // ("sync_sem\0", "\0", "\0", "\0", 0),
     // ("condsync_sem\0", "\0", "\0", "\0", 0),
     ("condsync_condvar\0", "\0", "\0", "\0", 0),
+    ("condvar_test\0", "\0", "\0", "\0", 0),
     ("threads_arg\0", "\0", "\0", "\0", 0),
     ("threads\0", "\0", "\0", "\0", 0),
     ("yield\0", "\0", "\0", "\0", 0),
@@ -44,16 +61,17 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
 ];
 
 static FAIL_TESTS: &[(&str, &str, &str, &str, i32)] = &[
-    ("stack_overflow\0", "\0", "\0", "\0", -2),
-    ("priv_csr\0", "\0", "\0", "\0", -3),
-    ("priv_inst\0", "\0", "\0", "\0", -3),
-    ("store_fault\0", "\0", "\0", "\0", -2),
-    ("adder\0", "\0", "\0", "\0", -4),
-    ("adder_simple_spin\0", "\0", "\0", "\0", -4),
-    ("adder_simple_yield\0", "\0", "\0", "\0", -4),
+    ("stack_overflow\0", "\0", "\0", "\0", -11),
+    ("priv_csr\0", "\0", "\0", "\0", -4),
+    ("priv_inst\0", "\0", "\0", "\0", -4),
+    ("store_fault\0", "\0", "\0", "\0", -11),
+    ("mprotect_fault\0", "\0", "\0", "\0", -11),
+    ("adder\0", "\0", "\0", "\0", -6),
+    ("adder_simple_spin\0", "\0", "\0", "\0", -6),
+    ("adder_simple_yield\0", "\0", "\0", "\0", -6),
 ];
 
-use user_lib::{exec, fork, waitpid};
+use user_lib::{chdir, exec, fork, waitpid};
 
 fn run_tests(tests: &[(&str, &str, &str, &str, i32)]) -> i32 {
     let mut pass_num = 0;
@@ -91,7 +109,7 @@ fn run_tests(tests: &[(&str, &str, &str, &str, i32)]) -> i32 {
 
         let pid = fork();
         if pid == 0 {
-            exec(0, test.0, &arr[..]);
+            exec(test.0, &arr[..]);
             panic!("unreachable!");
         } else {
             let mut exit_code: i32 = Default::default();
@@ -112,6 +130,8 @@ fn run_tests(tests: &[(&str, &str, &str, &str, i32)]) -> i32 {
 
 #[unsafe(no_mangle)]
 pub fn main() -> i32 {
+    // the tests live in the root and some expect to start there
+    chdir("/\0");
     let succ_num = run_tests(SUCC_TESTS);
     let err_num = run_tests(FAIL_TESTS);
     if succ_num == SUCC_TESTS.len() as i32 && err_num == FAIL_TESTS.len() as i32 {
@@ -18,15 +18,17 @@ static TESTS: &[&str] = &[
     "yield\0",
 ];
 
-use user_lib::{exec, fork, waitpid};
+use user_lib::{chdir, exec, fork, waitpid};
 
 #[unsafe(no_mangle)]
 pub fn main() -> i32 {
+    // the tests live in the root and some expect to start there
+    chdir("/\0");
     for test in TESTS {
         println!("Usertests: Running {}", test);
         let pid = fork();
         if pid == 0 {
-            exec(0, *test, &[core::ptr::null::<u8>()]);
+            exec(*test, &[core::ptr::null::<u8>()]);
             panic!("unreachable!");
         } else {
             let mut exit_code: i32 = Default::default();
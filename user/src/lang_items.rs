@@ -1,4 +1,4 @@
-use crate::kill;
+use crate::{SIGABRT, exit, getpid, kill};
 
 #[panic_handler]
 fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
@@ -13,6 +13,6 @@ fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
     } else {
         println!("Panicked: {}", err);
     }
-    kill();
-    unreachable!()
+    kill(getpid(), SIGABRT);
+    exit(-SIGABRT)
 }
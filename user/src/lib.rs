@@ -62,11 +62,39 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// Every write goes to the end of the file
+        const APPEND = 1 << 11;
     }
 }
 
-pub fn open(id: usize, path: &str, flags: OpenFlags) -> isize {
-    sys_open(id, path, flags.bits)
+bitflags! {
+    pub struct MmapProt: usize {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: usize {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+/// `dirfd` meaning the working directory
+pub const AT_FDCWD: isize = -100;
+
+/// Relative paths start from the working directory
+pub fn open(path: &str, flags: OpenFlags) -> isize {
+    sys_openat(AT_FDCWD, path, flags.bits)
+}
+
+/// Relative paths start from the directory open as `dirfd`
+pub fn openat(dirfd: isize, path: &str, flags: OpenFlags) -> isize {
+    sys_openat(dirfd, path, flags.bits)
 }
 pub fn close(fd: usize) -> isize {
     sys_close(fd)
@@ -78,6 +106,34 @@ pub fn write(fd: usize, buffer: &[u8]) -> isize {
     sys_write(fd, buffer)
 }
 
+/// `whence` of `lseek`
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// Move the offset of `fd`, returns the new offset
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+/// Read at `offset`, leaving the offset of `fd` where it was
+pub fn pread(fd: usize, buffer: &mut [u8], offset: usize) -> isize {
+    sys_pread64(fd, buffer, offset)
+}
+pub fn pwrite(fd: usize, buffer: &[u8], offset: usize) -> isize {
+    sys_pwrite64(fd, buffer, offset)
+}
+pub fn ftruncate(fd: usize, len: usize) -> isize {
+    sys_ftruncate(fd, len)
+}
+/// Write cached file system blocks back to the disk
+pub fn sync() -> isize {
+    sys_sync()
+}
+/// Same as `sync`, the kernel writes back every dirty block, not only `fd`'s
+pub fn fsync(fd: usize) -> isize {
+    sys_fsync(fd)
+}
+
 pub fn exit(exit_code: i32) -> ! {
     sys_exit(exit_code);
 }
@@ -86,6 +142,24 @@ pub fn yield_() -> isize {
     sys_yield()
 }
 
+pub fn set_priority(prio: isize) -> isize {
+    sys_set_priority(prio)
+}
+
+pub fn nice(increment: isize) -> isize {
+    sys_nice(increment)
+}
+
+pub const SCHED_STRIDE: usize = 0;
+pub const SCHED_MLFQ: usize = 1;
+pub const SCHED_FIFO: usize = 2;
+
+/// Pick the scheduling policy of the whole system, returns the previous one.
+/// Only initproc may, -1 for everyone else.
+pub fn sched_setscheduler(policy: usize) -> isize {
+    sys_sched_setscheduler(policy)
+}
+
 pub fn get_time() -> isize {
     sys_get_time()
 }
@@ -98,13 +172,13 @@ pub fn fork() -> isize {
     sys_fork()
 }
 
-pub fn exec(id: usize, path: &str, args: &[*const u8]) -> isize {
-    sys_exec(id, path, args)
+pub fn exec(path: &str, args: &[*const u8]) -> isize {
+    sys_exec(path, args)
 }
 
 pub fn wait(exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
+        match sys_waitpid(-1, exit_code as *mut _, 0) {
             -2 => {
                 yield_();
             }
@@ -116,7 +190,7 @@ pub fn wait(exit_code: &mut i32) -> isize {
 
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
+        match sys_waitpid(pid as isize, exit_code as *mut _, 0) {
             -2 => {
                 yield_();
             }
@@ -126,6 +200,23 @@ pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     }
 }
 
+/// Return -2 instead of waiting while the child runs
+pub const WNOHANG: usize = 1;
+/// Also return -3 for a child that stopped, with the stop signal in
+/// `exit_code`
+pub const WUNTRACED: usize = 2;
+
+pub fn waitpid_options(pid: usize, exit_code: &mut i32, options: usize) -> isize {
+    loop {
+        match sys_waitpid(pid as isize, exit_code as *mut _, options & WUNTRACED) {
+            -2 if options & WNOHANG == 0 => {
+                yield_();
+            }
+            ret => return ret,
+        }
+    }
+}
+
 pub fn sleep(period_ms: usize) {
     let start = get_time();
     while get_time() - start < period_ms as isize {
@@ -141,24 +232,151 @@ pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
 
-pub fn mkdir(id: usize, name: &str) -> isize {
-    sys_mkdir(id, name)
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdir(AT_FDCWD, path)
+}
+
+/// `d_type` of a directory entry
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+/// Fill `buf` with the next entries of the directory open as `fd`, returns the
+/// bytes filled, 0 once all were read. Walk them with `dirents`.
+pub fn getdents(fd: usize, buf: &mut [u8]) -> isize {
+    sys_getdents64(fd, buf)
+}
+
+/// One `linux_dirent64` record filled in by `getdents`
+pub struct Dirent<'a> {
+    pub ino: u64,
+    pub d_type: u8,
+    pub name: &'a str,
+}
+
+/// The records in the bytes `getdents` filled
+pub fn dirents(buf: &[u8]) -> impl Iterator<Item = Dirent<'_>> {
+    let mut pos = 0;
+    core::iter::from_fn(move || {
+        let record = buf.get(pos..)?;
+        if record.len() < 19 {
+            return None;
+        }
+        let reclen = u16::from_le_bytes([record[16], record[17]]) as usize;
+        if reclen < 19 {
+            return None;
+        }
+        let name = &record[19..reclen.min(record.len())];
+        let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        pos += reclen;
+        Some(Dirent {
+            ino: u64::from_le_bytes(record[..8].try_into().unwrap()),
+            d_type: record[18],
+            name: core::str::from_utf8(&name[..name_len]).unwrap_or("?"),
+        })
+    })
+}
+
+pub fn chdir(path: &str) -> isize {
+    sys_chdir(path)
+}
+
+/// The working directory as an absolute path with a NUL, returns its length
+/// or -1 if `buf` is too small
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    sys_getcwd(buf)
+}
+
+/// File type bits of `Stat::mode`, the rest are permissions
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+/// Act on a symbolic link itself rather than what it points to
+pub const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+/// `unlinkat` removes an empty directory instead of a file
+pub const AT_REMOVEDIR: u32 = 0x200;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stat {
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// 512-byte blocks allocated to the file
+    pub blocks: u64,
+    /// Seconds since the epoch
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+impl Stat {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+}
+
+pub fn fstat(fd: usize) -> Option<Stat> {
+    let mut st = Stat::default();
+    (sys_fstat(fd, &mut st) == 0).then_some(st)
+}
+
+pub fn stat(path: &str) -> Option<Stat> {
+    fstatat(AT_FDCWD, path, 0)
 }
 
-pub fn ls(id: usize) -> isize {
-    sys_ls(id)
+/// Like `stat`, but a symbolic link is described rather than followed
+pub fn lstat(path: &str) -> Option<Stat> {
+    fstatat(AT_FDCWD, path, AT_SYMLINK_NOFOLLOW)
 }
 
-pub fn cd(id: usize, path: &str) -> isize {
-    sys_cd(id, path)
+pub fn fstatat(dirfd: isize, path: &str, flags: u32) -> Option<Stat> {
+    let mut st = Stat::default();
+    (sys_stat(dirfd, path, &mut st, flags) == 0).then_some(st)
 }
 
-pub fn rm(id: usize, path: &str) -> isize {
-    sys_rm(id, path)
+/// A new hard link `new_path` to the file at `old_path`
+pub fn link(old_path: &str, new_path: &str) -> isize {
+    sys_linkat(AT_FDCWD, old_path, AT_FDCWD, new_path)
 }
 
-pub fn mv(id: usize, src: &str, dst: &str) -> isize {
-    sys_mv(id, src, dst)
+pub fn linkat(old_dirfd: isize, old_path: &str, new_dirfd: isize, new_path: &str) -> isize {
+    sys_linkat(old_dirfd, old_path, new_dirfd, new_path)
+}
+
+pub fn unlink(path: &str) -> isize {
+    sys_unlinkat(AT_FDCWD, path, 0)
+}
+
+pub fn unlinkat(dirfd: isize, path: &str, flags: u32) -> isize {
+    sys_unlinkat(dirfd, path, flags)
+}
+
+pub fn symlink(target: &str, path: &str) -> isize {
+    sys_symlinkat(target, AT_FDCWD, path)
+}
+
+/// The target is copied without a NUL, returns its length
+pub fn readlink(path: &str, buf: &mut [u8]) -> isize {
+    sys_readlinkat(AT_FDCWD, path, buf)
+}
+
+/// Remove a file or an empty directory
+pub fn rm(path: &str) -> isize {
+    sys_rm(AT_FDCWD, path)
+}
+
+pub fn mv(src: &str, dst: &str) -> isize {
+    sys_mv(AT_FDCWD, src, dst)
 }
 
 pub fn waittid(tid: usize) -> isize {
@@ -186,15 +404,189 @@ pub fn mutex_create() -> isize {
 pub fn mutex_blocking_create() -> isize {
     sys_mutex_create(true)
 }
-pub fn mutex_lock(mutex_id: usize) {
-    sys_mutex_lock(mutex_id);
+/// Returned by `mutex_lock`, `semaphore_down` and `condvar_wait` instead of
+/// waiting for good, once deadlock detection is on
+pub const DEADLOCK: isize = -0xdead;
+
+pub fn enable_deadlock_detect(enabled: bool) -> isize {
+    sys_enable_deadlock_detect(enabled as usize)
+}
+/// -1 if a signal cut the wait short
+pub fn mutex_lock(mutex_id: usize) -> isize {
+    sys_mutex_lock(mutex_id)
 }
 pub fn mutex_unlock(mutex_id: usize) {
     sys_mutex_unlock(mutex_id);
 }
+pub fn mutex_destroy(mutex_id: usize) -> isize {
+    sys_mutex_destroy(mutex_id)
+}
+pub fn semaphore_create(res_count: usize) -> isize {
+    sys_semaphore_create(res_count)
+}
+pub fn semaphore_up(sem_id: usize) {
+    sys_semaphore_up(sem_id);
+}
+/// -1 if a signal cut the wait short
+pub fn semaphore_down(sem_id: usize) -> isize {
+    sys_semaphore_down(sem_id)
+}
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGABRT: i32 = 6;
+pub const SIGBUS: i32 = 7;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGCHLD: i32 = 17;
+pub const SIGCONT: i32 = 18;
+pub const SIGSTOP: i32 = 19;
+pub const SIGTSTP: i32 = 20;
+pub const SIGTTIN: i32 = 21;
+pub const SIGTTOU: i32 = 22;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+bitflags! {
+    pub struct SignalFlags: u32 {
+        const SIGHUP = 1 << SIGHUP;
+        const SIGINT = 1 << SIGINT;
+        const SIGQUIT = 1 << SIGQUIT;
+        const SIGILL = 1 << SIGILL;
+        const SIGTRAP = 1 << SIGTRAP;
+        const SIGABRT = 1 << SIGABRT;
+        const SIGBUS = 1 << SIGBUS;
+        const SIGFPE = 1 << SIGFPE;
+        const SIGKILL = 1 << SIGKILL;
+        const SIGUSR1 = 1 << SIGUSR1;
+        const SIGSEGV = 1 << SIGSEGV;
+        const SIGUSR2 = 1 << SIGUSR2;
+        const SIGPIPE = 1 << SIGPIPE;
+        const SIGALRM = 1 << SIGALRM;
+        const SIGTERM = 1 << SIGTERM;
+        const SIGCHLD = 1 << SIGCHLD;
+        const SIGCONT = 1 << SIGCONT;
+        const SIGSTOP = 1 << SIGSTOP;
+        const SIGTSTP = 1 << SIGTSTP;
+        const SIGTTIN = 1 << SIGTTIN;
+        const SIGTTOU = 1 << SIGTTOU;
+    }
+}
+
+/// `handler` is `SIG_DFL`, `SIG_IGN` or a `fn(signum: i32)`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SignalAction {
+    pub handler: usize,
+    /// Filled in by `sigaction`
+    pub restorer: usize,
+    pub mask: SignalFlags,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            restorer: 0,
+            mask: SignalFlags::empty(),
+        }
+    }
+}
 
-pub fn kill() -> isize {
-    sys_kill()
+/// Handlers return here and leave through `sigreturn`
+extern "C" fn sigreturn_trampoline() -> ! {
+    sys_sigreturn();
+    unreachable!()
+}
+
+/// A negative `pid` signals the whole process group `-pid`
+pub fn kill(pid: isize, signum: i32) -> isize {
+    sys_kill(pid, signum)
+}
+pub fn sigaction(
+    signum: i32,
+    action: Option<&SignalAction>,
+    old_action: Option<&mut SignalAction>,
+) -> isize {
+    let action = action.map(|action| SignalAction {
+        restorer: sigreturn_trampoline as usize,
+        ..*action
+    });
+    sys_sigaction(
+        signum,
+        action
+            .as_ref()
+            .map_or(core::ptr::null(), |action| action as *const _),
+        old_action.map_or(core::ptr::null_mut(), |action| action as *mut _),
+    )
+}
+pub fn sigprocmask(
+    how: usize,
+    set: Option<SignalFlags>,
+    old_set: Option<&mut SignalFlags>,
+) -> isize {
+    let set = set.map(|set| set.bits());
+    let mut old = 0u32;
+    let ret = sys_sigprocmask(
+        how,
+        set.as_ref()
+            .map_or(core::ptr::null(), |set| set as *const _),
+        &mut old as *mut _,
+    );
+    if let Some(old_set) = old_set {
+        *old_set = SignalFlags::from_bits_truncate(old);
+    }
+    ret
+}
+
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+    sys_setpgid(pid, pgid)
+}
+pub fn getpgid(pid: usize) -> isize {
+    sys_getpgid(pid)
+}
+
+bitflags! {
+    /// Console modes, see `tcsetattr`
+    pub struct TtyFlags: u32 {
+        /// Ctrl-C, Ctrl-\ and Ctrl-Z signal the foreground process group
+        const ISIG = 0o1;
+        /// Input is edited and read a line at a time
+        const ICANON = 0o2;
+        const ECHO = 0o10;
+    }
+}
+
+pub fn tcgetattr(fd: usize) -> Option<TtyFlags> {
+    let flags = sys_tcgetattr(fd);
+    if flags < 0 {
+        None
+    } else {
+        Some(TtyFlags::from_bits_truncate(flags as u32))
+    }
+}
+pub fn tcsetattr(fd: usize, flags: TtyFlags) -> isize {
+    sys_tcsetattr(fd, flags.bits())
+}
+pub fn tcgetpgrp(fd: usize) -> isize {
+    sys_tcgetpgrp(fd)
+}
+pub fn tcsetpgrp(fd: usize, pgid: usize) -> isize {
+    sys_tcsetpgrp(fd, pgid)
 }
 
 pub fn condvar_create() -> isize {
@@ -203,8 +595,49 @@ pub fn condvar_create() -> isize {
 pub fn condvar_signal(condvar_id: usize) {
     sys_condvar_signal(condvar_id);
 }
-pub fn condvar_wait(condvar_id: usize, mutex_id: usize) {
-    sys_condvar_wait(condvar_id, mutex_id);
+/// A signal ends the wait early. -1 or `DEADLOCK` if taking the mutex again
+/// failed, which is not held then.
+pub fn condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    sys_condvar_wait(condvar_id, mutex_id)
+}
+pub fn condvar_broadcast(condvar_id: usize) {
+    sys_condvar_broadcast(condvar_id);
+}
+/// False if `timeout_ms` passed without a signal
+pub fn condvar_wait_timeout(condvar_id: usize, mutex_id: usize, timeout_ms: usize) -> bool {
+    sys_condvar_wait_timeout(condvar_id, mutex_id, timeout_ms) == 0
+}
+pub fn condvar_destroy(condvar_id: usize) -> isize {
+    sys_condvar_destroy(condvar_id)
+}
+
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+pub fn sbrk(increment: isize) -> isize {
+    sys_sbrk(increment)
+}
+pub fn mmap(addr: usize, len: usize, prot: MmapProt, flags: MmapFlags) -> isize {
+    sys_mmap(addr, len, prot.bits, flags.bits, usize::MAX, 0)
+}
+pub fn mmap_file(
+    addr: usize,
+    len: usize,
+    prot: MmapProt,
+    flags: MmapFlags,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    sys_mmap(addr, len, prot.bits, flags.bits, fd, offset)
+}
+pub fn msync(addr: usize, len: usize) -> isize {
+    sys_msync(addr, len, 0)
+}
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+pub fn mprotect(addr: usize, len: usize, prot: MmapProt) -> isize {
+    sys_mprotect(addr, len, prot.bits)
 }
 
 #[macro_export]